@@ -1,4 +1,4 @@
-#[cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 pub struct BitReader<'a> {
     to_read: &'a [u8],
@@ -18,7 +18,7 @@ impl<'a> BitReader<'a> {
         let read = self.to_read[off] & (1 << (self.pos % 8)) != 0;
         self.pos += 1;
 
-        return Some(read);
+        Some(read)
     }
 
     pub fn read_at(&mut self, bit_pos: usize) -> Option<bool> {
@@ -30,7 +30,7 @@ impl<'a> BitReader<'a> {
         let read = self.to_read[off] & (1 << (bit_pos % 8)) != 0;
         self.pos += 1;
 
-        return Some(read);
+        Some(read)
     }
 
     pub fn peek_bit(&self) -> Option<bool> {
@@ -40,7 +40,7 @@ impl<'a> BitReader<'a> {
         }
         let read = self.to_read[off] & (1 << (self.pos % 8)) != 0;
 
-        return Some(read);
+        Some(read)
     }
 }
 
@@ -109,7 +109,7 @@ impl<'a> BitWriter for BitVecWriter<'a> {
 
         let read = self.to_write[off] & (1 << (bit_pos % 8)) != 0;
 
-        return Some(read);
+        Some(read)
     }
 
     fn swap(&mut self, bit_pos_1: usize, bit_pos_2: usize) {
@@ -158,7 +158,7 @@ impl<'a> BitWriter for BitSliceWriter<'a> {
         debug_assert!(self.pos / 8 < self.bit_slice.len());
 
         let off = self.pos / 8;
-        let mask = !(1 << self.pos % 8);
+        let mask = !(1 << (self.pos % 8));
         self.bit_slice[off] = (self.bit_slice[off] & mask) | (bit << (self.pos % 8));
 
         self.pos += 1;
@@ -169,7 +169,7 @@ impl<'a> BitWriter for BitSliceWriter<'a> {
         debug_assert!(bit_pos / 8 < self.bit_slice.len());
 
         let off = bit_pos / 8;
-        let mask = !(1 << bit_pos % 8);
+        let mask = !(1 << (bit_pos % 8));
         self.bit_slice[off] = (self.bit_slice[off] & mask) | (bit << (bit_pos % 8));
 
         self.pos += 1;
@@ -193,7 +193,7 @@ impl<'a> BitWriter for BitSliceWriter<'a> {
 
         let read = self.bit_slice[off] & (1 << (bit_pos % 8)) != 0;
 
-        return Some(read);
+        Some(read)
     }
 
     fn swap(&mut self, bit_pos_1: usize, bit_pos_2: usize) {
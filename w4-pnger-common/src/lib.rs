@@ -2,6 +2,7 @@
 
 use core::convert::TryFrom;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum BitsPerPixel {
     One = 1,
@@ -23,6 +24,12 @@ impl BitsPerPixel {
         }
     }
 
+    //How many bytes a width x height sprite packs into. Pixels run on from one row to the next,
+    //so only the last byte can be partly filled
+    pub fn packed_len(&self, width: usize, height: usize) -> usize {
+        (width * height * self.get_num() as usize).div_ceil(8)
+    }
+
     pub fn try_from_flags(flags: u8) -> Result<Self, &'static str> {
         match flags {
             0 => Ok(BitsPerPixel::One),
@@ -32,10 +39,15 @@ impl BitsPerPixel {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum CompType {
     Uncompressed,
     Pk,
+    Lz,
+    Huffman,
+    //Lz, with matches reaching back into a dictionary shared by every sprite in a bundle
+    LzDict,
 }
 
 impl TryFrom<u8> for CompType {
@@ -45,7 +57,60 @@ impl TryFrom<u8> for CompType {
         match value {
             0 => Ok(CompType::Uncompressed),
             1 => Ok(CompType::Pk),
+            2 => Ok(CompType::Lz),
+            3 => Ok(CompType::Huffman),
+            4 => Ok(CompType::LzDict),
             _ => Err("Invalid compression type"),
         }
     }
 }
+
+//Every .ws sprite starts with its compression type, width, height and flags
+pub const WS_HEADER_SIZE: usize = 4;
+
+//Lz sprites have one header byte, with the number of bits in each match's offset in the low
+//nibble and the number of bits in its length in the high nibble. A bitstream follows, where
+//each 1 bit is followed by a literal byte, and each 0 bit by a match copying
+//length + LZ_MIN_MATCH bytes from offset + 1 bytes back. Values are written high bit first.
+//LzDict sprites are the same, but read as if the dictionary came just before the sprite
+pub const LZ_MIN_MATCH: usize = 2;
+
+//Huffman sprites have a params byte, with the symbol size in bits 0-1 (2, 4 or 8 bits per
+//symbol), whether the bitplanes are split in bit 2 and whether the bits are delta encoded in
+//bit 3. A canonical code table follows, high bit first: the longest code length in 4 bits, the
+//number of codes of each length from 1 up in symbol size + 1 bits, then every symbol in code
+//order. The codes themselves start on the next byte
+pub const HUFFMAN_MAX_CODE_LEN: usize = 15;
+
+//Bundles (.wsb) hold several sprites behind an offset table. All values are little endian:
+//  u8 flags, u16 sprite count, (count + 1) u16 offsets from the start of the bundle,
+//  count u32 name hashes if BUNDLE_NAME_HASHES is set, a u16 length and the dictionary
+//  if BUNDLE_DICTIONARY is set, then the sprites themselves. When BUNDLE_ANIMATION is set, the
+//  sprites are the frames of an animation, each after the first XORed with the frame before it
+pub const BUNDLE_NAME_HASHES: u8 = 1 << 0;
+pub const BUNDLE_DICTIONARY: u8 = 1 << 1;
+pub const BUNDLE_ANIMATION: u8 = 1 << 2;
+pub const BUNDLE_HEADER_SIZE: usize = 3;
+
+//Tilemaps (.wsm) hold a grid of cells, compressed a row at a time so any row can be decoded
+//without the others. All values are little endian:
+//  u8 flags, u16 width, u16 height, (height + 1) u16 offsets from the start of the tilemap,
+//  then each row as a .ws sprite whose bytes are the row's cells. Cells are u8, or u16 if
+//  TILEMAP_WIDE_CELLS is set. A cell is 0 where the map is empty, and otherwise one more than
+//  the index of its tile
+pub const TILEMAP_WIDE_CELLS: u8 = 1 << 0;
+pub const TILEMAP_HEADER_SIZE: usize = 5;
+
+//32-bit FNV-1a, used to look sprites up in a bundle by name
+pub const fn name_hash(name: &str) -> u32 {
+    let bytes = name.as_bytes();
+    let mut hash: u32 = 0x811c9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x01000193);
+        i += 1;
+    }
+
+    hash
+}
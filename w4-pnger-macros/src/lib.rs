@@ -0,0 +1,85 @@
+//! Compile-time sprite conversion for WASM-4.
+//!
+//! ```ignore
+//! use w4_pnger_macros::include_w4_sprite;
+//!
+//! static PLAYER: &[u8] = include_w4_sprite!("assets/player.png", compress = true);
+//! ```
+//!
+//! The path is relative to the crate's `Cargo.toml`, and the result is in the `.ws` layout
+//! `w4_tiny_decomp::Decompressor::decompress` reads. The png is tracked by the compiler, so
+//! the sprite is rebuilt whenever it changes.
+
+use std::path::PathBuf;
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_macro_input, Ident, LitBool, LitStr, Token};
+
+struct SpriteInput {
+    path: LitStr,
+    compress: bool,
+}
+
+impl Parse for SpriteInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let path: LitStr = input.parse()?;
+        let mut compress = false;
+
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+
+            let option: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+            match option.to_string().as_str() {
+                "compress" => compress = input.parse::<LitBool>()?.value,
+                _ => {
+                    return Err(syn::Error::new(
+                        option.span(),
+                        format!("Unknown option `{option}`, expected `compress`"),
+                    ))
+                }
+            }
+        }
+
+        Ok(SpriteInput { path, compress })
+    }
+}
+
+/// Converts a png into WASM-4 `.ws` data at compile time, expanding to a `&'static [u8]`.
+#[proc_macro]
+pub fn include_w4_sprite(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as SpriteInput);
+
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let path = PathBuf::from(manifest_dir).join(input.path.value());
+
+    let sprite = match w4_pnger::convert_png(&path, input.compress) {
+        Ok(sprite) => sprite,
+        Err(e) => {
+            return syn::Error::new(
+                input.path.span(),
+                format!("Failed to convert {}: {e}", path.display()),
+            )
+            .to_compile_error()
+            .into()
+        }
+    };
+
+    let path = path.to_string_lossy();
+    let bytes = sprite.bytes;
+
+    //Including the png makes the compiler rebuild this sprite when it changes
+    quote! {
+        {
+            const _: &[u8] = include_bytes!(#path);
+            const SPRITE: &[u8] = &[#(#bytes),*];
+            SPRITE
+        }
+    }
+    .into()
+}
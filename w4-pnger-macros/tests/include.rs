@@ -0,0 +1,22 @@
+use w4_pnger_macros::include_w4_sprite;
+use w4_tiny_decomp::Decompressor;
+
+static RAW: &[u8] = include_w4_sprite!("../test.png");
+static COMPRESSED: &[u8] = include_w4_sprite!("../test.png", compress = true,);
+
+#[test]
+fn test_include_w4_sprite() {
+    assert_eq!(
+        RAW,
+        w4_pnger::convert_png("../test.png", false).unwrap().bytes
+    );
+    assert_eq!(
+        COMPRESSED,
+        w4_pnger::convert_png("../test.png", true).unwrap().bytes
+    );
+
+    let mut buf = vec![0; 1024];
+    let mut decomp = Decompressor::new(&mut buf);
+    let decompressed = decomp.decompress(COMPRESSED).unwrap().bytes.to_vec();
+    assert_eq!(decompressed, &RAW[4..]);
+}
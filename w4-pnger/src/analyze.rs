@@ -1,57 +1,419 @@
-use png::Reader;
-use std::fs::File;
+use glob::{glob, GlobError};
+use serde::Serialize;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use w4_pnger_common::WS_HEADER_SIZE;
 
-use crate::compress::pkcomp::PkComp;
+use crate::budget::report_budget;
+use crate::compare::{Comparison, SpriteComparison};
+use crate::compress::pkcomp::{PkComp, PkParams};
 use crate::compress::Compressor;
-use crate::pngstream::PngStream;
+use crate::convert::load_matched;
+use crate::jobs::{run_ordered, Log};
 use crate::wasm4png::W4Sprite;
 
 pub struct Analyzer<'a> {
     path: &'a str,
+    jobs: usize,
+    format: ReportFormat,
+    budget: Option<usize>,
+    compare: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(from: &str) -> anyhow::Result<Self> {
+        match from {
+            "text" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => anyhow::bail!("Invalid report format {from}"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub sprites: Vec<SpriteReport>,
+    pub totals: Totals,
+}
+
+#[derive(Serialize)]
+pub struct SpriteReport {
+    pub name: String,
+    pub width: u16,
+    pub height: u16,
+    pub bpp: u32,
+    //Size of the pixel data in WASM-4's native format
+    pub raw_size: usize,
+    //Size of the compressed pixel data, including the compression header
+    pub compressed_size: usize,
+    pub ratio: f32,
+    pub method: String,
+    pub params: Option<PkParams>,
+}
+
+#[derive(Serialize)]
+pub struct Totals {
+    pub sprites: usize,
+    pub raw_size: usize,
+    pub compressed_size: usize,
+    pub ratio: f32,
+}
+
+impl Report {
+    pub fn new(sprites: Vec<SpriteReport>) -> Self {
+        let raw_size = sprites.iter().map(|s| s.raw_size).sum();
+        let compressed_size = sprites.iter().map(|s| s.compressed_size).sum();
+
+        Report {
+            totals: Totals {
+                sprites: sprites.len(),
+                raw_size,
+                compressed_size,
+                ratio: ratio(raw_size, compressed_size),
+            },
+            sprites,
+        }
+    }
+
+    pub fn write_json<W: Write>(&self, out: &mut W) -> anyhow::Result<()> {
+        serde_json::to_writer_pretty(&mut *out, self)?;
+        writeln!(out)?;
+        Ok(())
+    }
+
+    //One row per sprite, followed by a row with the totals
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> anyhow::Result<()> {
+        writeln!(
+            out,
+            "name,width,height,bpp,raw_size,compressed_size,ratio,method,split,xor,seq_delta,jump_delta,jump_size"
+        )?;
+
+        for sprite in &self.sprites {
+            let params = match &sprite.params {
+                Some(p) => format!(
+                    "{},{},{},{},{}",
+                    p.split, p.xor, p.seq_delta, p.jump_delta, p.jump_size
+                ),
+                None => ",,,,".to_owned(),
+            };
+
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{:.4},{},{params}",
+                csv_field(&sprite.name),
+                sprite.width,
+                sprite.height,
+                sprite.bpp,
+                sprite.raw_size,
+                sprite.compressed_size,
+                sprite.ratio,
+                csv_field(&sprite.method)
+            )?;
+        }
+
+        writeln!(
+            out,
+            "total,,,,{},{},{:.4},,,,,,",
+            self.totals.raw_size, self.totals.compressed_size, self.totals.ratio
+        )?;
+
+        Ok(())
+    }
+}
+
+//Compressed size as a fraction of the raw size, so smaller is better
+pub(crate) fn ratio(raw_size: usize, compressed_size: usize) -> f32 {
+    if raw_size == 0 {
+        0.0
+    } else {
+        compressed_size as f32 / raw_size as f32
+    }
+}
+
+pub(crate) fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
 }
 
 impl<'a> Analyzer<'a> {
     pub fn new(path: &'a str) -> Self {
-        Analyzer { path }
+        Analyzer {
+            path,
+            jobs: 1,
+            format: ReportFormat::Text,
+            budget: None,
+            compare: false,
+        }
+    }
+
+    //Analyzes up to this many sprites at once
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
     }
 
-    pub fn run(self) {
-        println!("Analyzing images...");
-        let stream = PngStream::new(self.path);
+    pub fn with_format(mut self, format: ReportFormat) -> Self {
+        self.format = format;
+        self
+    }
 
-        for png_res in stream {
-            match png_res {
-                Ok((name, png)) => process_png(name, png),
-                Err(e) => eprintln!("{}, continuing with other files", e),
+    //Checks the total size of the compressed sprites against this many bytes
+    pub fn with_budget(mut self, budget: Option<usize>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    //Compares every compressor on each sprite, rather than reporting the one convert uses
+    pub fn with_compare(mut self, compare: bool) -> Self {
+        self.compare = compare;
+        self
+    }
+
+    //Returns false if the sprites are over budget
+    pub fn run(self) -> bool {
+        let text = self.format == ReportFormat::Text;
+        if text {
+            println!("Analyzing images...");
+        }
+        let paths: Vec<_> = glob(self.path).expect("Must be a valid pattern").collect();
+
+        let mut stdout = std::io::stdout().lock();
+        let (sizes, result) = if self.compare {
+            let sprites = run_ordered(self.jobs, paths, |path_res| {
+                let mut log = Log::default();
+                let comparisons: Vec<_> = load_logged(path_res, &mut log)
+                    .into_iter()
+                    .filter_map(|(name, sprite)| compare_sprite(name, &sprite, text, &mut log))
+                    .collect();
+                (comparisons, log)
+            });
+
+            let comparison = Comparison::new(sprites.into_iter().flatten().collect());
+            let sizes: Vec<(String, usize)> = comparison
+                .sprites
+                .iter()
+                .map(|s| (s.name.clone(), s.winner_size()))
+                .collect();
+
+            let result = match self.format {
+                ReportFormat::Text => comparison.write_text(&mut stdout),
+                ReportFormat::Json => comparison.write_json(&mut stdout),
+                ReportFormat::Csv => comparison.write_csv(&mut stdout),
+            };
+            (sizes, result)
+        } else {
+            let sprites = run_ordered(self.jobs, paths, |path_res| {
+                let mut log = Log::default();
+                let reports: Vec<_> = load_logged(path_res, &mut log)
+                    .into_iter()
+                    .filter_map(|(name, sprite)| process_sprite(name, &sprite, text, &mut log))
+                    .collect();
+                (reports, log)
+            });
+
+            let report = Report::new(sprites.into_iter().flatten().collect());
+            let sizes: Vec<(String, usize)> = report
+                .sprites
+                .iter()
+                .map(|s| (s.name.clone(), s.compressed_size))
+                .collect();
+
+            let result = match self.format {
+                ReportFormat::Text => Ok(()),
+                ReportFormat::Json => report.write_json(&mut stdout),
+                ReportFormat::Csv => report.write_csv(&mut stdout),
+            };
+            (sizes, result)
+        };
+
+        if let Err(e) = result {
+            eprintln!("Failed to write report: {e}");
+        }
+
+        let Some(budget) = self.budget else {
+            return true;
+        };
+
+        //Each sprite ends up in the cart with its .ws header
+        let sizes: Vec<(String, usize)> = sizes
+            .into_iter()
+            .map(|(name, size)| (name, WS_HEADER_SIZE + size))
+            .collect();
+
+        //Keep stdout parseable when it holds a json or csv report
+        let fits = if text {
+            report_budget(&mut stdout, budget, &sizes)
+        } else {
+            report_budget(&mut std::io::stderr().lock(), budget, &sizes)
+        };
+
+        fits.unwrap_or_else(|e| {
+            eprintln!("Failed to report budget: {e}");
+            false
+        })
+    }
+}
+
+//Reads every sprite in a matched file, logging why if it can't
+fn load_logged(path_res: Result<PathBuf, GlobError>, log: &mut Log) -> Vec<(String, W4Sprite)> {
+    match load_matched(path_res) {
+        Ok(sprites) => sprites
+            .into_iter()
+            .map(|(name, sprite)| (name, sprite.pack()))
+            .collect(),
+        Err(e) => {
+            log.err(format!("{e}, continuing with other files"));
+            Vec::new()
+        }
+    }
+}
+
+fn compare_sprite(
+    image_name: String,
+    sprite: &W4Sprite,
+    text: bool,
+    log: &mut Log,
+) -> Option<SpriteComparison> {
+    match SpriteComparison::new(image_name.clone(), sprite) {
+        Ok(comparison) => {
+            if text {
+                let mut table = Vec::new();
+                match comparison.write_text(&mut table) {
+                    Ok(()) => log.out(String::from_utf8_lossy(&table).trim_end().to_owned()),
+                    Err(e) => log.err(format!("Failed to write comparison: {e}")),
+                }
             }
+            Some(comparison)
+        }
+        Err(e) => {
+            log.err(format!(
+                "Encountered error comparing sprite {image_name}: {e}"
+            ));
+            None
         }
     }
 }
 
-fn process_png(image_name: String, png_reader: Reader<File>) {
-    println!("Analyzing {image_name}...");
+fn process_sprite(
+    image_name: String,
+    png: &W4Sprite,
+    text: bool,
+    log: &mut Log,
+) -> Option<SpriteReport> {
+    if text {
+        log.out(format!("Analyzing {image_name}..."));
+    }
 
-    let mut png_reader = png_reader;
-    match W4Sprite::from_reader(&mut png_reader) {
-        Ok(png) => {
-            let png_bytes = png.get_bytes();
+    let png_bytes = png.get_bytes();
 
-            let compressor = PkComp {};
+    let compressor = PkComp {};
 
-            match compressor.compress(&png_bytes) {
-                Ok(compressed) => {
-                    let png_size = png_bytes.len();
-                    let compressed_size = compressed.total_size;
-                    let compression_method = compressed.readable_compression_name;
-                    let compression_statistics = compressed.readable_compression_statistics;
+    match compressor.compress(&png_bytes) {
+        Ok(compressed) => {
+            let png_size = png_bytes.len();
+            let compressed_size = compressed.total_size;
+            let params = PkParams::from_header(&compressed.header_bytes);
+            let compression_method = compressed.readable_compression_name;
+            let compression_statistics = compressed.readable_compression_statistics;
 
-                    println!("\nSprite {image_name} is {png_size}B in WASM-4 native format, and can be compressed to {compressed_size}B.\n\
-                    Compression method: {compression_method}\n\
-                    Statistics: {compression_statistics}");
-                }
-                Err(e) => eprintln!("Error encountered compressing sprite {image_name}: {e}"),
+            if text {
+                log.out(format!("\nSprite {image_name} is {png_size}B in WASM-4 native format, and can be compressed to {compressed_size}B.\n\
+                Compression method: {compression_method}\n\
+                Statistics: {compression_statistics}"));
             }
+
+            Some(SpriteReport {
+                name: image_name,
+                width: png.width,
+                height: png.height,
+                bpp: png.bpp.get_num(),
+                raw_size: png_size,
+                compressed_size,
+                ratio: ratio(png_size, compressed_size),
+                method: compression_method,
+                params,
+            })
+        }
+        Err(e) => {
+            log.err(format!(
+                "Error encountered compressing sprite {image_name}: {e}"
+            ));
+            None
         }
-        Err(e) => eprintln!("Encountered error processing sprite {image_name}: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Report, SpriteReport};
+    use crate::compress::pkcomp::PkParams;
+
+    #[test]
+    fn test_report() {
+        let report = Report::new(vec![
+            SpriteReport {
+                name: "a,b.png".to_owned(),
+                width: 8,
+                height: 8,
+                bpp: 1,
+                raw_size: 8,
+                compressed_size: 4,
+                ratio: 0.5,
+                method: "PnTree".to_owned(),
+                params: Some(PkParams {
+                    split: false,
+                    xor: false,
+                    seq_delta: 1,
+                    jump_delta: 0,
+                    jump_size: 4,
+                }),
+            },
+            SpriteReport {
+                name: "c.png".to_owned(),
+                width: 8,
+                height: 8,
+                bpp: 2,
+                raw_size: 16,
+                compressed_size: 14,
+                ratio: 0.875,
+                method: "PnTree".to_owned(),
+                params: None,
+            },
+        ]);
+
+        assert_eq!(report.totals.raw_size, 24);
+        assert_eq!(report.totals.compressed_size, 18);
+
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[1],
+            "\"a,b.png\",8,8,1,8,4,0.5000,PnTree,false,false,1,0,4"
+        );
+        assert_eq!(lines[2], "c.png,8,8,2,16,14,0.8750,PnTree,,,,,");
+        assert_eq!(lines[3], "total,,,,24,18,0.7500,,,,,,");
+
+        let mut json = Vec::new();
+        report.write_json(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["sprites"][0]["params"]["seq_delta"], 1);
+        assert_eq!(json["totals"]["sprites"], 2);
     }
 }
@@ -0,0 +1,853 @@
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use flate2::read::ZlibDecoder;
+use w4_pnger_common::BitsPerPixel;
+
+use crate::wasm4png::{palette_indices, sort_by_brightness, IndexedSprite};
+
+const HEADER_MAGIC: u16 = 0xa5e0;
+const FRAME_MAGIC: u16 = 0xf1fa;
+const HEADER_SIZE: usize = 128;
+const FRAME_HEADER_SIZE: usize = 16;
+
+const OLD_PALETTE_CHUNK: u16 = 0x0004;
+const LAYER_CHUNK: u16 = 0x2004;
+const CEL_CHUNK: u16 = 0x2005;
+const TAGS_CHUNK: u16 = 0x2018;
+const PALETTE_CHUNK: u16 = 0x2019;
+
+//Aseprite palettes hold at most this many colors
+const MAX_PALETTE_SIZE: usize = 256;
+//WASM-4 sprites store their size in a byte each
+const MAX_CANVAS_SIZE: u16 = u8::MAX as u16;
+
+const LAYER_VISIBLE: u16 = 1 << 0;
+const LAYER_BACKGROUND: u16 = 1 << 3;
+const PALETTE_ENTRY_NAMED: u16 = 1 << 0;
+
+//Whether a file has the .ase or .aseprite extension
+pub fn is_aseprite(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|e| e == "ase" || e == "aseprite")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorDepth {
+    Rgba,
+    Grayscale,
+    Indexed,
+}
+
+impl ColorDepth {
+    fn bytes(self) -> usize {
+        match self {
+            ColorDepth::Rgba => 4,
+            ColorDepth::Grayscale => 2,
+            ColorDepth::Indexed => 1,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerKind {
+    Image,
+    Group,
+    Tilemap,
+}
+
+#[derive(Debug)]
+pub struct Layer {
+    pub name: String,
+    pub kind: LayerKind,
+    pub visible: bool,
+    pub background: bool,
+    //How many groups the layer is nested in
+    pub child_level: u16,
+}
+
+//A named range of frames, which Aseprite plays as one animation
+#[derive(Debug)]
+pub struct Tag {
+    pub name: String,
+    pub from: usize,
+    pub to: usize,
+}
+
+#[derive(Debug)]
+pub struct Frame {
+    //How long the frame shows for, in milliseconds
+    pub duration: u16,
+    cels: Vec<Cel>,
+}
+
+#[derive(Debug)]
+struct Cel {
+    layer: usize,
+    x: i16,
+    y: i16,
+    image: CelImage,
+}
+
+#[derive(Debug)]
+enum CelImage {
+    Pixels {
+        width: u16,
+        height: u16,
+        pixels: Vec<u8>,
+    },
+    //Shares the cel on the same layer in another frame
+    Linked(usize),
+    //Tilemap cels, which aren't drawn
+    Tiles,
+}
+
+//An .aseprite file, read natively instead of exporting it to pngs first
+pub struct AsepriteFile {
+    pub width: u16,
+    pub height: u16,
+    pub depth: ColorDepth,
+    //The palette index drawn as transparent in indexed files
+    pub transparent_index: u8,
+    pub layers: Vec<Layer>,
+    pub tags: Vec<Tag>,
+    pub frames: Vec<Frame>,
+    pub palette: Vec<[u8; 4]>,
+}
+
+impl AsepriteFile {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut header = Cursor::new(
+            bytes
+                .get(..HEADER_SIZE)
+                .ok_or_else(|| anyhow!(ENDS_EARLY))?,
+        );
+        header.skip(4)?;
+        if header.u16()? != HEADER_MAGIC {
+            bail!("Not an Aseprite file");
+        }
+        let frame_count = header.u16()?;
+        let width = header.u16()?;
+        let height = header.u16()?;
+        if width > MAX_CANVAS_SIZE || height > MAX_CANVAS_SIZE {
+            bail!(
+                "The canvas is {width}x{height}, but sprites can be at most \
+                {MAX_CANVAS_SIZE}x{MAX_CANVAS_SIZE}"
+            );
+        }
+        let depth = match header.u16()? {
+            32 => ColorDepth::Rgba,
+            16 => ColorDepth::Grayscale,
+            8 => ColorDepth::Indexed,
+            other => bail!("Unknown color depth {other}"),
+        };
+        header.skip(14)?;
+        let transparent_index = header.u8()?;
+
+        let mut file = AsepriteFile {
+            width,
+            height,
+            depth,
+            transparent_index,
+            layers: Vec::new(),
+            tags: Vec::new(),
+            frames: Vec::new(),
+            palette: Vec::new(),
+        };
+
+        let mut old_palette = Vec::new();
+        let mut pos = HEADER_SIZE;
+        for _ in 0..frame_count {
+            let mut frame = Cursor::new(bytes.get(pos..).ok_or_else(|| anyhow!(ENDS_EARLY))?);
+            let frame_len = frame.u32()? as usize;
+            if frame.u16()? != FRAME_MAGIC {
+                bail!("Frame {} has a broken header", file.frames.len());
+            }
+            let old_chunks = frame.u16()?;
+            let duration = frame.u16()?;
+            frame.skip(2)?;
+            let chunk_count = match frame.u32()? {
+                0 => old_chunks as u32,
+                new_chunks => new_chunks,
+            };
+
+            let mut cels = Vec::new();
+            let mut chunk_pos = pos + FRAME_HEADER_SIZE;
+            for _ in 0..chunk_count {
+                let mut chunk =
+                    Cursor::new(bytes.get(chunk_pos..).ok_or_else(|| anyhow!(ENDS_EARLY))?);
+                let chunk_len = chunk.u32()? as usize;
+                let kind = chunk.u16()?;
+                let data = bytes
+                    .get(chunk_pos + 6..chunk_pos + chunk_len.max(6))
+                    .ok_or_else(|| anyhow!(ENDS_EARLY))?;
+
+                match kind {
+                    LAYER_CHUNK => file.layers.push(read_layer(data)?),
+                    CEL_CHUNK => cels.push(read_cel(data, &file)?),
+                    TAGS_CHUNK => file.tags.extend(read_tags(data)?),
+                    PALETTE_CHUNK => read_palette(data, &mut file.palette)?,
+                    OLD_PALETTE_CHUNK => read_old_palette(data, &mut old_palette)?,
+                    _ => {}
+                }
+
+                chunk_pos += chunk_len.max(6);
+            }
+
+            file.frames.push(Frame { duration, cels });
+            pos += frame_len.max(FRAME_HEADER_SIZE);
+        }
+
+        //Files from before Aseprite 1.2 only have the old palette chunk
+        if file.palette.is_empty() {
+            file.palette = old_palette;
+        }
+
+        Ok(file)
+    }
+
+    //Whether a layer and every group it's in are visible
+    pub fn is_visible(&self, layer: usize) -> bool {
+        let mut level = match self.layers.get(layer) {
+            Some(l) if l.visible => l.child_level,
+            _ => return false,
+        };
+
+        //A layer's group is the closest layer before it which is one level further out
+        for parent in self.layers[..layer].iter().rev() {
+            if level == 0 {
+                break;
+            }
+            if parent.child_level == level - 1 {
+                if !parent.visible {
+                    return false;
+                }
+                level = parent.child_level;
+            }
+        }
+
+        true
+    }
+
+    //Draws the visible layers of a frame from the bottom up, in the file's color depth.
+    //Opacity and blend modes are ignored, as WASM-4 sprites have no partial transparency
+    pub fn render(&self, frame: usize) -> Result<Vec<u8>> {
+        let bytes = self.depth.bytes();
+        let (width, height) = (self.width as usize, self.height as usize);
+        let len = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(bytes))
+            .ok_or_else(|| anyhow!("A {width}x{height} canvas is too big"))?;
+        let mut canvas = match self.depth {
+            ColorDepth::Indexed => vec![self.transparent_index; len],
+            _ => vec![0; len],
+        };
+
+        let mut cels: Vec<&Cel> = self
+            .frames
+            .get(frame)
+            .ok_or_else(|| anyhow!("There is no frame {frame}"))?
+            .cels
+            .iter()
+            .filter(|cel| self.is_visible(cel.layer))
+            .collect();
+        cels.sort_by_key(|cel| cel.layer);
+
+        for cel in cels {
+            let cel = self.resolve_link(cel)?;
+            let (cel_width, cel_height, pixels) = match &cel.image {
+                CelImage::Pixels {
+                    width,
+                    height,
+                    pixels,
+                } => (*width as usize, *height as usize, pixels),
+                _ => continue,
+            };
+            let background = self.layers.get(cel.layer).is_some_and(|l| l.background);
+
+            for cy in 0..cel_height {
+                for cx in 0..cel_width {
+                    //Cels can hang off any edge of the canvas
+                    let x = usize::try_from(cel.x as isize + cx as isize);
+                    let y = usize::try_from(cel.y as isize + cy as isize);
+                    let (Ok(x), Ok(y)) = (x, y) else {
+                        continue;
+                    };
+                    if x >= width || y >= height {
+                        continue;
+                    }
+
+                    let src = (cy * cel_width + cx) * bytes;
+                    let pixel = &pixels[src..src + bytes];
+                    if !background && self.is_transparent(pixel) {
+                        continue;
+                    }
+
+                    let dest = (y * width + x) * bytes;
+                    canvas[dest..dest + bytes].copy_from_slice(pixel);
+                }
+            }
+        }
+
+        Ok(canvas)
+    }
+
+    //Turns every frame into a sprite. Frames in a tag are named after it, as
+    //player_walk_0.ase, player_walk_1.ase, ... counting from the tag's first frame, and
+    //any other frames after their position, as player_3.ase. A file with one frame and no
+    //tags keeps its own name.
+    //
+    //Indexed files use their palette indices as draw colors unless a palette is given.
+    //Otherwise colors are sorted by brightness across the whole file, so every frame
+    //shares the same draw colors and bits per pixel
+    pub fn sprites(
+        &self,
+        file_name: &str,
+        palette: Option<&[[u8; 4]]>,
+    ) -> Result<Vec<(String, IndexedSprite)>> {
+        let images = (0..self.frames.len())
+            .map(|frame| self.render(frame))
+            .collect::<Result<Vec<_>>>()?;
+
+        let indices = match (palette, self.depth) {
+            (None, ColorDepth::Indexed) => {
+                for (frame, image) in images.iter().enumerate() {
+                    if let Some(i) = image.iter().position(|&index| index >= 4) {
+                        bail!(
+                            "Frame {frame} uses color {} at {}, {}, past the 4 WASM-4 draw colors",
+                            image[i],
+                            i % self.width as usize,
+                            i / self.width as usize
+                        );
+                    }
+                }
+                images
+            }
+            (palette, _) => {
+                let images = images
+                    .iter()
+                    .map(|image| self.to_rgba(image))
+                    .collect::<Result<Vec<_>>>()?;
+
+                let palette = match palette {
+                    Some(palette) => palette.to_vec(),
+                    None => {
+                        let mut colors: Vec<[u8; 4]> = Vec::new();
+                        for color in images.iter().flatten() {
+                            if !colors.contains(color) {
+                                colors.push(*color);
+                            }
+                        }
+                        if colors.len() > 4 {
+                            bail!("Uses {} colors, but sprites can have 4", colors.len());
+                        }
+                        sort_by_brightness(&mut colors);
+                        colors
+                    }
+                };
+
+                images
+                    .iter()
+                    .map(|image| palette_indices(self.width.into(), image, &palette))
+                    .collect::<Result<Vec<_>>>()?
+            }
+        };
+
+        let bpp = if indices.iter().flatten().all(|&i| i < 2) {
+            BitsPerPixel::One
+        } else {
+            BitsPerPixel::Two
+        };
+
+        self.frame_names(file_name)
+            .into_iter()
+            .map(|(name, frame)| {
+                let sprite = IndexedSprite::new(
+                    self.width.into(),
+                    self.height.into(),
+                    bpp,
+                    indices[frame].clone(),
+                )?;
+                Ok((name, sprite))
+            })
+            .collect()
+    }
+
+    //The name of each sprite and the frame it comes from
+    fn frame_names(&self, file_name: &str) -> Vec<(String, usize)> {
+        if self.frames.len() == 1 && self.tags.is_empty() {
+            return vec![(file_name.to_owned(), 0)];
+        }
+
+        let path = Path::new(file_name);
+        let stem = path
+            .file_stem()
+            .map_or(file_name.into(), |s| s.to_string_lossy());
+        let extension = path
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+
+        let mut names = Vec::new();
+        for tag in &self.tags {
+            for frame in tag.from..=tag.to.min(self.frames.len().saturating_sub(1)) {
+                let n = frame - tag.from;
+                names.push((format!("{stem}_{}_{n}{extension}", tag.name), frame));
+            }
+        }
+        for frame in 0..self.frames.len() {
+            if !self.tags.iter().any(|t| (t.from..=t.to).contains(&frame)) {
+                names.push((format!("{stem}_{frame}{extension}"), frame));
+            }
+        }
+
+        names
+    }
+
+    fn resolve_link<'a>(&'a self, cel: &'a Cel) -> Result<&'a Cel> {
+        match cel.image {
+            CelImage::Linked(frame) => self
+                .frames
+                .get(frame)
+                .and_then(|f| {
+                    f.cels.iter().find(|linked| {
+                        linked.layer == cel.layer && !matches!(linked.image, CelImage::Linked(_))
+                    })
+                })
+                .ok_or_else(|| anyhow!("A cel links to frame {frame}, which has no cel to share")),
+            _ => Ok(cel),
+        }
+    }
+
+    fn is_transparent(&self, pixel: &[u8]) -> bool {
+        match self.depth {
+            ColorDepth::Rgba => pixel[3] == 0,
+            ColorDepth::Grayscale => pixel[1] == 0,
+            ColorDepth::Indexed => pixel[0] == self.transparent_index,
+        }
+    }
+
+    fn to_rgba(&self, image: &[u8]) -> Result<Vec<[u8; 4]>> {
+        match self.depth {
+            ColorDepth::Rgba => Ok(image.chunks(4).map(|c| [c[0], c[1], c[2], c[3]]).collect()),
+            ColorDepth::Grayscale => {
+                Ok(image.chunks(2).map(|c| [c[0], c[0], c[0], c[1]]).collect())
+            }
+            ColorDepth::Indexed => image
+                .iter()
+                .map(|&i| {
+                    self.palette
+                        .get(i as usize)
+                        .copied()
+                        .ok_or_else(|| anyhow!("Color {i} is not in the file's palette"))
+                })
+                .collect(),
+        }
+    }
+}
+
+const ENDS_EARLY: &str = "Aseprite file ends early";
+
+//Reads the little endian values Aseprite files are made of
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Cursor { bytes, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| anyhow!(ENDS_EARLY))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.pos.min(self.bytes.len())..];
+        self.pos = self.bytes.len();
+        rest
+    }
+}
+
+fn read_layer(data: &[u8]) -> Result<Layer> {
+    let mut chunk = Cursor::new(data);
+    let flags = chunk.u16()?;
+    let kind = match chunk.u16()? {
+        0 => LayerKind::Image,
+        1 => LayerKind::Group,
+        _ => LayerKind::Tilemap,
+    };
+    let child_level = chunk.u16()?;
+    chunk.skip(10)?;
+
+    Ok(Layer {
+        name: chunk.string()?,
+        kind,
+        visible: flags & LAYER_VISIBLE != 0,
+        background: flags & LAYER_BACKGROUND != 0,
+        child_level,
+    })
+}
+
+fn read_cel(data: &[u8], file: &AsepriteFile) -> Result<Cel> {
+    let mut chunk = Cursor::new(data);
+    let layer = chunk.u16()? as usize;
+    let x = chunk.i16()?;
+    let y = chunk.i16()?;
+    chunk.skip(1)?;
+    let kind = chunk.u16()?;
+    chunk.skip(7)?;
+
+    let image = match kind {
+        0 | 2 => {
+            let width = chunk.u16()?;
+            let height = chunk.u16()?;
+            //The sizes come from the file, so a corrupt one could ask for gigabytes
+            if width > file.width || height > file.height {
+                bail!(
+                    "A {width}x{height} cel on layer {layer} is bigger than the {}x{} canvas",
+                    file.width,
+                    file.height
+                );
+            }
+            let len = width as usize * height as usize * file.depth.bytes();
+
+            let pixels = if kind == 0 {
+                chunk.take(len)?.to_vec()
+            } else {
+                //Grows with the pixels actually decompressed, up to the cel's size
+                let mut pixels = Vec::new();
+                ZlibDecoder::new(chunk.rest())
+                    .take(len as u64)
+                    .read_to_end(&mut pixels)?;
+                pixels
+            };
+            if pixels.len() < len {
+                bail!("A {width}x{height} cel on layer {layer} is missing pixels");
+            }
+
+            CelImage::Pixels {
+                width,
+                height,
+                pixels,
+            }
+        }
+        1 => CelImage::Linked(chunk.u16()? as usize),
+        _ => CelImage::Tiles,
+    };
+
+    Ok(Cel { layer, x, y, image })
+}
+
+fn read_tags(data: &[u8]) -> Result<Vec<Tag>> {
+    let mut chunk = Cursor::new(data);
+    let count = chunk.u16()?;
+    chunk.skip(8)?;
+
+    (0..count)
+        .map(|_| {
+            let from = chunk.u16()? as usize;
+            let to = chunk.u16()? as usize;
+            chunk.skip(13)?;
+            Ok(Tag {
+                name: chunk.string()?,
+                from,
+                to,
+            })
+        })
+        .collect()
+}
+
+fn read_palette(data: &[u8], palette: &mut Vec<[u8; 4]>) -> Result<()> {
+    let mut chunk = Cursor::new(data);
+    let size = chunk.u32()? as usize;
+    let first = chunk.u32()? as usize;
+    let last = chunk.u32()? as usize;
+    chunk.skip(8)?;
+    if size > MAX_PALETTE_SIZE || last >= MAX_PALETTE_SIZE {
+        bail!("Palette has more than {MAX_PALETTE_SIZE} colors");
+    }
+
+    palette.resize(size.max(palette.len()), [0, 0, 0, 0xff]);
+    for index in first..=last {
+        let flags = chunk.u16()?;
+        let color = chunk.take(4)?;
+        if let Some(entry) = palette.get_mut(index) {
+            entry.copy_from_slice(color);
+        }
+        if flags & PALETTE_ENTRY_NAMED != 0 {
+            chunk.string()?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_old_palette(data: &[u8], palette: &mut Vec<[u8; 4]>) -> Result<()> {
+    let mut chunk = Cursor::new(data);
+    let packets = chunk.u16()?;
+
+    let mut index = 0;
+    for _ in 0..packets {
+        index += chunk.u8()? as usize;
+        let count = match chunk.u8()? {
+            0 => 256,
+            count => count as usize,
+        };
+
+        for _ in 0..count {
+            if index >= MAX_PALETTE_SIZE {
+                bail!("Palette has more than {MAX_PALETTE_SIZE} colors");
+            }
+            let rgb = chunk.take(3)?;
+            if palette.len() <= index {
+                palette.resize(index + 1, [0, 0, 0, 0xff]);
+            }
+            palette[index] = [rgb[0], rgb[1], rgb[2], 0xff];
+            index += 1;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::{
+        AsepriteFile, ColorDepth, CEL_CHUNK, LAYER_CHUNK, OLD_PALETTE_CHUNK, PALETTE_CHUNK,
+        TAGS_CHUNK,
+    };
+
+    fn string(out: &mut Vec<u8>, s: &str) {
+        out.extend((s.len() as u16).to_le_bytes());
+        out.extend(s.as_bytes());
+    }
+
+    fn chunk(kind: u16, data: Vec<u8>) -> Vec<u8> {
+        let mut out = ((data.len() + 6) as u32).to_le_bytes().to_vec();
+        out.extend(kind.to_le_bytes());
+        out.extend(data);
+        out
+    }
+
+    fn layer(name: &str, flags: u16) -> Vec<u8> {
+        let mut data = flags.to_le_bytes().to_vec();
+        data.extend([0; 14]);
+        string(&mut data, name);
+        chunk(LAYER_CHUNK, data)
+    }
+
+    fn cel(layer: u16, x: i16, kind: u16, body: Vec<u8>) -> Vec<u8> {
+        let mut data = layer.to_le_bytes().to_vec();
+        data.extend(x.to_le_bytes());
+        data.extend(0i16.to_le_bytes());
+        data.push(0xff);
+        data.extend(kind.to_le_bytes());
+        data.extend([0; 7]);
+        data.extend(body);
+        chunk(CEL_CHUNK, data)
+    }
+
+    fn frame(chunks: Vec<Vec<u8>>) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut out = ((body.len() + 16) as u32).to_le_bytes().to_vec();
+        out.extend(0xf1fau16.to_le_bytes());
+        out.extend((chunks.len() as u16).to_le_bytes());
+        out.extend(100u16.to_le_bytes());
+        out.extend([0; 6]);
+        out.extend(body);
+        out
+    }
+
+    //An indexed 8x2 file with a hidden layer over the one being drawn, and three frames
+    //where the last two are tagged "walk"
+    fn test_file() -> Vec<u8> {
+        let mut palette = 4u32.to_le_bytes().to_vec();
+        palette.extend(0u32.to_le_bytes());
+        palette.extend(3u32.to_le_bytes());
+        palette.extend([0; 8]);
+        for color in [
+            [0, 0, 0, 0],
+            [0xff; 4],
+            [0x80, 0x80, 0x80, 0xff],
+            [0, 0, 0, 0xff],
+        ] {
+            palette.extend([0, 0]);
+            palette.extend(color);
+        }
+
+        let mut tags = 1u16.to_le_bytes().to_vec();
+        tags.extend([0; 8]);
+        tags.extend(1u16.to_le_bytes());
+        tags.extend(2u16.to_le_bytes());
+        tags.extend([0; 13]);
+        string(&mut tags, "walk");
+
+        let mut raw = 2u16.to_le_bytes().to_vec();
+        raw.extend(1u16.to_le_bytes());
+        raw.extend([1, 2]);
+
+        let mut compressed = 8u16.to_le_bytes().to_vec();
+        compressed.extend(2u16.to_le_bytes());
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(&[3, 3, 0, 0, 0, 0, 2, 2, 1, 3, 3, 0, 0, 0, 0, 2])
+            .unwrap();
+        compressed.extend(encoder.finish().unwrap());
+
+        let frames = [
+            frame(vec![
+                chunk(PALETTE_CHUNK, palette),
+                layer("body", 1),
+                layer("hidden", 0),
+                chunk(TAGS_CHUNK, tags),
+                cel(0, 3, 0, raw),
+                cel(1, 0, 0, [8, 0, 2, 0].into_iter().chain([3; 16]).collect()),
+            ]),
+            frame(vec![cel(0, 0, 2, compressed)]),
+            frame(vec![cel(0, 0, 1, 1u16.to_le_bytes().to_vec())]),
+        ];
+
+        file(&frames)
+    }
+
+    //An indexed 8x2 file made of these frames
+    fn file(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut header = vec![0; 128];
+        header[4..6].copy_from_slice(&0xa5e0u16.to_le_bytes());
+        header[6..8].copy_from_slice(&(frames.len() as u16).to_le_bytes());
+        header[8..10].copy_from_slice(&8u16.to_le_bytes());
+        header[10..12].copy_from_slice(&2u16.to_le_bytes());
+        header[12..14].copy_from_slice(&8u16.to_le_bytes());
+
+        [header, frames.concat()].concat()
+    }
+
+    #[test]
+    fn test_read() {
+        let file = AsepriteFile::from_bytes(&test_file()).unwrap();
+        assert_eq!(
+            (file.width, file.height, file.depth),
+            (8, 2, ColorDepth::Indexed)
+        );
+        assert_eq!(file.frames.len(), 3);
+        assert_eq!(file.palette.len(), 4);
+        assert!(file.is_visible(0));
+        assert!(!file.is_visible(1));
+        assert_eq!(
+            (
+                file.tags[0].name.as_str(),
+                file.tags[0].from,
+                file.tags[0].to
+            ),
+            ("walk", 1, 2)
+        );
+
+        //The hidden layer's solid cel isn't drawn
+        let first = file.render(0).unwrap();
+        assert_eq!(first, [0, 0, 0, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let second = file.render(1).unwrap();
+        assert_eq!(second, [3, 3, 0, 0, 0, 0, 2, 2, 1, 3, 3, 0, 0, 0, 0, 2]);
+        assert_eq!(file.render(2).unwrap(), second);
+
+        let sprites = file.sprites("player.aseprite", None).unwrap();
+        let names: Vec<&str> = sprites.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "player_walk_0.aseprite",
+                "player_walk_1.aseprite",
+                "player_0.aseprite"
+            ]
+        );
+        assert_eq!(sprites[0].1.indices, second);
+        assert_eq!(sprites[2].1.indices, first);
+
+        //Mapped through a palette, the file's transparent color has to be in it
+        let palette = [
+            [0xff; 4],
+            [0, 0, 0, 0],
+            [0x80, 0x80, 0x80, 0xff],
+            [0, 0, 0, 0xff],
+        ];
+        let sprites = file.sprites("player.aseprite", Some(&palette)).unwrap();
+        assert_eq!(sprites[2].1.indices[..5], [1, 1, 1, 0, 2]);
+        assert!(file
+            .sprites("player.aseprite", Some(&palette[..3]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        //A header claiming a 65535x65535 canvas, too big to render or fit in a sprite
+        let mut wide = file(&[]);
+        wide[8..12].copy_from_slice(&[0xff; 4]);
+        assert!(AsepriteFile::from_bytes(&wide).is_err());
+        wide[8..12].copy_from_slice(&[0xff, 0, 0xff, 0]);
+        assert!(AsepriteFile::from_bytes(&wide).is_ok());
+
+        //A compressed cel claiming to be far bigger than the canvas
+        let mut huge = u16::MAX.to_le_bytes().to_vec();
+        huge.extend(u16::MAX.to_le_bytes());
+        let huge = file(&[frame(vec![layer("body", 1), cel(0, 0, 2, huge)])]);
+        assert!(AsepriteFile::from_bytes(&huge).is_err());
+
+        let mut palette = 1000u32.to_le_bytes().to_vec();
+        palette.extend(0u32.to_le_bytes());
+        palette.extend(0u32.to_le_bytes());
+        palette.extend([0; 8]);
+        palette.extend([0, 0, 0, 0, 0, 0xff]);
+        let palette = file(&[frame(vec![chunk(PALETTE_CHUNK, palette)])]);
+        assert!(AsepriteFile::from_bytes(&palette).is_err());
+
+        //Two packets of 256 colors, which run past the end of the palette
+        let mut old_palette = 2u16.to_le_bytes().to_vec();
+        for _ in 0..2 {
+            old_palette.extend([0, 0]);
+            old_palette.extend([0; 256 * 3]);
+        }
+        let old_palette = file(&[frame(vec![chunk(OLD_PALETTE_CHUNK, old_palette)])]);
+        assert!(AsepriteFile::from_bytes(&old_palette).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::Write;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+//How many of the biggest assets to list
+const LISTED_ASSETS: usize = 10;
+
+//Parses a size in bytes, such as "4096", "48k" or "64KiB"
+pub fn parse_size(size: &str) -> Result<usize> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+
+    let number: usize = match number.parse() {
+        Ok(number) => number,
+        Err(_) => bail!("{size} is not a size, expected a number of bytes like 4096 or 48KiB"),
+    };
+    let multiplier: usize = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        _ => bail!("{size} is not a size, expected a number of bytes like 4096 or 48KiB"),
+    };
+
+    match number.checked_mul(multiplier) {
+        Some(bytes) => Ok(bytes),
+        None => bail!("{size} is too big to be a size"),
+    }
+}
+
+//A budget in a manifest, either `budget = 49152` or `budget = "48KiB"`
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum BudgetSize {
+    Bytes(usize),
+    Text(String),
+}
+
+impl BudgetSize {
+    pub fn bytes(&self) -> Result<usize> {
+        match self {
+            BudgetSize::Bytes(bytes) => Ok(*bytes),
+            BudgetSize::Text(text) => parse_size(text),
+        }
+    }
+}
+
+//Writes the total size of every asset against the budget, along with the biggest assets.
+//Returns whether the assets fit
+pub fn report_budget<W: Write>(
+    out: &mut W,
+    budget: usize,
+    assets: &[(String, usize)],
+) -> Result<bool> {
+    let total: usize = assets.iter().map(|(_, size)| size).sum();
+
+    let mut ranked: Vec<&(String, usize)> = assets.iter().collect();
+    ranked.sort_by(|(name1, size1), (name2, size2)| size2.cmp(size1).then(name1.cmp(name2)));
+
+    writeln!(
+        out,
+        "{} assets take up {total} of {budget} bytes ({:.1} %)",
+        assets.len(),
+        percent(total, budget)
+    )?;
+    for (i, (name, size)) in ranked.iter().take(LISTED_ASSETS).enumerate() {
+        writeln!(
+            out,
+            "{:>4}. {name}: {size} bytes ({:.1} %)",
+            i + 1,
+            percent(*size, budget)
+        )?;
+    }
+    if ranked.len() > LISTED_ASSETS {
+        writeln!(out, "      ...and {} more", ranked.len() - LISTED_ASSETS)?;
+    }
+
+    if total > budget {
+        writeln!(out, "Over budget by {} bytes", total - budget)?;
+    }
+
+    Ok(total <= budget)
+}
+
+fn percent(size: usize, budget: usize) -> f32 {
+    if budget == 0 {
+        100.0
+    } else {
+        size as f32 / budget as f32 * 100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_size, report_budget};
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size("48k").unwrap(), 48 * 1024);
+        assert_eq!(parse_size("64 KiB").unwrap(), 64 * 1024);
+        assert!(parse_size("64MB").is_err());
+        assert!(parse_size("KiB").is_err());
+        assert!(parse_size(&format!("{}K", usize::MAX / 1024 + 1)).is_err());
+    }
+
+    #[test]
+    fn test_report_budget() {
+        let assets = vec![
+            ("small".to_owned(), 10),
+            ("big".to_owned(), 100),
+            ("medium".to_owned(), 50),
+        ];
+
+        let mut out = Vec::new();
+        assert!(report_budget(&mut out, 160, &assets).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "3 assets take up 160 of 160 bytes (100.0 %)");
+        assert_eq!(lines[1], "   1. big: 100 bytes (62.5 %)");
+        assert_eq!(lines[3], "   3. small: 10 bytes (6.2 %)");
+
+        let mut out = Vec::new();
+        assert!(!report_budget(&mut out, 150, &assets).unwrap());
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("Over budget by 10 bytes\n"));
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use w4_pnger_common::{
+    name_hash, BUNDLE_ANIMATION, BUNDLE_DICTIONARY, BUNDLE_HEADER_SIZE, BUNDLE_NAME_HASHES,
+};
+
+use crate::convert::ConvertedSprite;
+
+//The dictionary is stored once for the bundle's lz-dict sprites, and left out if it's empty.
+//An animation's sprites are its frames, each after the first already XORed with the one before
+pub fn write_bundle(
+    sprites: &[ConvertedSprite],
+    name_hashes: bool,
+    dictionary: &[u8],
+    animation: bool,
+) -> Result<Vec<u8>> {
+    if sprites.len() > u16::MAX as usize {
+        bail!("Bundles can hold at most {} sprites", u16::MAX);
+    }
+
+    let data_size: usize = sprites.iter().map(|s| s.bytes.len()).sum();
+    let total_size = data_start(sprites.len(), name_hashes, dictionary.len()) + data_size;
+
+    if total_size > u16::MAX as usize {
+        bail!(
+            "Bundle would be {total_size} bytes, but offsets are limited to {} bytes",
+            u16::MAX
+        );
+    }
+
+    let mut flags = 0;
+    if name_hashes {
+        flags |= BUNDLE_NAME_HASHES;
+    }
+    if !dictionary.is_empty() {
+        flags |= BUNDLE_DICTIONARY;
+    }
+    if animation {
+        flags |= BUNDLE_ANIMATION;
+    }
+
+    let mut out = Vec::with_capacity(total_size);
+    out.push(flags);
+    out.extend_from_slice(&(sprites.len() as u16).to_le_bytes());
+
+    for offset in sprite_offsets(sprites, name_hashes, dictionary.len()) {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+    }
+    out.extend_from_slice(&(total_size as u16).to_le_bytes());
+
+    if name_hashes {
+        for sprite in sprites {
+            out.extend_from_slice(&name_hash(bundle_name(&sprite.name)).to_le_bytes());
+        }
+    }
+
+    if !dictionary.is_empty() {
+        out.extend_from_slice(&(dictionary.len() as u16).to_le_bytes());
+        out.extend_from_slice(dictionary);
+    }
+
+    for sprite in sprites {
+        out.extend_from_slice(&sprite.bytes);
+    }
+
+    Ok(out)
+}
+
+//Where each sprite starts, counted from the beginning of the bundle
+pub fn sprite_offsets(
+    sprites: &[ConvertedSprite],
+    name_hashes: bool,
+    dictionary_len: usize,
+) -> Vec<usize> {
+    let mut offset = data_start(sprites.len(), name_hashes, dictionary_len);
+
+    sprites
+        .iter()
+        .map(|sprite| {
+            let start = offset;
+            offset += sprite.bytes.len();
+            start
+        })
+        .collect()
+}
+
+//Where the sprites start, after the header, offset table, name hashes and dictionary
+pub(crate) fn data_start(count: usize, name_hashes: bool, dictionary_len: usize) -> usize {
+    let hashes = if name_hashes { count * 4 } else { 0 };
+    let dictionary = if dictionary_len > 0 {
+        2 + dictionary_len
+    } else {
+        0
+    };
+
+    BUNDLE_HEADER_SIZE + (count + 1) * 2 + hashes + dictionary
+}
+
+//Sprites are looked up by their file name without the extension, so "player.png" is "player"
+pub fn bundle_name(image_name: &str) -> &str {
+    Path::new(image_name)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(image_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use w4_pnger_common::{name_hash, CompType};
+    use w4_tiny_decomp::Bundle;
+
+    use crate::convert::ConvertedSprite;
+
+    use super::write_bundle;
+
+    #[test]
+    fn test_write_bundle() {
+        let sprites: Vec<ConvertedSprite> = ["a.png", "b.png"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| ConvertedSprite {
+                name: name.to_string(),
+                width: 8,
+                height: 1,
+                flags: 0,
+                comp_type: CompType::Uncompressed,
+                bytes: vec![0, 8, 1, 0, i as u8],
+            })
+            .collect();
+
+        let bytes = write_bundle(&sprites, true, &[], false).unwrap();
+        let bundle = Bundle::new(&bytes).unwrap();
+
+        assert_eq!(name_hash("a"), 0xe40c292c);
+        assert_eq!(bundle.len(), 2);
+        assert_eq!(bundle.get(1), Some(&sprites[1].bytes[..]));
+        assert_eq!(bundle.find(name_hash("a")), Some(&sprites[0].bytes[..]));
+
+        let bytes = write_bundle(&sprites, false, &[], false).unwrap();
+        let bundle = Bundle::new(&bytes).unwrap();
+
+        assert_eq!(bundle.get(0), Some(&sprites[0].bytes[..]));
+        assert_eq!(bundle.find(name_hash("a")), None);
+        assert!(bundle.dictionary().is_empty());
+
+        let bytes = write_bundle(&sprites, true, &[1, 2, 3], true).unwrap();
+        let bundle = Bundle::new(&bytes).unwrap();
+
+        assert_eq!(bundle.dictionary(), &[1, 2, 3]);
+        assert!(bundle.is_animation());
+        assert_eq!(bundle.get(1), Some(&sprites[1].bytes[..]));
+        assert_eq!(bundle.find(name_hash("b")), Some(&sprites[1].bytes[..]));
+    }
+}
@@ -0,0 +1,216 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::compress::{CompressionResult, Compressor};
+
+pub const DEFAULT_CACHE_DIR: &str = ".w4-pnger-cache";
+
+//Bump whenever the entry layout or key changes. Changes to a compressor's output bump its
+//Compressor::version instead, which is also part of the key
+const CACHE_VERSION: u8 = 3;
+const MAGIC: &[u8; 4] = b"W4C\0";
+
+//Compression results stored on disk, one file per entry, keyed on the sprite's
+//header and pixel bytes along with the compressor used
+pub struct Cache {
+    dir: PathBuf,
+}
+
+//A hash naming the entry's file, along with everything that went into it. Entries store their
+//input and are only used when it matches, so a hash collision is a miss rather than another
+//sprite's bytes
+pub struct CacheKey {
+    hash: u64,
+    input: Vec<u8>,
+}
+
+impl Cache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Cache {
+            dir: dir.as_ref().to_owned(),
+        }
+    }
+
+    pub fn key(compressor: &dyn Compressor, header: &[u8], sprite: &[u8]) -> CacheKey {
+        let mut input = vec![CACHE_VERSION];
+        input.extend_from_slice(compressor.name().as_bytes());
+        input.extend_from_slice(&[0, compressor.version()]);
+        input.extend_from_slice(header);
+        input.extend_from_slice(sprite);
+
+        let mut hash = Fnv64::new();
+        hash.write(&input);
+        CacheKey {
+            hash: hash.finish(),
+            input,
+        }
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<CompressionResult> {
+        let bytes = std::fs::read(self.entry_path(key)).ok()?;
+        decode_entry(&bytes, &key.input).ok()
+    }
+
+    pub fn put(&self, key: &CacheKey, result: &CompressionResult) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+
+        //Write then rename, so an interrupted build never leaves a truncated entry behind
+        let path = self.entry_path(key);
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, encode_entry(&key.input, result)?)?;
+        std::fs::rename(temp_path, path)?;
+
+        Ok(())
+    }
+
+    //Returns the cached result if there is one, otherwise compresses and stores the result.
+    //The bool is true on a cache hit
+    pub fn get_or_compress<F>(
+        &self,
+        key: &CacheKey,
+        compress: F,
+    ) -> Result<(CompressionResult, bool)>
+    where
+        F: FnOnce() -> Result<CompressionResult>,
+    {
+        if let Some(result) = self.get(key) {
+            return Ok((result, true));
+        }
+
+        let result = compress()?;
+        if let Err(e) = self.put(key, &result) {
+            eprintln!("Failed to write to cache {}: {e}", self.dir.display());
+        }
+
+        Ok((result, false))
+    }
+
+    fn entry_path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(format!("{:016x}", key.hash))
+    }
+}
+
+struct Fnv64(u64);
+
+impl Fnv64 {
+    fn new() -> Self {
+        Fnv64(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+//[magic][version][input][header][content][name][statistics], each field after the version
+//stored as a u32 little endian length followed by its bytes
+fn encode_entry(input: &[u8], result: &CompressionResult) -> Result<Vec<u8>> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(CACHE_VERSION);
+
+    for field in [
+        input,
+        &result.header_bytes[..],
+        &result.content_bytes[..],
+        result.readable_compression_name.as_bytes(),
+        result.readable_compression_statistics.as_bytes(),
+    ] {
+        bytes.extend_from_slice(&u32::try_from(field.len())?.to_le_bytes());
+        bytes.extend_from_slice(field);
+    }
+
+    Ok(bytes)
+}
+
+fn decode_entry(bytes: &[u8], input: &[u8]) -> Result<CompressionResult> {
+    if bytes.len() < 5 || &bytes[..4] != MAGIC || bytes[4] != CACHE_VERSION {
+        bail!("Not a cache entry");
+    }
+
+    let mut rest = &bytes[5..];
+    let mut next_field = || -> Result<Vec<u8>> {
+        if rest.len() < 4 {
+            bail!("Truncated cache entry");
+        }
+        let len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        if rest.len() < 4 + len {
+            bail!("Truncated cache entry");
+        }
+        let field = rest[4..4 + len].to_vec();
+        rest = &rest[4 + len..];
+        Ok(field)
+    };
+
+    if next_field()? != input {
+        bail!("Cache entry is for another sprite");
+    }
+    let header_bytes = next_field()?;
+    let content_bytes = next_field()?;
+    let readable_compression_name = String::from_utf8(next_field()?)?;
+    let readable_compression_statistics = String::from_utf8(next_field()?)?;
+
+    Ok(CompressionResult {
+        total_size: header_bytes.len() + content_bytes.len(),
+        content_bytes,
+        header_bytes,
+        readable_compression_name,
+        readable_compression_statistics,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cache;
+    use crate::compress::{huffcomp::HuffComp, pkcomp::PkComp, CompressionResult};
+
+    #[test]
+    fn test_cache() {
+        let dir = std::env::temp_dir().join("w4-pnger-cache-test");
+        _ = std::fs::remove_dir_all(&dir);
+        let cache = Cache::new(&dir);
+
+        let key = Cache::key(&PkComp, &[8, 8, 1], &[1, 2, 3]);
+        assert_ne!(key.hash, Cache::key(&PkComp, &[8, 8, 0], &[1, 2, 3]).hash);
+        assert_ne!(key.hash, Cache::key(&HuffComp, &[8, 8, 1], &[1, 2, 3]).hash);
+        assert!(cache.get(&key).is_none());
+
+        let compress = || {
+            Ok(CompressionResult {
+                content_bytes: vec![4, 5, 6],
+                header_bytes: vec![7],
+                total_size: 4,
+                readable_compression_name: "Test".to_owned(),
+                readable_compression_statistics: "stats".to_owned(),
+            })
+        };
+
+        let (_, hit) = cache.get_or_compress(&key, compress).unwrap();
+        assert!(!hit);
+
+        let (result, hit) = cache
+            .get_or_compress(&key, || panic!("should be cached"))
+            .unwrap();
+        assert!(hit);
+        assert_eq!(result.content_bytes, [4, 5, 6]);
+        assert_eq!(result.header_bytes, [7]);
+        assert_eq!(result.total_size, 4);
+        assert_eq!(result.readable_compression_name, "Test");
+        assert_eq!(result.readable_compression_statistics, "stats");
+
+        //Another sprite whose key collides with this one's
+        let mut collision = Cache::key(&PkComp, &[8, 8, 1], &[1, 2, 4]);
+        collision.hash = key.hash;
+        assert!(cache.get(&collision).is_none());
+
+        std::fs::write(dir.join(format!("{:016x}", key.hash)), [0, 1]).unwrap();
+        assert!(cache.get(&key).is_none());
+    }
+}
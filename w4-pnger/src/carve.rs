@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use w4_pnger_common::{BitsPerPixel, CompType};
+
+use crate::compress::lzcomp::{LzParams, MAX_LENGTH_BITS, MAX_OFFSET_BITS};
+use crate::wasm4png::W4Sprite;
+
+const WASM_MAGIC: &[u8; 4] = b"\0asm";
+const DATA_SECTION: u8 = 11;
+
+//A data segment from a wasm module, which is where a cart's sprites end up
+pub struct DataSegment {
+    pub index: usize,
+    //Where an active segment is copied to in memory, None for passive segments
+    pub address: Option<u32>,
+    pub bytes: Vec<u8>,
+}
+
+//A sprite found in a data segment
+pub struct CarvedSprite {
+    pub location: Location,
+    //None for raw pixel data without a .ws header
+    pub comp_type: Option<CompType>,
+    //How many bytes the sprite takes up in the cart, including the .ws header
+    pub len: usize,
+    pub sprite: W4Sprite,
+}
+
+#[derive(Clone, Copy)]
+pub struct Location {
+    pub segment: usize,
+    pub offset: usize,
+    pub address: Option<u32>,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.address {
+            Some(address) => write!(f, "0x{:06x}", address as usize + self.offset),
+            None => write!(f, "segment{}+0x{:x}", self.segment, self.offset),
+        }
+    }
+}
+
+pub fn data_segments(wasm: &[u8]) -> Result<Vec<DataSegment>> {
+    if wasm.len() < 8 || &wasm[..4] != WASM_MAGIC {
+        bail!("Not a wasm module");
+    }
+
+    let mut reader = WasmReader {
+        bytes: wasm,
+        pos: 8,
+    };
+
+    while !reader.is_empty() {
+        let id = reader.byte()?;
+        let size = reader.u32()? as usize;
+        let section = reader.take(size)?;
+
+        if id == DATA_SECTION {
+            return read_data_section(section);
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+fn read_data_section(section: &[u8]) -> Result<Vec<DataSegment>> {
+    let mut reader = WasmReader {
+        bytes: section,
+        pos: 0,
+    };
+
+    let count = reader.u32()?;
+    let mut segments = Vec::new();
+
+    for index in 0..count as usize {
+        let address = match reader.u32()? {
+            0 => reader.const_expr()?,
+            1 => None,
+            2 => {
+                reader.u32()?;
+                reader.const_expr()?
+            }
+            kind => bail!("Unknown data segment kind {kind}"),
+        };
+
+        let len = reader.u32()? as usize;
+        let bytes = reader.take(len)?.to_vec();
+
+        segments.push(DataSegment {
+            index,
+            address,
+            bytes,
+        });
+    }
+
+    Ok(segments)
+}
+
+struct WasmReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> WasmReader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        match self.bytes.get(self.pos) {
+            Some(byte) => {
+                self.pos += 1;
+                Ok(*byte)
+            }
+            None => bail!("Unexpected end of wasm module"),
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.pos < len {
+            bail!("Unexpected end of wasm module");
+        }
+
+        let taken = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(taken)
+    }
+
+    //Unsigned LEB128
+    fn u32(&mut self) -> Result<u32> {
+        let mut value = 0u64;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value as u32);
+            }
+        }
+        bail!("Invalid LEB128 number");
+    }
+
+    //Signed LEB128
+    fn i64(&mut self) -> Result<i64> {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift < 64 {
+                value |= ((byte & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Ok(value);
+            }
+            if shift >= 70 {
+                bail!("Invalid LEB128 number");
+            }
+        }
+    }
+
+    //The offset of an active data segment. Only constant offsets have a known address
+    fn const_expr(&mut self) -> Result<Option<u32>> {
+        let mut address = None;
+        loop {
+            match self.byte()? {
+                0x0b => return Ok(address),
+                0x41 | 0x42 => address = u32::try_from(self.i64()?).ok(),
+                0x23 => {
+                    self.u32()?;
+                    address = None;
+                }
+                opcode => bail!("Unsupported opcode 0x{opcode:02x} in a data segment offset"),
+            }
+        }
+    }
+}
+
+//Looks for .ws sprites at every offset of the segment. Sprites found don't overlap
+pub fn carve_ws(segment: &DataSegment) -> Vec<CarvedSprite> {
+    let bytes = &segment.bytes;
+    let mut carved = Vec::new();
+    let mut offset = 0;
+
+    while offset + 4 <= bytes.len() {
+        match ws_at(&bytes[offset..]) {
+            Some((comp_type, len, sprite)) => {
+                carved.push(CarvedSprite {
+                    location: Location {
+                        segment: segment.index,
+                        offset,
+                        address: segment.address,
+                    },
+                    comp_type: Some(comp_type),
+                    len,
+                    sprite,
+                });
+                offset += len;
+            }
+            None => offset += 1,
+        }
+    }
+
+    carved
+}
+
+//Checks whether a .ws sprite starts at the beginning of bytes, returning its length if so
+fn ws_at(bytes: &[u8]) -> Option<(CompType, usize, W4Sprite)> {
+    let comp_type = CompType::try_from(bytes[0]).ok()?;
+    let (width, height) = (bytes[1] as u16, bytes[2] as u16);
+    let bpp = BitsPerPixel::try_from_flags(bytes[3]).ok()?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    match comp_type {
+        //Can't be decompressed without finding the bundle's dictionary as well
+        CompType::LzDict => None,
+        CompType::Uncompressed => {
+            //Pixel data alone is too easy to find by accident, so only look for sprites
+            //with whole bytes per row, which are most of them, and skip solid blocks
+            if width % 8 != 0 {
+                return None;
+            }
+
+            let len = 4 + bpp.packed_len(width.into(), height.into());
+            let pixels = bytes.get(4..len)?;
+            if pixels.iter().all(|&b| b == pixels[0]) {
+                return None;
+            }
+
+            let sprite = W4Sprite::from_packed(width, height, bpp, pixels.to_vec()).ok()?;
+            Some((comp_type, len, sprite))
+        }
+        CompType::Pk => {
+            //Only parameters PkComp would ever choose
+            let params = *bytes.get(4)?;
+            let jump_size = *bytes.get(5)?;
+            let (split, xor, seq) = (params & 1 != 0, params & 2 != 0, (params >> 2) & 0xf);
+            if (xor && !split) || seq > 4 || params >> 6 > 2 {
+                return None;
+            }
+            if !(4..=32).contains(&jump_size) || jump_size % 2 != 0 {
+                return None;
+            }
+
+            let sprite = W4Sprite::from_ws(bytes).ok()?;
+            Some((comp_type, shortest_ws(bytes, 6), sprite))
+        }
+        CompType::Lz => {
+            //Only parameters LzComp would ever choose
+            let params = LzParams::from_header(bytes.get(4..)?)?;
+            if !(1..=MAX_OFFSET_BITS).contains(&params.offset_bits)
+                || !(1..=MAX_LENGTH_BITS).contains(&params.length_bits)
+            {
+                return None;
+            }
+
+            let sprite = W4Sprite::from_ws(bytes).ok()?;
+            Some((comp_type, shortest_ws(bytes, 5), sprite))
+        }
+        CompType::Huffman => {
+            //Only parameters HuffComp would ever choose
+            let params = *bytes.get(4)?;
+            if params & 0b11 == 0b11 || params >> 4 != 0 {
+                return None;
+            }
+
+            let sprite = W4Sprite::from_ws(bytes).ok()?;
+            Some((comp_type, shortest_ws(bytes, 6), sprite))
+        }
+    }
+}
+
+//Decoding fails when the data runs out early, so the shortest prefix which still decodes
+//is the sprite itself
+fn shortest_ws(bytes: &[u8], header_len: usize) -> usize {
+    let (mut low, mut high) = (header_len, bytes.len());
+    while low < high {
+        let mid = (low + high) / 2;
+        if W4Sprite::from_ws(&bytes[..mid]).is_ok() {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+
+    low
+}
+
+//Reads headerless pixel data, like png2src output, at each offset.
+//Without offsets the segment is split into back to back sprites
+pub fn carve_raw(
+    segment: &DataSegment,
+    width: u16,
+    height: u16,
+    bpp: BitsPerPixel,
+    addresses: &[u32],
+) -> Vec<CarvedSprite> {
+    let len = bpp.packed_len(width.into(), height.into());
+    if len == 0 {
+        return Vec::new();
+    }
+
+    let offsets: Vec<usize> = match segment.address {
+        Some(start) if !addresses.is_empty() => addresses
+            .iter()
+            .filter_map(|&a| a.checked_sub(start))
+            .map(|offset| offset as usize)
+            .collect(),
+        _ if !addresses.is_empty() => Vec::new(),
+        _ => (0..segment.bytes.len()).step_by(len).collect(),
+    };
+
+    offsets
+        .into_iter()
+        .filter_map(|offset| {
+            let pixels = segment.bytes.get(offset..offset + len)?;
+            if addresses.is_empty() && pixels.iter().all(|&b| b == pixels[0]) {
+                return None;
+            }
+
+            Some(CarvedSprite {
+                location: Location {
+                    segment: segment.index,
+                    offset,
+                    address: segment.address,
+                },
+                comp_type: None,
+                len,
+                sprite: W4Sprite::from_packed(width, height, bpp, pixels.to_vec()).ok()?,
+            })
+        })
+        .collect()
+}
+
+//For each sprite, the index of the first identical sprite before it, if there is one
+pub fn find_duplicates(carved: &[CarvedSprite]) -> Vec<Option<usize>> {
+    let mut seen = HashMap::new();
+
+    carved
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let key = (c.sprite.get_header_bytes(), c.sprite.get_bytes());
+            match seen.get(&key) {
+                Some(&first) => Some(first),
+                None => {
+                    seen.insert(key, i);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+//Headerless sprites to look for, as png2src writes them
+pub struct RawSprites {
+    pub width: u16,
+    pub height: u16,
+    pub bpp: BitsPerPixel,
+    pub addresses: Vec<u32>,
+}
+
+//Writes every sprite found in a cart to out_dir as a png, and lists them along with any duplicates
+pub fn carve_file(
+    cart: &Path,
+    out_dir: &Path,
+    palette: &[[u8; 4]],
+    raw: Option<&RawSprites>,
+) -> Result<()> {
+    let wasm = std::fs::read(cart).with_context(|| format!("Failed to read {}", cart.display()))?;
+    let segments = data_segments(&wasm)
+        .with_context(|| format!("Failed to read data segments from {}", cart.display()))?;
+
+    let carved: Vec<CarvedSprite> = segments
+        .iter()
+        .flat_map(|segment| match raw {
+            Some(raw) => carve_raw(segment, raw.width, raw.height, raw.bpp, &raw.addresses),
+            None => carve_ws(segment),
+        })
+        .collect();
+
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create {}", out_dir.display()))?;
+
+    let duplicates = find_duplicates(&carved);
+    let mut wasted = 0;
+
+    for (c, duplicate) in carved.iter().zip(&duplicates) {
+        let kind = match c.comp_type {
+            Some(CompType::Uncompressed) => "uncompressed",
+            Some(CompType::Pk) => "pk",
+            Some(CompType::Lz) => "lz",
+            Some(CompType::Huffman) => "huffman",
+            Some(CompType::LzDict) => "lz-dict",
+            None => "raw",
+        };
+        let out_name = format!(
+            "{}_{}x{}.png",
+            c.location.to_string().replace('+', "_"),
+            c.sprite.width,
+            c.sprite.height
+        );
+
+        let out_path = out_dir.join(&out_name);
+        let out = File::create(&out_path)
+            .with_context(|| format!("Failed to create {}", out_path.display()))?;
+        c.sprite.write_png(BufWriter::new(out), palette)?;
+
+        print!(
+            "{}: {}x{} {} bpp, {kind}, {} bytes -> {out_name}",
+            c.location,
+            c.sprite.width,
+            c.sprite.height,
+            c.sprite.bpp.get_num(),
+            c.len
+        );
+        match duplicate {
+            Some(first) => {
+                wasted += c.len;
+                println!(", same as {}", carved[*first].location);
+            }
+            None => println!(),
+        }
+    }
+
+    let duplicate_count = duplicates.iter().filter(|d| d.is_some()).count();
+    println!(
+        "Found {} sprites in {} data segments, {duplicate_count} of them duplicates taking up {wasted} bytes",
+        carved.len(),
+        segments.len()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use w4_pnger_common::CompType;
+
+    use super::{carve_raw, carve_ws, data_segments, find_duplicates};
+    use crate::compress::{pkcomp::PkComp, Compressor};
+    use crate::convert::ConvertedSprite;
+    use crate::wasm4png::W4Sprite;
+
+    fn leb(mut n: usize, out: &mut Vec<u8>) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                out.push(byte);
+                return;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    #[test]
+    fn test_carve() {
+        let sprite = W4Sprite::from_path("../test.png").unwrap();
+        let uncompressed = ConvertedSprite::uncompressed("a".to_owned(), &sprite).bytes;
+        let compressed = ConvertedSprite::compressed(
+            "b".to_owned(),
+            &sprite,
+            CompType::Pk,
+            PkComp {}.compress(&sprite.get_bytes()).unwrap(),
+        )
+        .bytes;
+
+        let mut data = vec![7, 0, 3, 0, 9];
+        data.extend_from_slice(&compressed);
+        data.extend_from_slice(&[1, 2, 3]);
+        data.extend_from_slice(&uncompressed);
+
+        //A module with only a data section, holding one active segment at 0x19a0
+        let mut section = vec![1, 0, 0x41];
+        section.extend_from_slice(&[0xa0, 0x33, 0x0b]);
+        leb(data.len(), &mut section);
+        section.extend_from_slice(&data);
+
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        wasm.extend_from_slice(&[0, 2, 0, 0]);
+        wasm.push(11);
+        leb(section.len(), &mut wasm);
+        wasm.extend_from_slice(&section);
+
+        let segments = data_segments(&wasm).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].address, Some(0x19a0));
+        assert_eq!(segments[0].bytes, data);
+
+        let carved = carve_ws(&segments[0]);
+        assert_eq!(carved.len(), 2);
+        assert_eq!(carved[0].location.offset, 5);
+        assert_eq!(carved[0].len, compressed.len());
+        assert_eq!(carved[0].location.to_string(), "0x0019a5");
+        assert_eq!(carved[1].location.offset, 8 + compressed.len());
+        assert_eq!(carved[1].len, uncompressed.len());
+        for c in &carved {
+            assert_eq!(c.sprite.get_bytes(), sprite.get_bytes());
+        }
+        assert_eq!(find_duplicates(&carved), [None, Some(0)]);
+
+        let address = 0x19a0 + 8 + compressed.len() as u32 + 4;
+        let raw = carve_raw(&segments[0], 32, 24, sprite.bpp, &[address]);
+        assert_eq!(raw.len(), 1);
+        assert_eq!(raw[0].sprite.get_bytes(), sprite.get_bytes());
+
+        assert!(data_segments(b"not wasm").is_err());
+    }
+}
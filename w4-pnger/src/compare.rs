@@ -0,0 +1,255 @@
+use std::io::Write;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::analyze::{csv_field, ratio};
+use crate::compress::compressors;
+use crate::wasm4png::W4Sprite;
+
+pub const UNCOMPRESSED: &str = "Uncompressed";
+
+#[derive(Serialize)]
+pub struct Comparison {
+    pub sprites: Vec<SpriteComparison>,
+    pub methods: Vec<MethodTotal>,
+}
+
+#[derive(Serialize)]
+pub struct SpriteComparison {
+    pub name: String,
+    pub raw_size: usize,
+    //Uncompressed first, then every compressor in the order they're tried
+    pub results: Vec<MethodResult>,
+    //The method with the smallest output, uncompressed winning ties
+    pub winner: String,
+}
+
+#[derive(Serialize)]
+pub struct MethodResult {
+    pub method: String,
+    //Size of the pixel data, including the compression header
+    pub size: usize,
+    pub ratio: f32,
+    pub decode_cost: usize,
+}
+
+#[derive(Serialize)]
+pub struct MethodTotal {
+    pub method: String,
+    //Size of every sprite if they all used this method
+    pub size: usize,
+    //Size of every sprite if this method is only used where it beats uncompressed
+    pub best_size: usize,
+    pub decoder_size: usize,
+    //Bytes saved over leaving every sprite uncompressed, once the decoder is paid for
+    pub saved: i64,
+}
+
+impl SpriteComparison {
+    //Compresses the sprite with every method
+    pub fn new(name: String, sprite: &W4Sprite) -> Result<Self> {
+        let raw = sprite.get_bytes();
+
+        let mut results = vec![MethodResult {
+            method: UNCOMPRESSED.to_owned(),
+            size: raw.len(),
+            ratio: ratio(raw.len(), raw.len()),
+            decode_cost: 0,
+        }];
+        for compressor in compressors() {
+            let compressed = compressor.compress(&raw)?;
+            results.push(MethodResult {
+                decode_cost: compressor.decode_cost(&compressed, raw.len()),
+                method: compressed.readable_compression_name,
+                size: compressed.total_size,
+                ratio: ratio(raw.len(), compressed.total_size),
+            });
+        }
+
+        let mut winner = &results[0];
+        for result in &results[1..] {
+            if result.size < winner.size {
+                winner = result;
+            }
+        }
+
+        Ok(SpriteComparison {
+            name,
+            raw_size: raw.len(),
+            winner: winner.method.clone(),
+            results,
+        })
+    }
+
+    pub fn winner_size(&self) -> usize {
+        self.results
+            .iter()
+            .find(|r| r.method == self.winner)
+            .map_or(self.raw_size, |r| r.size)
+    }
+
+    pub fn write_text<W: Write>(&self, out: &mut W) -> Result<()> {
+        writeln!(out, "\n{}, {} bytes raw", self.name, self.raw_size)?;
+        let width = method_width(self.results.iter().map(|r| r.method.as_str()));
+        writeln!(
+            out,
+            "    {:width$}  {:>6}  {:>6}  {:>11}",
+            "Method", "Size", "Ratio", "Decode cost"
+        )?;
+
+        for result in &self.results {
+            let marker = if result.method == self.winner {
+                "*"
+            } else {
+                " "
+            };
+            writeln!(
+                out,
+                "  {marker} {:width$}  {:>6}  {:>5.1}%  {:>11}",
+                result.method,
+                result.size,
+                result.ratio * 100.0,
+                result.decode_cost
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Comparison {
+    pub fn new(sprites: Vec<SpriteComparison>) -> Self {
+        let raw_size: usize = sprites.iter().map(|s| s.raw_size).sum();
+        let decoder_sizes: Vec<usize> = std::iter::once(0)
+            .chain(compressors().iter().map(|c| c.decoder_size()))
+            .collect();
+
+        let mut methods = Vec::new();
+        if let Some(first) = sprites.first() {
+            for (i, result) in first.results.iter().enumerate() {
+                let size = sprites.iter().map(|s| s.results[i].size).sum();
+                let best_size: usize = sprites
+                    .iter()
+                    .map(|s| s.results[i].size.min(s.raw_size))
+                    .sum();
+                //Without any sprite to decompress, the decoder can be left out
+                let decoder_size = if best_size < raw_size {
+                    decoder_sizes[i]
+                } else {
+                    0
+                };
+
+                methods.push(MethodTotal {
+                    method: result.method.clone(),
+                    size,
+                    best_size,
+                    decoder_size,
+                    saved: raw_size as i64 - (best_size + decoder_size) as i64,
+                });
+            }
+        }
+
+        Comparison { sprites, methods }
+    }
+
+    //The sprite tables are written as sprites are compared, so this only writes the totals
+    pub fn write_text<W: Write>(&self, out: &mut W) -> Result<()> {
+        writeln!(
+            out,
+            "\nAcross {} sprites, using each method wherever it beats uncompressed:",
+            self.sprites.len()
+        )?;
+        let width = method_width(self.methods.iter().map(|m| m.method.as_str()));
+        writeln!(
+            out,
+            "    {:width$}  {:>6}  {:>7}  {:>7}",
+            "Method", "Size", "Decoder", "Saved"
+        )?;
+
+        for method in &self.methods {
+            writeln!(
+                out,
+                "    {:width$}  {:>6}  {:>7}  {:>7}",
+                method.method, method.best_size, method.decoder_size, method.saved
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_json<W: Write>(&self, out: &mut W) -> Result<()> {
+        serde_json::to_writer_pretty(&mut *out, self)?;
+        writeln!(out)?;
+        Ok(())
+    }
+
+    //One row per method per sprite
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> Result<()> {
+        writeln!(out, "name,raw_size,method,size,ratio,decode_cost,winner")?;
+
+        for sprite in &self.sprites {
+            for result in &sprite.results {
+                writeln!(
+                    out,
+                    "{},{},{},{},{:.4},{},{}",
+                    csv_field(&sprite.name),
+                    sprite.raw_size,
+                    csv_field(&result.method),
+                    result.size,
+                    result.ratio,
+                    result.decode_cost,
+                    result.method == sprite.winner
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn method_width<'a>(methods: impl Iterator<Item = &'a str>) -> usize {
+    methods.map(str::len).max().unwrap_or(0).max("Method".len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Comparison, SpriteComparison, UNCOMPRESSED};
+    use crate::wasm4png::W4Sprite;
+    use w4_pnger_common::BitsPerPixel;
+
+    #[test]
+    fn test_compare() {
+        let solid = W4Sprite::from_packed(16, 16, BitsPerPixel::One, vec![0; 32]).unwrap();
+        let noise: Vec<u8> = (0..32u32).map(|i| (i * 151 + 7) as u8 ^ 0x5a).collect();
+        let noise = W4Sprite::from_packed(16, 16, BitsPerPixel::One, noise).unwrap();
+
+        let solid = SpriteComparison::new("solid".to_owned(), &solid).unwrap();
+        assert_eq!(solid.results[0].method, UNCOMPRESSED);
+        assert_ne!(solid.winner, UNCOMPRESSED);
+        assert!(solid.winner_size() < 32);
+
+        let noise = SpriteComparison::new("noise".to_owned(), &noise).unwrap();
+        assert_eq!(noise.winner, UNCOMPRESSED);
+        assert_eq!(noise.winner_size(), 32);
+
+        let comparison = Comparison::new(vec![solid, noise]);
+        let uncompressed = &comparison.methods[0];
+        assert_eq!((uncompressed.size, uncompressed.saved), (64, 0));
+        //A couple of bytes saved on a solid sprite don't pay for a decoder
+        assert!(comparison.methods[1].saved < 0);
+        assert_eq!(
+            comparison.methods[1].best_size,
+            comparison.sprites[0].results[1].size + 32
+        );
+
+        let mut csv = Vec::new();
+        comparison.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), 1 + 2 * comparison.methods.len());
+        assert_eq!(
+            csv.lines().nth(1),
+            Some("solid,32,Uncompressed,32,1.0000,0,false")
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use super::{delta_encode, split_bitplanes, CompressionResult, Compressor};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use tiny_bitfiddle::{BitReader, BitVecWriter, BitWriter};
+use w4_pnger_common::{CompType, HUFFMAN_MAX_CODE_LEN};
+
+//2 bits is a pixel of a 2bpp sprite, 4 and 8 bits catch patterns across pixels
+const SYMBOL_SIZES: [u8; 3] = [2, 4, 8];
+
+pub struct HuffComp;
+
+impl Compressor for HuffComp {
+    fn compress(&self, png: &[u8]) -> Result<CompressionResult> {
+        if png.is_empty() {
+            bail!("Sprite has no pixel data to compress");
+        }
+
+        let mut best: Option<(HuffParams, Vec<u8>, Vec<u8>)> = None;
+
+        //Splitting needs each bitplane to be whole bytes
+        let max_split = if png.len().is_multiple_of(2) { 1 } else { 0 };
+
+        for split in 0..=max_split {
+            for delta in 0..=1 {
+                let transformed = transform(png, split != 0, delta != 0);
+
+                for symbol_bits in SYMBOL_SIZES {
+                    let params = HuffParams {
+                        symbol_bits,
+                        split: split != 0,
+                        delta: delta != 0,
+                    };
+                    let (table, content) = encode(&transformed, symbol_bits);
+
+                    let size = table.len() + content.len();
+                    if best
+                        .as_ref()
+                        .is_none_or(|(_, t, c)| size < t.len() + c.len())
+                    {
+                        best = Some((params, table, content));
+                    }
+                }
+            }
+        }
+
+        let (params, table, out_content) = best.unwrap();
+        let mut out_header = params.to_header();
+        out_header.extend(table);
+
+        let len = out_content.len() + out_header.len();
+
+        Ok(CompressionResult {
+            content_bytes: out_content,
+            header_bytes: out_header,
+            total_size: len,
+            readable_compression_name: "Huffman".to_owned(),
+            readable_compression_statistics: format!(
+                "{} bit symbols, split bitplanes={}, delta-encoded={}",
+                params.symbol_bits, params.split, params.delta
+            ),
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        "huffman"
+    }
+
+    fn version(&self) -> u8 {
+        1
+    }
+
+    fn comp_type(&self) -> CompType {
+        CompType::Huffman
+    }
+
+    fn decode_cost(&self, result: &CompressionResult, raw_size: usize) -> usize {
+        let bits = raw_size * 8;
+        let Some(params) = HuffParams::from_header(&result.header_bytes) else {
+            return bits;
+        };
+
+        //Each code is read a bit at a time, then each symbol written out
+        let mut cost = result.content_bytes.len() * 8 + bits;
+        if params.delta {
+            cost += bits;
+        }
+        if params.split {
+            //The in-place in-shuffle moves each bit about three times
+            cost += bits * 3;
+        }
+
+        cost
+    }
+
+    fn decoder_size(&self) -> usize {
+        //Not measured. The decoder rebuilds the code table from the header before walking the
+        //bits, so it's put on par with pk's estimate
+        2300
+    }
+}
+
+//The parameters HuffComp chose for a sprite, as stored in the first byte of its compression header
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct HuffParams {
+    pub symbol_bits: u8,
+    pub split: bool,
+    pub delta: bool,
+}
+
+impl HuffParams {
+    pub fn from_header(header: &[u8]) -> Option<Self> {
+        let params = header.first()?;
+        let symbol_bits = match params & 0b11 {
+            0 => 2,
+            1 => 4,
+            2 => 8,
+            _ => return None,
+        };
+
+        Some(HuffParams {
+            symbol_bits,
+            split: params & (1 << 2) != 0,
+            delta: params & (1 << 3) != 0,
+        })
+    }
+
+    pub fn to_header(&self) -> Vec<u8> {
+        let size = match self.symbol_bits {
+            2 => 0,
+            4 => 1,
+            _ => 2,
+        };
+
+        vec![size | (self.split as u8) << 2 | (self.delta as u8) << 3]
+    }
+}
+
+fn transform(png: &[u8], split: bool, delta: bool) -> Vec<u8> {
+    let mut bytes = png.to_vec();
+
+    if split {
+        let mut bitplane_1 = Vec::with_capacity(bytes.len() / 2);
+        let mut bitplane_2 = Vec::with_capacity(bytes.len() / 2);
+        split_bitplanes(&bytes, &mut bitplane_1, &mut bitplane_2);
+
+        bytes = bitplane_1;
+        bytes.append(&mut bitplane_2);
+    }
+
+    if delta {
+        let mut encoded = Vec::with_capacity(bytes.len());
+        delta_encode(&bytes, &mut encoded);
+        bytes = encoded;
+    }
+
+    bytes
+}
+
+//Returns the code table and the coded symbols
+fn encode(bytes: &[u8], symbol_bits: u8) -> (Vec<u8>, Vec<u8>) {
+    let mut reader = BitReader::new(bytes);
+    let symbols: Vec<usize> = (0..bytes.len() * 8 / symbol_bits as usize)
+        .map(|_| {
+            (0..symbol_bits).fold(0, |symbol, _| {
+                symbol << 1 | reader.read_bit().expect("whole symbols fit in the bytes") as usize
+            })
+        })
+        .collect();
+
+    let mut freq = vec![0; 1 << symbol_bits];
+    for &symbol in &symbols {
+        freq[symbol] += 1;
+    }
+    let lengths = code_lengths(&freq);
+    let max_len = lengths.iter().copied().max().unwrap_or(0);
+
+    //Canonical codes go to shorter codes first, then lower symbols
+    let mut order: Vec<usize> = (0..freq.len()).filter(|&s| lengths[s] > 0).collect();
+    order.sort_by_key(|&s| (lengths[s], s));
+
+    let mut codes = vec![0; freq.len()];
+    let mut code = 0;
+    let mut next = order.iter().peekable();
+    for len in 1..=max_len {
+        while let Some(&&symbol) = next.peek() {
+            if lengths[symbol] != len {
+                break;
+            }
+            codes[symbol] = code;
+            code += 1;
+            next.next();
+        }
+        code <<= 1;
+    }
+
+    let mut table = Vec::new();
+    let mut writer = BitVecWriter::new(&mut table);
+    writer.write(max_len as u32, 4);
+    for len in 1..=max_len {
+        let count = order.iter().filter(|&&s| lengths[s] == len).count();
+        writer.write(count as u32, symbol_bits as usize + 1);
+    }
+    for &symbol in &order {
+        writer.write(symbol as u32, symbol_bits as usize);
+    }
+
+    let mut content = Vec::new();
+    let mut writer = BitVecWriter::new(&mut content);
+    for &symbol in &symbols {
+        writer.write(codes[symbol], lengths[symbol] as usize);
+    }
+
+    (table, content)
+}
+
+//Huffman code lengths for each symbol, 0 for symbols which never appear. Codes longer than the
+//decoder allows are avoided by flattening the frequencies until the tree is shallow enough
+fn code_lengths(freq: &[usize]) -> Vec<u8> {
+    let mut freq = freq.to_vec();
+
+    loop {
+        let mut heap: BinaryHeap<Reverse<(usize, usize)>> = freq
+            .iter()
+            .enumerate()
+            .filter(|(_, &f)| f > 0)
+            .map(|(symbol, &f)| Reverse((f, symbol)))
+            .collect();
+
+        //Leaves are the symbols themselves, and every merge adds a node after them
+        let mut parent = vec![usize::MAX; freq.len()];
+        while heap.len() > 1 {
+            let Reverse((f1, n1)) = heap.pop().unwrap();
+            let Reverse((f2, n2)) = heap.pop().unwrap();
+            let node = parent.len();
+            parent.push(usize::MAX);
+            parent[n1] = node;
+            parent[n2] = node;
+            heap.push(Reverse((f1 + f2, node)));
+        }
+
+        let lengths: Vec<u8> = (0..freq.len())
+            .map(|symbol| {
+                if freq[symbol] == 0 {
+                    return 0;
+                }
+
+                let mut len = 0;
+                let mut node = symbol;
+                while parent[node] != usize::MAX {
+                    node = parent[node];
+                    len += 1;
+                }
+                //A lone symbol still needs a code
+                len.max(1)
+            })
+            .collect();
+
+        if lengths
+            .iter()
+            .all(|&len| len as usize <= HUFFMAN_MAX_CODE_LEN)
+        {
+            return lengths;
+        }
+
+        for f in freq.iter_mut().filter(|f| **f > 0) {
+            *f = f.div_ceil(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use w4_pnger_common::{BitsPerPixel, CompType, HUFFMAN_MAX_CODE_LEN};
+    use w4_tiny_decomp::Decompressor;
+
+    use crate::{compress::Compressor, wasm4png::W4Sprite};
+
+    use super::{code_lengths, HuffComp, HuffParams};
+
+    #[test]
+    fn test_comp_decomp() {
+        //Mostly the background colour, with a little of each of the others
+        let skewed: Vec<u8> = (0..64u32)
+            .map(|i| match i % 16 {
+                3 => 0b0001_1011,
+                9 => 0b0100_0000,
+                _ => 0,
+            })
+            .collect();
+        let skewed = W4Sprite::from_packed(16, 16, BitsPerPixel::Two, skewed).unwrap();
+        let solid = W4Sprite::from_packed(8, 1, BitsPerPixel::One, vec![0xff]).unwrap();
+
+        for sprite in [W4Sprite::from_path("../test.png").unwrap(), skewed, solid] {
+            let bytes = sprite.get_bytes();
+            let mut compressed = HuffComp.compress(&bytes).unwrap();
+            assert!(HuffParams::from_header(&compressed.header_bytes).is_some());
+
+            let mut assembled = vec![CompType::Huffman as u8];
+            assembled.append(&mut sprite.get_header_bytes());
+            assembled.append(&mut compressed.header_bytes);
+            assembled.append(&mut compressed.content_bytes);
+
+            let mut decomp_buf = vec![0u8; bytes.len()];
+            let mut decomp = Decompressor::new(&mut decomp_buf[..]);
+            let decompressed = decomp.decompress(&assembled[..]).unwrap();
+
+            assert_eq!(decompressed.bytes, bytes);
+        }
+    }
+
+    #[test]
+    fn test_code_lengths() {
+        assert_eq!(code_lengths(&[0, 5, 0, 0]), vec![0, 1, 0, 0]);
+        assert_eq!(code_lengths(&[8, 1, 1, 2]), vec![1, 3, 3, 2]);
+
+        //Fibonacci frequencies make the deepest possible tree
+        let mut fib = vec![1, 1];
+        while fib.len() < 24 {
+            fib.push(fib[fib.len() - 1] + fib[fib.len() - 2]);
+        }
+        let lengths = code_lengths(&fib);
+        assert!(lengths
+            .iter()
+            .all(|&len| len >= 1 && len as usize <= HUFFMAN_MAX_CODE_LEN));
+    }
+}
@@ -0,0 +1,424 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{CompressionResult, Compressor};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use tiny_bitfiddle::{BitVecWriter, BitWriter};
+use w4_pnger_common::{CompType, LZ_MIN_MATCH};
+
+//Offsets reach at most 4 KiB back, which covers all but the biggest 2bpp sprites
+pub const MAX_OFFSET_BITS: u8 = 12;
+pub const MAX_LENGTH_BITS: u8 = 6;
+const MAX_MATCH: usize = (1 << MAX_LENGTH_BITS) - 1 + LZ_MIN_MATCH;
+
+//A flag bit and a byte
+const LITERAL_BITS: usize = 9;
+
+//Dictionaries are trained from runs of bytes this long which several sprites share
+const DICT_KMER: usize = 4;
+const DICT_SEGMENT: usize = 16;
+
+pub struct LzComp;
+
+impl Compressor for LzComp {
+    fn compress(&self, png: &[u8]) -> Result<CompressionResult> {
+        compress_with_dictionary(png, &[], "LZSS")
+    }
+
+    fn name(&self) -> &'static str {
+        "lz"
+    }
+
+    fn version(&self) -> u8 {
+        1
+    }
+
+    fn comp_type(&self) -> CompType {
+        CompType::Lz
+    }
+
+    fn decode_cost(&self, result: &CompressionResult, raw_size: usize) -> usize {
+        //Each compressed bit is read once, and each byte written once
+        result.content_bytes.len() * 8 + raw_size
+    }
+
+    fn decoder_size(&self) -> usize {
+        //A guess rather than a measurement. The decoder is one loop copying literals and earlier
+        //bytes, so it should be well under half the size of pk's
+        950
+    }
+}
+
+//LzComp, but with matches reaching back into a dictionary shared by every sprite in a bundle
+pub struct LzDictComp {
+    dictionary: Vec<u8>,
+}
+
+impl LzDictComp {
+    pub const NAME: &'static str = "lz-dict";
+
+    pub fn new(dictionary: Vec<u8>) -> Self {
+        LzDictComp { dictionary }
+    }
+
+    pub fn dictionary(&self) -> &[u8] {
+        &self.dictionary
+    }
+}
+
+impl Compressor for LzDictComp {
+    fn compress(&self, png: &[u8]) -> Result<CompressionResult> {
+        compress_with_dictionary(png, &self.dictionary, "LZSS with dictionary")
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn version(&self) -> u8 {
+        LzComp.version()
+    }
+
+    fn comp_type(&self) -> CompType {
+        CompType::LzDict
+    }
+
+    fn decode_cost(&self, result: &CompressionResult, raw_size: usize) -> usize {
+        LzComp.decode_cost(result, raw_size)
+    }
+
+    fn decoder_size(&self) -> usize {
+        //The same decoder as LzComp
+        LzComp.decoder_size()
+    }
+}
+
+//Compresses png as if the dictionary came just before it, so matches can start in the dictionary
+fn compress_with_dictionary(
+    png: &[u8],
+    dictionary: &[u8],
+    readable_name: &str,
+) -> Result<CompressionResult> {
+    if png.is_empty() {
+        bail!("Sprite has no pixel data to compress");
+    }
+
+    let start = dictionary.len();
+    let data = [dictionary, png].concat();
+    let longest = longest_matches(&data, start);
+
+    let mut best_params = LzParams {
+        offset_bits: 1,
+        length_bits: 1,
+    };
+    let mut best_score = usize::MAX;
+
+    for offset_bits in 1..=MAX_OFFSET_BITS {
+        for length_bits in 1..=MAX_LENGTH_BITS {
+            let params = LzParams {
+                offset_bits,
+                length_bits,
+            };
+            let score = parse(&data, start, &longest, params)
+                .iter()
+                .map(|token| token.bits(params))
+                .sum();
+            if score < best_score {
+                best_score = score;
+                best_params = params;
+            }
+        }
+    }
+
+    let tokens = parse(&data, start, &longest, best_params);
+    let mut out_content = Vec::new();
+    let mut writer = BitVecWriter::new(&mut out_content);
+    let mut pos = start;
+    for token in &tokens {
+        match token {
+            Token::Literal => {
+                writer.write_bit(1);
+                writer.write(data[pos] as u32, 8);
+                pos += 1;
+            }
+            Token::Match(len) => {
+                let distance = find_distance(&data, pos, *len, best_params.window());
+                writer.write_bit(0);
+                writer.write((distance - 1) as u32, best_params.offset_bits as usize);
+                writer.write(
+                    (len - LZ_MIN_MATCH) as u32,
+                    best_params.length_bits as usize,
+                );
+                pos += len;
+            }
+        }
+    }
+
+    let out_header = best_params.to_header();
+    let len = out_content.len() + out_header.len();
+    let matches = tokens
+        .iter()
+        .filter(|t| matches!(t, Token::Match(_)))
+        .count();
+
+    Ok(CompressionResult {
+        content_bytes: out_content,
+        header_bytes: out_header,
+        total_size: len,
+        readable_compression_name: readable_name.to_owned(),
+        readable_compression_statistics: format!(
+            "{} bit offsets, {} bit lengths, {matches} matches and {} literals",
+            best_params.offset_bits,
+            best_params.length_bits,
+            tokens.len() - matches
+        ),
+    })
+}
+
+//The parameters LzComp chose for a sprite, as stored in its compression header
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct LzParams {
+    pub offset_bits: u8,
+    pub length_bits: u8,
+}
+
+impl LzParams {
+    pub fn from_header(header: &[u8]) -> Option<Self> {
+        header.first().map(|params| LzParams {
+            offset_bits: params & 0x0f,
+            length_bits: params >> 4,
+        })
+    }
+
+    pub fn to_header(&self) -> Vec<u8> {
+        vec![self.offset_bits | self.length_bits << 4]
+    }
+
+    //How far back a match can start
+    fn window(&self) -> usize {
+        1 << self.offset_bits
+    }
+
+    fn max_match(&self) -> usize {
+        (1 << self.length_bits) - 1 + LZ_MIN_MATCH
+    }
+}
+
+enum Token {
+    Literal,
+    Match(usize),
+}
+
+impl Token {
+    fn bits(&self, params: LzParams) -> usize {
+        match self {
+            Token::Literal => LITERAL_BITS,
+            Token::Match(_) => 1 + params.offset_bits as usize + params.length_bits as usize,
+        }
+    }
+}
+
+//For each position from start on, the longest match starting at most 2^k bytes back, for every k
+fn longest_matches(bytes: &[u8], start: usize) -> Vec<[usize; MAX_OFFSET_BITS as usize + 1]> {
+    let mut longest = vec![[0; MAX_OFFSET_BITS as usize + 1]; bytes.len() - start];
+
+    for (pos, longest) in (start..).zip(longest.iter_mut()) {
+        let max = MAX_MATCH.min(bytes.len() - pos);
+        let mut best = 0;
+        for (k, longest) in longest.iter_mut().enumerate() {
+            let (start, end) = (if k == 0 { 1 } else { (1 << (k - 1)) + 1 }, 1 << k);
+            for distance in start..=end.min(pos) {
+                //Nothing is longer than the longest possible match, and a match can only beat
+                //the best so far if it also matches the byte just past it
+                if best == max {
+                    break;
+                }
+                if bytes[pos + best] == bytes[pos + best - distance] {
+                    best = best.max(match_len(bytes, pos, distance, max));
+                }
+            }
+            *longest = best;
+        }
+    }
+
+    longest
+}
+
+fn match_len(bytes: &[u8], pos: usize, distance: usize, max: usize) -> usize {
+    let max = max.min(bytes.len() - pos);
+    (0..max)
+        .take_while(|i| bytes[pos + i] == bytes[pos + i - distance])
+        .count()
+}
+
+//Greedily takes the longest match wherever it's cheaper than writing literals
+fn parse(
+    bytes: &[u8],
+    start: usize,
+    longest: &[[usize; MAX_OFFSET_BITS as usize + 1]],
+    params: LzParams,
+) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let match_bits = Token::Match(0).bits(params);
+
+    let mut pos = start;
+    while pos < bytes.len() {
+        let len = longest[pos - start][params.offset_bits as usize].min(params.max_match());
+        if len >= LZ_MIN_MATCH && match_bits < len * LITERAL_BITS {
+            tokens.push(Token::Match(len));
+            pos += len;
+        } else {
+            tokens.push(Token::Literal);
+            pos += 1;
+        }
+    }
+
+    tokens
+}
+
+fn find_distance(bytes: &[u8], pos: usize, len: usize, window: usize) -> usize {
+    (1..=window.min(pos))
+        .find(|&distance| match_len(bytes, pos, distance, len) == len)
+        .expect("parse only takes matches which exist")
+}
+
+//Picks runs of bytes which turn up in many of the samples, up to size bytes of them. The most
+//useful runs go at the end, nearest the sprites, where they're cheapest to reach
+pub fn train_dictionary(samples: &[Vec<u8>], size: usize) -> Vec<u8> {
+    let size = size.min(1 << MAX_OFFSET_BITS);
+
+    let mut scores: HashMap<&[u8], usize> = HashMap::new();
+    for sample in samples {
+        let kmers: HashSet<&[u8]> = sample.windows(DICT_KMER).collect();
+        for kmer in kmers {
+            *scores.entry(kmer).or_default() += 1;
+        }
+    }
+    //A run only in one sprite is as cheap to match within that sprite
+    for score in scores.values_mut() {
+        *score -= 1;
+    }
+
+    let segments: Vec<&[u8]> = samples
+        .iter()
+        .flat_map(|sample| sample.windows(DICT_SEGMENT.min(sample.len())))
+        .collect();
+
+    let mut chosen: Vec<&[u8]> = Vec::new();
+    let mut len = 0;
+    while len < size {
+        let score = |segment: &[u8]| {
+            let kmers: HashSet<&[u8]> = segment.windows(DICT_KMER).collect();
+            kmers.iter().map(|kmer| scores[kmer]).sum::<usize>()
+        };
+        let Some(&best) = segments.iter().max_by_key(|segment| score(segment)) else {
+            break;
+        };
+        if score(best) == 0 {
+            break;
+        }
+
+        //Runs already in the dictionary don't need adding again
+        for kmer in best.windows(DICT_KMER) {
+            scores.insert(kmer, 0);
+        }
+
+        let best = &best[..best.len().min(size - len)];
+        len += best.len();
+        chosen.push(best);
+    }
+
+    chosen
+        .iter()
+        .rev()
+        .flat_map(|segment| segment.iter())
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use w4_pnger_common::{BitsPerPixel, CompType};
+    use w4_tiny_decomp::Decompressor;
+
+    use crate::{compress::Compressor, wasm4png::W4Sprite};
+
+    use super::{train_dictionary, LzComp, LzDictComp, LzParams};
+
+    #[test]
+    fn test_comp_decomp() {
+        let tile = [0x3c, 0x42, 0x81, 0x81, 0x81, 0x81, 0x42, 0x3c];
+        let mut tiles: Vec<u8> = tile.repeat(4);
+        tiles.extend((0..32u32).map(|i| (i * 151 + 7) as u8 ^ 0x5a));
+        let tiled = W4Sprite::from_packed(32, 16, BitsPerPixel::One, tiles).unwrap();
+
+        for sprite in [W4Sprite::from_path("../test.png").unwrap(), tiled] {
+            let bytes = sprite.get_bytes();
+            let mut compressed = LzComp.compress(&bytes).unwrap();
+            assert!(LzParams::from_header(&compressed.header_bytes).is_some());
+
+            let mut assembled = vec![CompType::Lz as u8];
+            assembled.append(&mut sprite.get_header_bytes());
+            assembled.append(&mut compressed.header_bytes);
+            assembled.append(&mut compressed.content_bytes);
+
+            let mut decomp_buf = vec![0u8; bytes.len()];
+            let mut decomp = Decompressor::new(&mut decomp_buf[..]);
+            let decompressed = decomp.decompress(&assembled[..]).unwrap();
+
+            assert_eq!(decompressed.bytes, bytes);
+
+            //Cut short, the data no longer covers the whole sprite
+            let mut decomp = Decompressor::new(&mut decomp_buf[..]);
+            assert!(decomp
+                .decompress(&assembled[..assembled.len() - 2])
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn test_dictionary() {
+        //Each sprite shares a tile with the others, but has no repeats of its own
+        let tile = [0x3c, 0x42, 0xa5, 0x81, 0xa5, 0x99, 0x42, 0x3c];
+        let sprites: Vec<W4Sprite> = (0..4u32)
+            .map(|s| {
+                let mut bytes: Vec<u8> = (0..24u32)
+                    .map(|i| (i * 151 + s * 37 + 7) as u8 ^ 0x5a)
+                    .collect();
+                bytes.extend(tile);
+                W4Sprite::from_packed(32, 8, BitsPerPixel::One, bytes).unwrap()
+            })
+            .collect();
+
+        let samples: Vec<Vec<u8>> = sprites.iter().map(|s| s.get_bytes()).collect();
+        let dictionary = train_dictionary(&samples, 64);
+        assert!(!dictionary.is_empty() && dictionary.len() <= 64);
+        assert!(dictionary.windows(tile.len()).any(|w| w == tile));
+
+        let comp = LzDictComp::new(dictionary);
+        for sprite in &sprites {
+            let bytes = sprite.get_bytes();
+            let mut compressed = comp.compress(&bytes).unwrap();
+            assert!(compressed.total_size < LzComp.compress(&bytes).unwrap().total_size);
+
+            let mut assembled = vec![CompType::LzDict as u8];
+            assembled.append(&mut sprite.get_header_bytes());
+            assembled.append(&mut compressed.header_bytes);
+            assembled.append(&mut compressed.content_bytes);
+
+            let mut decomp_buf = vec![0u8; bytes.len()];
+            let mut decomp = Decompressor::new(&mut decomp_buf[..]);
+            let decompressed = decomp
+                .decompress_with_dictionary(&assembled[..], comp.dictionary())
+                .unwrap();
+            assert_eq!(decompressed.bytes, bytes);
+
+            //Without the dictionary, the matches into it have nothing to copy
+            let mut decomp = Decompressor::new(&mut decomp_buf[..]);
+            assert!(decomp.decompress(&assembled[..]).is_err());
+        }
+
+        assert!(train_dictionary(&samples[..1], 64).is_empty());
+    }
+}
@@ -1,10 +1,134 @@
+pub mod huffcomp;
+pub mod lzcomp;
 pub mod pkcomp;
 
-use anyhow::Result;
+use huffcomp::HuffComp;
+use lzcomp::{LzComp, LzDictComp};
+use pkcomp::PkComp;
+
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
 use tiny_bitfiddle::{BitReader, BitVecWriter, BitWriter};
+use w4_pnger_common::CompType;
+
+//Send and Sync so sprites can be compressed on several threads at once
+pub trait Compressor: Send + Sync {
+    fn compress(&self, png: &[u8]) -> Result<CompressionResult>;
+
+    //Short name used on the command line, in manifests and in cache keys
+    fn name(&self) -> &'static str;
+
+    //Part of cache keys, so bump it whenever the bytes this produces for a sprite change, or
+    //cached results in the old encoding will keep being used
+    fn version(&self) -> u8;
+
+    //The type written at the start of .ws files compressed with this
+    fn comp_type(&self) -> CompType;
+
+    //Roughly how many bit operations it takes to decompress a result back into raw_size bytes
+    fn decode_cost(&self, result: &CompressionResult, raw_size: usize) -> usize;
+
+    //Roughly how many bytes of wasm the decompressor for this method adds to a cart. These are
+    //hand estimates rather than measurements, so only differences of a kilobyte or so mean much
+    fn decoder_size(&self) -> usize;
+}
+
+//Every compressor which works on a sprite by itself, in the order they're tried
+pub fn compressors() -> Vec<Box<dyn Compressor>> {
+    vec![Box::new(PkComp), Box::new(LzComp), Box::new(HuffComp)]
+}
 
-pub trait Compressor {
-    fn compress(&self, png: &Vec<u8>) -> Result<CompressionResult>;
+pub fn compressor_for(comp_type: CompType) -> Option<Box<dyn Compressor>> {
+    compressors()
+        .into_iter()
+        .find(|c| c.comp_type() == comp_type)
+}
+
+//How sprites should be compressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    None,
+    //Always use the compressor for this type, even if it makes the sprite bigger
+    Only(CompType),
+    //Use whichever compressor, or none, makes the smallest sprite
+    Auto,
+}
+
+impl FromStr for Method {
+    type Err = anyhow::Error;
+
+    fn from_str(from: &str) -> Result<Self> {
+        match from {
+            "none" => Ok(Method::None),
+            "auto" => Ok(Method::Auto),
+            LzDictComp::NAME => Ok(Method::Only(CompType::LzDict)),
+            _ => match compressors().iter().find(|c| c.name() == from) {
+                Some(compressor) => Ok(Method::Only(compressor.comp_type())),
+                None => bail!("Unknown compression method {from}"),
+            },
+        }
+    }
+}
+
+//`true` is the compression w4-pnger has always used
+impl From<bool> for Method {
+    fn from(compress: bool) -> Self {
+        if compress {
+            Method::Only(CompType::Pk)
+        } else {
+            Method::None
+        }
+    }
+}
+
+//Every method name, for command line help and validation
+pub fn method_names() -> Vec<&'static str> {
+    let mut names = vec!["none", "auto"];
+    names.extend(compressors().iter().map(|c| c.name()));
+    names.push(LzDictComp::NAME);
+    names
+}
+
+//Compresses raw sprite bytes as the method asks, calling compress for each of the candidates
+//tried, which are usually compressors(). Returns None when the sprite should be left uncompressed
+pub fn compress_with<F>(
+    method: Method,
+    candidates: &[Box<dyn Compressor>],
+    raw: &[u8],
+    mut compress: F,
+) -> Result<Option<(CompType, CompressionResult)>>
+where
+    F: FnMut(&dyn Compressor) -> Result<CompressionResult>,
+{
+    match method {
+        Method::None => Ok(None),
+        Method::Only(comp_type) => match candidates.iter().find(|c| c.comp_type() == comp_type) {
+            Some(compressor) => Ok(Some((comp_type, compress(&**compressor)?))),
+            None if comp_type == CompType::LzDict => {
+                bail!("lz-dict needs a dictionary, which convert --dictionary trains")
+            }
+            None => bail!("No compressor for {comp_type:?}"),
+        },
+        Method::Auto => {
+            let mut best: Option<(CompType, CompressionResult)> = None;
+
+            for compressor in candidates {
+                //Uncompressed always works, so a compressor which can't handle the sprite is skipped
+                let Ok(compressed) = compress(&**compressor) else {
+                    continue;
+                };
+                if best
+                    .as_ref()
+                    .is_none_or(|(_, b)| compressed.total_size < b.total_size)
+                {
+                    best = Some((compressor.comp_type(), compressed));
+                }
+            }
+
+            Ok(best.filter(|(_, b)| b.total_size < raw.len()))
+        }
+    }
 }
 
 pub struct CompressionResult {
@@ -15,19 +139,14 @@ pub struct CompressionResult {
     pub readable_compression_statistics: String,
 }
 
-fn delta_encode(in_bytes: &Vec<u8>, out_bytes: &mut Vec<u8>) {
+fn delta_encode(in_bytes: &[u8], out_bytes: &mut Vec<u8>) {
     let mut writer = BitVecWriter::new(out_bytes);
 
     let mut reader = BitReader::new(in_bytes);
 
     let mut last = false;
 
-    loop {
-        let b1 = match reader.read_bit() {
-            Some(b) => b,
-            None => break,
-        };
-
+    while let Some(b1) = reader.read_bit() {
         if b1 == last {
             writer.write_bit(0);
         } else {
@@ -38,12 +157,12 @@ fn delta_encode(in_bytes: &Vec<u8>, out_bytes: &mut Vec<u8>) {
     }
 }
 
-pub fn delta_encode_by_jump(in_bytes: &Vec<u8>, out_bytes: &mut Vec<u8>, jump: usize) {
+pub fn delta_encode_by_jump(in_bytes: &[u8], out_bytes: &mut Vec<u8>, jump: usize) {
     let mut writer = BitVecWriter::new(out_bytes);
 
     let mut reader = BitReader::new(in_bytes);
 
-    for _ in 0..jump {
+    for _ in 0..jump.min(in_bytes.len() * 8) {
         writer.write_bit(reader.read_bit().unwrap() as u8);
     }
 
@@ -60,19 +179,16 @@ pub fn delta_encode_by_jump(in_bytes: &Vec<u8>, out_bytes: &mut Vec<u8>, jump: u
     }
 }
 
-fn split_bitplanes(in_bytes: &Vec<u8>, out_left: &mut Vec<u8>, out_right: &mut Vec<u8>) {
+fn split_bitplanes(in_bytes: &[u8], out_left: &mut Vec<u8>, out_right: &mut Vec<u8>) {
     let mut writer_1 = BitVecWriter::new(out_left);
     let mut writer_2 = BitVecWriter::new(out_right);
 
-    let mut reader = BitReader::new(&in_bytes);
+    let mut reader = BitReader::new(in_bytes);
 
     let mut write_1 = true;
 
-    loop {
-        let b1 = match reader.read_bit() {
-            Some(b) => b,
-            None => break,
-        } as u8;
+    while let Some(b1) = reader.read_bit() {
+        let b1 = b1 as u8;
 
         if write_1 {
             writer_1.write_bit(b1);
@@ -84,8 +200,58 @@ fn split_bitplanes(in_bytes: &Vec<u8>, out_left: &mut Vec<u8>, out_right: &mut V
     }
 }
 
-fn xor_bitplanes(bp1: &Vec<u8>, bp2: &mut Vec<u8>) {
+pub(crate) fn xor_bitplanes(bp1: &[u8], bp2: &mut [u8]) {
     for (b1, b2) in bp1.iter().zip(bp2.iter_mut()) {
-        *b2 = *b1 ^ *b2;
+        *b2 ^= *b1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use w4_pnger_common::CompType;
+
+    use super::{compress_with, compressors, Method};
+
+    #[test]
+    fn test_compress_with() {
+        let solid = [0u8; 32];
+        let noise: Vec<u8> = (0..32u32).map(|i| (i * 151 + 7) as u8 ^ 0x5a).collect();
+
+        let (_, compressed) =
+            compress_with(Method::Auto, &compressors(), &solid, |c| c.compress(&solid))
+                .unwrap()
+                .unwrap();
+        let (comp_type, pk) =
+            compress_with(Method::Only(CompType::Pk), &compressors(), &solid, |c| {
+                c.compress(&solid)
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(comp_type, CompType::Pk);
+        assert!(compressed.total_size <= pk.total_size);
+        assert!(compressed.total_size < solid.len());
+
+        //Compression would only make noise bigger
+        assert!(
+            compress_with(Method::Auto, &compressors(), &noise, |c| c.compress(&noise))
+                .unwrap()
+                .is_none()
+        );
+        let (_, compressed) =
+            compress_with(Method::Only(CompType::Pk), &compressors(), &noise, |c| {
+                c.compress(&noise)
+            })
+            .unwrap()
+            .unwrap();
+        assert!(compressed.total_size >= noise.len());
+
+        assert!(
+            compress_with(Method::None, &compressors(), &solid, |c| c.compress(&solid))
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!("auto".parse::<Method>().unwrap(), Method::Auto);
+        assert_eq!("pk".parse::<Method>().unwrap(), Method::Only(CompType::Pk));
+        assert!("zip".parse::<Method>().is_err());
     }
 }
@@ -5,13 +5,19 @@ use super::{
     Compressor,
 };
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use serde::Serialize;
 use tiny_bitfiddle::{BitReader, BitVecWriter, BitWriter};
+use w4_pnger_common::CompType;
 
 pub struct PkComp;
 
 impl Compressor for PkComp {
-    fn compress(&self, png: &Vec<u8>) -> Result<CompressionResult> {
+    fn compress(&self, png: &[u8]) -> Result<CompressionResult> {
+        if png.is_empty() {
+            bail!("Sprite has no pixel data to compress");
+        }
+
         let mut best = None;
         let mut best_score = usize::MAX;
 
@@ -21,7 +27,10 @@ impl Compressor for PkComp {
         let mut best_jump = 0;
         let mut best_jump_size = 0;
 
-        for split in 0..=1 {
+        //Splitting needs each bitplane to be whole bytes
+        let max_split = if png.len().is_multiple_of(2) { 1 } else { 0 };
+
+        for split in 0..=max_split {
             for xor in 0..=split {
                 for seq_delta in 0..=4 {
                     for jump_delta in 0..=2 {
@@ -50,11 +59,14 @@ impl Compressor for PkComp {
             }
         }
 
-        let mut out_header = vec![0, best_jump_size as u8];
-        out_header[0] |= if best_split { 1 << 0 } else { 0 };
-        out_header[0] |= if best_xor { 1 << 1 } else { 0 };
-        out_header[0] |= (best_seq as u8) << 2;
-        out_header[0] |= (best_jump as u8) << 6;
+        let out_header = PkParams {
+            split: best_split,
+            xor: best_xor,
+            seq_delta: best_seq as u8,
+            jump_delta: best_jump as u8,
+            jump_size: best_jump_size as u8,
+        }
+        .to_header();
         let out_content = best.unwrap();
 
         let len = out_content.len() + out_header.len();
@@ -68,17 +80,90 @@ impl Compressor for PkComp {
                 jump delta-encoded {best_jump} times with {best_jump_size} pixel jump").to_owned()
         })
     }
+
+    fn name(&self) -> &'static str {
+        "pk"
+    }
+
+    fn version(&self) -> u8 {
+        //2 fixed streams the decompressor couldn't reproduce
+        2
+    }
+
+    fn comp_type(&self) -> CompType {
+        CompType::Pk
+    }
+
+    fn decode_cost(&self, result: &CompressionResult, raw_size: usize) -> usize {
+        let bits = raw_size * 8;
+        let Some(params) = PkParams::from_header(&result.header_bytes) else {
+            return bits;
+        };
+
+        //Every bit is written once, then each delta pass reads and rewrites every bit
+        let mut cost = bits * (1 + params.seq_delta as usize + params.jump_delta as usize);
+        if params.xor {
+            cost += raw_size / 2;
+        }
+        if params.split {
+            //The in-place in-shuffle moves each bit about three times
+            cost += bits * 3;
+        }
+
+        cost
+    }
+
+    fn decoder_size(&self) -> usize {
+        //Estimated from the amount of code in pkdecomp and the bitplane and delta passes it
+        //runs, not measured
+        2300
+    }
+}
+
+//The parameters PkComp chose for a sprite, as stored in its compression header
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct PkParams {
+    pub split: bool,
+    pub xor: bool,
+    pub seq_delta: u8,
+    pub jump_delta: u8,
+    pub jump_size: u8,
+}
+
+impl PkParams {
+    pub fn from_header(header: &[u8]) -> Option<Self> {
+        match header {
+            [params, jump_size, ..] => Some(PkParams {
+                split: params & (1 << 0) != 0,
+                xor: params & (1 << 1) != 0,
+                seq_delta: (params & 0b00111100) >> 2,
+                jump_delta: (params & 0b11000000) >> 6,
+                jump_size: *jump_size,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_header(&self) -> Vec<u8> {
+        let mut params = 0;
+        params |= if self.split { 1 << 0 } else { 0 };
+        params |= if self.xor { 1 << 1 } else { 0 };
+        params |= self.seq_delta << 2;
+        params |= self.jump_delta << 6;
+
+        vec![params, self.jump_size]
+    }
 }
 
 fn compress_for(
-    png: &Vec<u8>,
+    png: &[u8],
     do_split_bitplanes: bool,
     do_xor_bitplanes: bool,
     seq_delta_encode: usize,
     jump_delta_encode: usize,
     jump_delta_encode_size: usize,
 ) -> Vec<u8> {
-    let mut cloned = png.clone();
+    let mut cloned = png.to_vec();
 
     let mut out_vec = Vec::new();
 
@@ -122,16 +207,16 @@ fn compress_bytes(
 ) -> Vec<u8> {
     let mut double_buffer = Vec::with_capacity(bytes.len());
     for _ in 0..seq_delta_encode {
-        delta_encode(&bytes, &mut double_buffer);
+        delta_encode(bytes, &mut double_buffer);
         std::mem::swap(bytes, &mut double_buffer);
     }
 
     for _ in 0..jump_delta_encode {
-        delta_encode_by_jump(&bytes, &mut double_buffer, jump_delta_encode_size);
+        delta_encode_by_jump(bytes, &mut double_buffer, jump_delta_encode_size);
         std::mem::swap(bytes, &mut double_buffer);
     }
 
-    let mut reader = BitReader::new(&bytes);
+    let mut reader = BitReader::new(bytes);
 
     let mut out_vec: Vec<u8> = Vec::new();
     let mut writer = BitVecWriter::new(&mut out_vec);
@@ -154,15 +239,22 @@ fn compress_bytes(
         let b1 = match reader.read_bit() {
             Some(b) => b,
             None => {
+                //A lone pair still needs its marker, the same as when zeroes follow it
+                if let State::Root(lb1, lb2, 1) = state {
+                    if (lb1, lb2) == (true, true) {
+                        writer.write_bit(1);
+                    } else {
+                        writer.write_bit(0);
+                        writer.write_bit(lb1 as u8);
+                        break;
+                    }
+                }
                 write(state, &mut writer);
                 break;
             } //Found end-of-stream
         };
 
-        let b2 = match reader.read_bit() {
-            Some(b) => b,
-            None => false, //Odd number of pairs, fill in 0 for the last bit
-        };
+        let b2 = reader.read_bit().unwrap_or_default(); //Odd number of pairs, fill in 0 for the last bit
 
         state = match state {
             State::Zeroes(n) => match (b1, b2) {
@@ -216,8 +308,8 @@ fn write(prev_state: State, writer: &mut BitVecWriter) {
             writer.write(v as u32, hb - 1);
         }
         State::Root(b1, b2, _) => {
-            _ = writer.write_bit(b1 as u8);
-            _ = writer.write_bit(b2 as u8);
+            writer.write_bit(b1 as u8);
+            writer.write_bit(b2 as u8);
         }
     }
 }
@@ -225,7 +317,7 @@ fn write(prev_state: State, writer: &mut BitVecWriter) {
 fn highest_bit(mut n: usize) -> usize {
     let mut bit: usize = 0;
     while n > 0 {
-        n = n >> 1;
+        n >>= 1;
         bit += 1;
     }
 
@@ -253,7 +345,7 @@ mod tests {
 
         let mut png_s = PngStream::new("../test.png");
 
-        let (name, mut reader) = png_s.next().unwrap().unwrap();
+        let (_name, mut reader) = png_s.next().unwrap().unwrap();
 
         let w4sprite = W4Sprite::from_reader(&mut reader).unwrap();
 
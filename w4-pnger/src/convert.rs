@@ -1,109 +1,709 @@
-use png::Reader;
+use anyhow::Context;
+use glob::{glob, GlobError};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
 use w4_pnger_common::CompType;
 
-use crate::compress::pkcomp::PkComp;
-use crate::compress::Compressor;
-use crate::pngstream::PngStream;
-use crate::wasm4png::W4Sprite;
+use crate::aseprite::{is_aseprite, AsepriteFile};
+use crate::budget::report_budget;
+use crate::bundle::{data_start, sprite_offsets, write_bundle};
+use crate::cache::Cache;
+use crate::compress::lzcomp::{train_dictionary, LzDictComp};
+use crate::compress::{
+    compress_with, compressors, xor_bitplanes, CompressionResult, Compressor, Method,
+};
+use crate::index::{write_index, IndexSource};
+use crate::jobs::{run_ordered, Log};
+use crate::pngstream::open_png;
+use crate::slice::Slicer;
+use crate::source::{self, Language};
+use crate::template::Template;
+use crate::wasm4png::{IndexedSprite, W4Sprite};
 
 pub struct Converter<'a> {
     path: &'a str,
     name: &'a str,
     out_type: OutputType,
-    compress: bool,
+    compress: Method,
+    index: Option<&'a str>,
+    package: &'a str,
+    watch: bool,
+    palette: Option<Vec<[u8; 4]>>,
+    cache: Option<Cache>,
+    jobs: usize,
+    budget: Option<usize>,
+    dictionary_size: Option<usize>,
+    animation: bool,
+    slice: Option<Slicer>,
+    //The dictionary trained by the last update, empty if the sprites are better off without one
+    dictionary: Mutex<Vec<u8>>,
+    //Sprites which couldn't be loaded, converted or written during the last update
+    failures: AtomicUsize,
+}
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+type NamedSprites = Vec<(String, W4Sprite)>;
+type IndexedSprites = Vec<(String, IndexedSprite)>;
+
+struct WatchedFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    //One sprite, or every sprite cut from a sheet
+    sprites: Vec<ConvertedSprite>,
+}
+
+fn sprites_of(watched: &[WatchedFile]) -> Vec<ConvertedSprite> {
+    watched.iter().flat_map(|f| f.sprites.clone()).collect()
 }
 
 impl<'a> Converter<'a> {
-    pub fn new(path: &'a str, name: &'a str, out_type: OutputType, compress: bool) -> Self {
+    //compress is either a Method or a bool, where true means pk compression
+    pub fn new(
+        path: &'a str,
+        name: &'a str,
+        out_type: OutputType,
+        compress: impl Into<Method>,
+    ) -> Self {
         Converter {
             path,
             name,
             out_type,
-            compress,
+            compress: compress.into(),
+            index: None,
+            package: source::DEFAULT_PACKAGE,
+            watch: false,
+            palette: None,
+            cache: None,
+            jobs: 1,
+            budget: None,
+            dictionary_size: None,
+            animation: false,
+            slice: None,
+            dictionary: Mutex::new(Vec::new()),
+            failures: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn with_palette(mut self, palette: Option<Vec<[u8; 4]>>) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    //Converts up to this many sprites at once
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    //Reports how much of this many bytes the sprites take up, and whether they fit
+    pub fn with_budget(mut self, budget: Option<usize>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    //Trains a dictionary of up to this many bytes from all of the sprites, which is stored once in
+    //the bundle for lz-dict to compress against. Every sprite is reconverted whenever one changes,
+    //and the dictionary is left out if it costs more than it saves
+    pub fn with_dictionary(mut self, dictionary_size: Option<usize>) -> Self {
+        self.dictionary_size = dictionary_size;
+        self
+    }
+
+    //Treats the sprites as the frames of an animation, in pattern order, storing each frame after
+    //the first as its XOR with the frame before, which is mostly zeroes and compresses well
+    pub fn with_animation(mut self, animation: bool) -> Self {
+        self.animation = animation;
+        self
+    }
+
+    //Cuts every file into a grid of sprites, each converted as if it were a file of its own
+    pub fn with_slice(mut self, slice: Option<Slicer>) -> Self {
+        self.slice = slice;
+        self
+    }
+
+    //Reuses compression results from earlier runs for sprites which haven't changed
+    pub fn with_cache(mut self, cache: Option<Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    //Keeps running after the first pass, reconverting sprites whenever their files change
+    pub fn with_watch(mut self, watch: bool) -> Self {
+        self.watch = watch;
+        self
+    }
+
+    pub fn with_index(mut self, index: Option<&'a str>) -> Self {
+        self.index = index;
+        self
+    }
+
+    //The package to put Go and Odin output in, main unless given
+    pub fn with_package(mut self, package: Option<&'a str>) -> Self {
+        self.package = package.unwrap_or(source::DEFAULT_PACKAGE);
+        self
+    }
+
+    //Returns false if the template couldn't be loaded, any sprite or output failed, or the sprites
+    //are over budget
+    pub fn run(self) -> bool {
+        let template = match &self.out_type {
+            OutputType::Template(template_path) => match load_template(template_path) {
+                Ok(template) => Some(template),
+                Err(e) => {
+                    eprintln!("Failed to load template {template_path}: {e}");
+                    return false;
+                }
+            },
+            _ => None,
+        };
+
+        let mut watched = self.update(Vec::new()).0;
+        let sprites = sprites_of(&watched);
+        let mut succeeded = self.report_failures();
+        if let Err(e) = self.write_outputs(&sprites, template.as_ref()) {
+            eprintln!("{e:#}");
+            succeeded = false;
+        }
+        let fits = self.check_budget(&sprites);
+
+        if !self.watch {
+            return succeeded && fits;
+        }
+
+        println!("Watching {} for changes...", self.path);
+
+        loop {
+            std::thread::sleep(WATCH_INTERVAL);
+
+            let (updated, changed) = self.update(watched);
+            watched = updated;
+
+            if changed {
+                let sprites = sprites_of(&watched);
+                self.report_failures();
+                match self.write_outputs(&sprites, template.as_ref()) {
+                    Ok(()) => println!("Updated outputs with {} sprites", sprites.len()),
+                    Err(e) => eprintln!("{e:#}"),
+                }
+                self.check_budget(&sprites);
+            }
+        }
+    }
+
+    //Converts every matching file without writing any combined outputs, failing if any sprite
+    //couldn't be converted
+    pub fn convert(&self) -> anyhow::Result<Vec<ConvertedSprite>> {
+        let sprites = sprites_of(&self.update(Vec::new()).0);
+
+        match self.failures.load(Ordering::Relaxed) {
+            0 => Ok(sprites),
+            1 => anyhow::bail!("A sprite in {} failed to convert", self.path),
+            failures => anyhow::bail!("{failures} sprites in {} failed to convert", self.path),
         }
     }
 
-    pub fn run(self) {
-        let stream = PngStream::new(self.path);
+    //Writes the combined outputs, such as source files and bundles, for already converted sprites
+    pub fn write(&self, sprites: &[ConvertedSprite]) -> anyhow::Result<()> {
+        let template = match &self.out_type {
+            OutputType::Template(template_path) => Some(load_template(template_path)?),
+            _ => None,
+        };
+
+        self.write_outputs(sprites, template.as_ref())
+    }
+
+    //The bytes the sprites take up once written, for checking against a budget
+    pub fn written_sizes(&self, sprites: &[ConvertedSprite]) -> Vec<(String, usize)> {
+        let dictionary_len = self.dictionary.lock().expect("a job panicked").len();
+        self.out_type.written_sizes(sprites, dictionary_len)
+    }
 
-        for png_res in stream {
-            match png_res {
-                Ok((name, png)) => self.process_png(name, png),
-                Err(e) => eprintln!("{}, continuing with other files", e),
+    //Prints how many sprites failed in the last update, returning true if none did
+    fn report_failures(&self) -> bool {
+        match self.failures.load(Ordering::Relaxed) {
+            0 => true,
+            1 => {
+                eprintln!("A sprite failed, see above");
+                false
+            }
+            failures => {
+                eprintln!("{failures} sprites failed, see above");
+                false
             }
         }
     }
 
-    fn process_png(&self, image_name: String, png_reader: Reader<File>) {
-        let mut png_reader = png_reader;
-        match W4Sprite::from_reader(&mut png_reader) {
-            Ok(png) => {
-                let png_bytes = png.get_bytes();
+    fn fail(&self, log: &mut Log, message: String) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+        log.err(message);
+    }
+
+    fn check_budget(&self, sprites: &[ConvertedSprite]) -> bool {
+        let Some(budget) = self.budget else {
+            return true;
+        };
+
+        match report_budget(
+            &mut std::io::stdout().lock(),
+            budget,
+            &self.written_sizes(sprites),
+        ) {
+            Ok(fits) => fits,
+            Err(e) => {
+                eprintln!("Failed to report budget: {e}");
+                false
+            }
+        }
+    }
 
-                let out_bytes = if self.compress {
-                    let compressor = PkComp {};
-                    let mut compressed = compressor.compress(&png_bytes).unwrap();
+    //Converts every file matching the pattern that's new or modified since the last update.
+    //Returns the files in pattern order, and whether anything was added, changed or removed
+    fn update(&self, previous: Vec<WatchedFile>) -> (Vec<WatchedFile>, bool) {
+        self.failures.store(0, Ordering::Relaxed);
+        let mut previous: HashMap<PathBuf, WatchedFile> = previous
+            .into_iter()
+            .map(|file| (file.path.clone(), file))
+            .collect();
+        let mut watched = Vec::new();
+        let mut to_convert = Vec::new();
 
-                    println!(
-                        "Compressed {} with {}, from {} bytes to {} bytes, ({:04.2} %)",
-                        image_name,
-                        &compressed.readable_compression_name,
-                        png_bytes.len(),
-                        compressed.total_size,
-                        (png_bytes.len() as f32 / compressed.total_size as f32) * 100.0
+        for path_res in glob(self.path).expect("Must be a valid pattern") {
+            let path = match path_res {
+                Ok(path) => path,
+                Err(e) => {
+                    self.fail(
+                        &mut Log::default(),
+                        format!("{e}, continuing with other files"),
                     );
+                    continue;
+                }
+            };
 
-                    let mut out = vec![CompType::Pk as u8];
-                    out.append(&mut png.get_header_bytes());
-                    out.append(&mut compressed.header_bytes);
-                    out.append(&mut compressed.content_bytes);
-                    out
-                } else {
-                    let mut out = vec![CompType::Uncompressed as u8];
-                    out.append(&mut png.get_header_bytes());
-                    out.append(&mut png_bytes.clone());
-                    out
-                };
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+
+            match previous.remove(&path) {
+                Some(file) if modified.is_some() && file.modified == modified => watched.push(file),
+                _ => {
+                    to_convert.push((watched.len(), path.clone()));
+                    watched.push(WatchedFile {
+                        path,
+                        modified,
+                        sprites: Vec::new(),
+                    });
+                }
+            }
+        }
+
+        let changed = !to_convert.is_empty() || !previous.is_empty();
+
+        //Any change can change the dictionary, or the changes to the next frame of an animation,
+        //so every sprite has to be reconverted
+        let whole_set = self.dictionary_size.is_some() || self.animation;
+        let files: Vec<(usize, PathBuf)> = if whole_set && changed {
+            watched
+                .iter()
+                .enumerate()
+                .map(|(slot, file)| (slot, file.path.clone()))
+                .collect()
+        } else {
+            to_convert
+        };
+        if files.is_empty() {
+            return (watched, changed);
+        }
+
+        for (slot, _) in &files {
+            watched[*slot].sprites.clear();
+        }
+
+        let mut loaded = self.load_all(files);
+        if self.animation {
+            let frames = loaded.len();
+            loaded = frame_changes(loaded);
+            self.failures
+                .fetch_add(frames - loaded.len(), Ordering::Relaxed);
+        }
+
+        let converted = match self.dictionary_size {
+            Some(size) => self.convert_with_dictionary(loaded, size),
+            None => run_ordered(self.jobs, loaded, |(slot, name, sprite)| {
+                let mut log = Log::default();
+                let sprite = self.process_sprite(name, sprite, &compressors(), &mut log);
+
+                ((slot, sprite), log)
+            }),
+        };
+
+        for (slot, sprite) in converted {
+            watched[slot].sprites.extend(sprite);
+        }
 
-                match self.out_type {
-                    OutputType::Raw => {
-                        let out_name = self.name.to_owned() + "_" + &image_name + ".ws";
+        (watched, changed)
+    }
+
+    //Loads the sprites in every file without converting them yet, tagged with each file's slot
+    fn load_all(&self, files: Vec<(usize, PathBuf)>) -> Vec<(usize, String, W4Sprite)> {
+        let loaded = run_ordered(self.jobs, files, |(slot, path)| {
+            let mut log = Log::default();
+            let sprites = self.load_sprites(&path, &mut log);
+
+            ((slot, sprites), log)
+        });
+
+        loaded
+            .into_iter()
+            .flat_map(|(slot, sprites)| {
+                sprites
+                    .into_iter()
+                    .map(move |(name, sprite)| (slot, name, sprite))
+            })
+            .collect()
+    }
+
+    //Trains a dictionary from every sprite, then converts them both with and without it, keeping
+    //whichever makes the bundle smaller
+    fn convert_with_dictionary(
+        &self,
+        loaded: Vec<(usize, String, W4Sprite)>,
+        size: usize,
+    ) -> Vec<(usize, Option<ConvertedSprite>)> {
+        let samples: Vec<Vec<u8>> = loaded.iter().map(|(_, _, s)| s.get_bytes()).collect();
+        let dictionary = train_dictionary(&samples, size);
+
+        let with_dictionary = {
+            let mut candidates = compressors();
+            candidates.push(Box::new(LzDictComp::new(dictionary.clone())));
+            candidates
+        };
+        let forced = self.compress == Method::Only(CompType::LzDict);
+
+        //Logs are held back until it's known which conversion is kept
+        let converted = run_ordered(self.jobs, loaded, |(slot, name, sprite)| {
+            let mut with_log = Log::default();
+            let with = self.process_sprite(
+                name.clone(),
+                sprite.clone(),
+                &with_dictionary,
+                &mut with_log,
+            );
+
+            let mut without_log = Log::default();
+            let without = if forced {
+                None
+            } else {
+                self.process_sprite(name, sprite, &compressors(), &mut without_log)
+            };
+
+            (
+                (slot, (with, with_log), (without, without_log)),
+                Log::default(),
+            )
+        });
+
+        let mut total_with = 0;
+        let mut total_without = 0;
+        let mut uses_dictionary = false;
+        for (_, (with, _), (without, _)) in &converted {
+            if let Some(with) = with {
+                total_with += with.bytes.len();
+                uses_dictionary |= with.comp_type == CompType::LzDict;
+            }
+            total_without += without.as_ref().map_or(0, |s| s.bytes.len());
+        }
+
+        //The bundle stores the dictionary's length as well as the dictionary itself
+        let total_with = total_with + 2 + dictionary.len();
+        let keep = forced || (uses_dictionary && total_with < total_without);
+
+        if keep && forced {
+            println!("Trained a {} byte dictionary", dictionary.len());
+        } else if keep {
+            println!(
+                "Trained a {} byte dictionary, which saves {} bytes",
+                dictionary.len(),
+                total_without - total_with
+            );
+        } else {
+            println!("Left out the dictionary, as it costs more than it saves");
+        }
+
+        let kept = converted
+            .into_iter()
+            .map(|(slot, with, without)| {
+                let (sprite, log) = if keep { with } else { without };
+                log.print();
+                (slot, sprite)
+            })
+            .collect();
+
+        *self.dictionary.lock().expect("a job panicked") =
+            if keep { dictionary } else { Vec::new() };
 
-                        let file = open_output_file(&out_name);
+        kept
+    }
+
+    fn write_outputs(
+        &self,
+        sprites: &[ConvertedSprite],
+        template: Option<&Template>,
+    ) -> anyhow::Result<()> {
+        if let OutputType::Source(language) = self.out_type {
+            let out_name = self.name.to_owned() + "." + language.extension();
+
+            write_output_file(&out_name, |out| {
+                source::write_source(out, language, self.package, sprites)
+            })?;
+        }
+
+        if let OutputType::Bundle { name_hashes } = self.out_type {
+            let dictionary = self.dictionary.lock().expect("a job panicked");
+            let bundle = write_bundle(sprites, name_hashes, &dictionary, self.animation)
+                .context("Failed to build bundle")?;
+            std::fs::write(self.name, bundle)
+                .with_context(|| format!("Failed to write {}", self.name))?;
+        }
 
-                        match file {
-                            Ok(mut f) => {
-                                _ = f.write_all(&out_bytes);
+        if let Some(index) = self.index {
+            self.write_index(index, sprites)
+                .with_context(|| format!("Failed to write {index}"))?;
+        }
+
+        if let Some(template) = template {
+            write_output_file(self.name, |out| {
+                out.write_all(template.render(sprites)?.as_bytes())?;
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn write_index(&self, index: &str, sprites: &[ConvertedSprite]) -> anyhow::Result<()> {
+        let (source, offsets) = match self.out_type {
+            OutputType::Bundle { name_hashes } => (
+                IndexSource::Bundle(Path::new(self.name)),
+                sprite_offsets(
+                    sprites,
+                    name_hashes,
+                    self.dictionary.lock().expect("a job panicked").len(),
+                ),
+            ),
+            OutputType::Raw => (
+                IndexSource::Files(
+                    sprites
+                        .iter()
+                        .map(|s| PathBuf::from(self.raw_file_name(&s.name)))
+                        .collect(),
+                ),
+                vec![0; sprites.len()],
+            ),
+            _ => anyhow::bail!("Sprite indices need either --raw or --bundle output"),
+        };
+
+        let mut buf_write = BufWriter::new(open_output_file(index)?);
+        write_index(&mut buf_write, Path::new(index), &source, sprites, &offsets)
+    }
+
+    fn raw_file_name(&self, image_name: &str) -> String {
+        self.name.to_owned() + "_" + image_name + ".ws"
+    }
+
+    //Loads the sprites in a file, cutting each into slices when slicing sheets
+    fn load_sprites(&self, path: &Path, log: &mut Log) -> NamedSprites {
+        match load_file(path, self.palette.as_deref()) {
+            Ok((_, Ok(sprites))) => {
+                //Sheets are sliced before packing, as packed rows don't always start on a byte
+                let sprites = match &self.slice {
+                    Some(slice) => {
+                        let mut sliced = Vec::new();
+                        for (name, sprite) in sprites {
+                            match slice.slice(&name, &sprite) {
+                                Ok(sprites) => sliced.extend(sprites),
+                                Err(e) => self.fail(
+                                    log,
+                                    format!("Encountered error slicing sheet {name}: {e}"),
+                                ),
                             }
-                            Err(e) => eprint!("{}", e),
                         }
+                        sliced
                     }
-                    OutputType::Text => {
-                        let out_name = self.name.to_owned() + "_" + &image_name + ".txt";
-
-                        let file = open_output_file(&out_name);
-
-                        match file {
-                            Ok(f) => {
-                                let mut buf_write = BufWriter::new(f);
-
-                                _ = buf_write.write(
-                                    format!(
-                                        "width: {}\nheight: {}\nbpp: {}\ndata: ",
-                                        png.width, png.height, png.bpp as u8
-                                    )
-                                    .as_bytes(),
-                                );
-                                _ = buf_write.write(format!("{:02X?}", &out_bytes).as_bytes());
-                            }
-                            Err(e) => eprint!("{}", e),
-                        }
+                    None => sprites,
+                };
+                sprites
+                    .into_iter()
+                    .map(|(name, sprite)| (name, sprite.pack()))
+                    .collect()
+            }
+            Ok((name, Err(e))) => {
+                self.fail(
+                    log,
+                    format!("Encountered error processing sprite {name}: {e}"),
+                );
+                Vec::new()
+            }
+            Err(e) => {
+                self.fail(log, format!("{}, continuing with other files", e));
+                Vec::new()
+            }
+        }
+    }
+
+    fn process_sprite(
+        &self,
+        image_name: String,
+        png: W4Sprite,
+        candidates: &[Box<dyn Compressor>],
+        log: &mut Log,
+    ) -> Option<ConvertedSprite> {
+        let png_bytes = png.get_bytes();
+
+        let mut cached = false;
+        let compressed =
+            compress_with(
+                self.compress,
+                candidates,
+                &png_bytes,
+                |compressor| match &self.cache {
+                    //The dictionary changes whenever any sprite does, so isn't worth caching
+                    Some(_) if compressor.comp_type() == CompType::LzDict => {
+                        compressor.compress(&png_bytes)
                     }
+                    Some(cache) => {
+                        let key = Cache::key(compressor, &png.get_header_bytes(), &png_bytes);
+                        let (compressed, hit) =
+                            cache.get_or_compress(&key, || compressor.compress(&png_bytes))?;
+                        cached |= hit;
+                        Ok(compressed)
+                    }
+                    None => compressor.compress(&png_bytes),
+                },
+            );
+        let compressed = match compressed {
+            Ok(compressed) => compressed,
+            Err(e) => {
+                self.fail(
+                    log,
+                    format!("Error encountered compressing sprite {image_name}: {e}"),
+                );
+                return None;
+            }
+        };
+        let cached = if cached { " (cached)" } else { "" };
+
+        let converted = match compressed {
+            Some((comp_type, compressed)) => {
+                log.out(format!(
+                    "Compressed {} with {}, from {} bytes to {} bytes, ({:04.2} %){cached}",
+                    image_name,
+                    &compressed.readable_compression_name,
+                    png_bytes.len(),
+                    compressed.total_size,
+                    (png_bytes.len() as f32 / compressed.total_size as f32) * 100.0,
+                ));
+
+                ConvertedSprite::compressed(image_name, &png, comp_type, compressed)
+            }
+            None => {
+                if self.compress == Method::Auto {
+                    log.out(format!(
+                        "Left {image_name} uncompressed, as no compressor makes it smaller{cached}"
+                    ));
                 }
+                ConvertedSprite::uncompressed(image_name, &png)
+            }
+        };
+        let out_bytes = &converted.bytes;
+        let image_name = &converted.name;
+
+        let written = match self.out_type {
+            OutputType::Raw => write_output_file(&self.raw_file_name(image_name), |out| {
+                out.write_all(out_bytes)?;
+                Ok(())
+            }),
+            OutputType::Text => {
+                let out_name = self.name.to_owned() + "_" + image_name + ".txt";
+
+                write_output_file(&out_name, |out| {
+                    write!(
+                        out,
+                        "width: {}\nheight: {}\nbpp: {}\ndata: {:02X?}",
+                        png.width, png.height, png.bpp as u8, out_bytes
+                    )?;
+                    Ok(())
+                })
             }
-            Err(e) => eprintln!("Encountered error processing sprite {image_name}: {e}"),
+            OutputType::Source(_) | OutputType::Template(_) | OutputType::Bundle { .. } => Ok(()),
+        };
+        if let Err(e) = written {
+            self.fail(log, format!("{e:#}"));
+        }
+
+        Some(converted)
+    }
+}
+
+#[derive(Clone)]
+pub struct ConvertedSprite {
+    pub name: String,
+    pub width: u16,
+    pub height: u16,
+    pub flags: u8,
+    pub comp_type: CompType,
+    pub bytes: Vec<u8>,
+}
+
+impl ConvertedSprite {
+    pub fn uncompressed(name: String, sprite: &W4Sprite) -> Self {
+        let mut bytes = vec![CompType::Uncompressed as u8];
+        bytes.append(&mut sprite.get_header_bytes());
+        bytes.append(&mut sprite.get_bytes());
+
+        Self::from_parts(name, sprite, CompType::Uncompressed, bytes)
+    }
+
+    pub fn compressed(
+        name: String,
+        sprite: &W4Sprite,
+        comp_type: CompType,
+        compressed: CompressionResult,
+    ) -> Self {
+        let mut compressed = compressed;
+
+        let mut bytes = vec![comp_type as u8];
+        bytes.append(&mut sprite.get_header_bytes());
+        bytes.append(&mut compressed.header_bytes);
+        bytes.append(&mut compressed.content_bytes);
+
+        Self::from_parts(name, sprite, comp_type, bytes)
+    }
+
+    //What source and template output hold for the sprite. Uncompressed sprites are plain pixel
+    //data which can be passed straight to blit, like png2src writes, while compressed sprites
+    //keep their .ws header for the decompressor
+    pub fn data(&self) -> &[u8] {
+        match self.comp_type {
+            CompType::Uncompressed => &self.bytes[4..],
+            _ => &self.bytes,
+        }
+    }
+
+    fn from_parts(name: String, sprite: &W4Sprite, comp_type: CompType, bytes: Vec<u8>) -> Self {
+        ConvertedSprite {
+            name,
+            width: sprite.width,
+            height: sprite.height,
+            flags: sprite.bpp.get_flags(),
+            comp_type,
+            bytes,
         }
     }
 }
@@ -111,22 +711,240 @@ impl<'a> Converter<'a> {
 pub enum OutputType {
     Raw,
     Text,
+    Source(Language),
+    Template(String),
+    Bundle { name_hashes: bool },
 }
 
 impl OutputType {
-    pub fn from_str(from: &str) -> OutputType {
+    //The bytes each sprite takes up in this output, which is what ends up in the cart. Bundles
+    //add their header, offset table and name hashes, plus the dictionary when there is one
+    pub fn written_sizes(
+        &self,
+        sprites: &[ConvertedSprite],
+        dictionary_len: usize,
+    ) -> Vec<(String, usize)> {
+        let mut sizes: Vec<(String, usize)> = sprites
+            .iter()
+            .map(|s| match self {
+                //Source code holds uncompressed sprites without their .ws header
+                OutputType::Source(_) | OutputType::Template(_) => (s.name.clone(), s.data().len()),
+                OutputType::Raw | OutputType::Text | OutputType::Bundle { .. } => {
+                    (s.name.clone(), s.bytes.len())
+                }
+            })
+            .collect();
+
+        if let OutputType::Bundle { name_hashes } = *self {
+            sizes.push((
+                "(bundle header)".to_owned(),
+                data_start(sprites.len(), name_hashes, 0),
+            ));
+            if dictionary_len > 0 {
+                sizes.push(("(dictionary)".to_owned(), 2 + dictionary_len));
+            }
+        }
+
+        sizes
+    }
+}
+
+impl FromStr for OutputType {
+    type Err = anyhow::Error;
+
+    fn from_str(from: &str) -> anyhow::Result<Self> {
         match from {
-            "raw" => OutputType::Raw,
-            "text" => OutputType::Text,
-            _ => panic!("Invalid output type"),
+            "raw" => Ok(OutputType::Raw),
+            "text" => Ok(OutputType::Text),
+            "rs" => Ok(OutputType::Source(Language::Rust)),
+            "c" => Ok(OutputType::Source(Language::C)),
+            "zig" => Ok(OutputType::Source(Language::Zig)),
+            "as" => Ok(OutputType::Source(Language::AssemblyScript)),
+            "go" => Ok(OutputType::Source(Language::Go)),
+            "odin" => Ok(OutputType::Source(Language::Odin)),
+            _ => anyhow::bail!("Invalid output type {from}"),
         }
     }
 }
 
+//Replaces every frame after the first with its XOR against the frame before it. Frames which
+//don't match the first frame's size are left out, with the next frame following on from the
+//last one kept
+fn frame_changes(frames: Vec<(usize, String, W4Sprite)>) -> Vec<(usize, String, W4Sprite)> {
+    let mut previous: Option<W4Sprite> = None;
+
+    frames
+        .into_iter()
+        .filter_map(|(slot, name, frame)| {
+            let Some(prev) = previous.replace(frame.clone()) else {
+                return Some((slot, name, frame));
+            };
+
+            if (frame.width, frame.height, frame.bpp) != (prev.width, prev.height, prev.bpp) {
+                eprintln!(
+                    "Frame {name} is {}x{} at {} bpp, but the animation is {}x{} at {} bpp, continuing with other frames",
+                    frame.width,
+                    frame.height,
+                    frame.bpp as u8,
+                    prev.width,
+                    prev.height,
+                    prev.bpp as u8
+                );
+                previous = Some(prev);
+                return None;
+            }
+
+            let mut changes = frame.get_bytes();
+            xor_bitplanes(&prev.get_bytes(), &mut changes);
+            let changes = W4Sprite::from_packed(frame.width, frame.height, frame.bpp, changes)
+                .expect("the changes are the same size as the frame");
+
+            Some((slot, name, changes))
+        })
+        .collect()
+}
+
+//Reads a png, or every frame of an Aseprite file. Fails if the file can't be opened, otherwise
+//returns its name along with its sprites, or why they couldn't be read
+pub(crate) fn load_file(
+    path: &Path,
+    palette: Option<&[[u8; 4]]>,
+) -> anyhow::Result<(String, anyhow::Result<IndexedSprites>)> {
+    if is_aseprite(path) {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let sprites = AsepriteFile::from_path(path).and_then(|file| file.sprites(&name, palette));
+        return Ok((name, sprites));
+    }
+
+    let (name, sprite) = match palette {
+        Some(palette) => {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let sprite = IndexedSprite::from_path_with_palette(path, palette);
+            (name, sprite)
+        }
+        None => {
+            let (name, mut png_reader) = open_png(path)?;
+            let sprite = IndexedSprite::from_reader(&mut png_reader);
+            (name, sprite)
+        }
+    };
+    Ok((name.clone(), sprite.map(|sprite| vec![(name, sprite)])))
+}
+
+//Reads the sprites in a file matched by a pattern, for commands which treat a file that can't be
+//read like any other failed sprite
+pub(crate) fn load_matched(path: Result<PathBuf, GlobError>) -> anyhow::Result<IndexedSprites> {
+    let (name, sprites) = load_file(&path?, None)?;
+    sprites.map_err(|e| anyhow::anyhow!("Encountered error processing sprite {name}: {e}"))
+}
+
+fn load_template(path: &str) -> anyhow::Result<Template> {
+    Template::parse(&std::fs::read_to_string(path)?)
+}
+
+//Writes a source or template output, with the file name in any error
+fn write_output_file(
+    path: &str,
+    write: impl FnOnce(&mut BufWriter<File>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let file = open_output_file(path).with_context(|| format!("Failed to open {path}"))?;
+    let mut buf_write = BufWriter::new(file);
+
+    write(&mut buf_write)
+        .and_then(|_| Ok(buf_write.flush()?))
+        .with_context(|| format!("Failed to write {path}"))
+}
+
 fn open_output_file(path: &str) -> Result<File, std::io::Error> {
     std::fs::OpenOptions::new()
         .create(true)
         .write(true)
         .truncate(true)
         .open(path)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use w4_pnger_common::BitsPerPixel;
+    use w4_tiny_decomp::{AnimationPlayer, Bundle, Decompressor};
+
+    use crate::bundle::write_bundle;
+    use crate::compress::Method;
+    use crate::{convert_sprite_with, W4Sprite};
+
+    use super::{frame_changes, ConvertedSprite, Converter, OutputType};
+    use crate::source::Language;
+
+    #[test]
+    fn test_animation() {
+        //A dot moving along a row, with a frame of the wrong size in the middle
+        let frames: Vec<W4Sprite> = (0..4)
+            .map(|i| {
+                let mut bytes = vec![0u8; 8];
+                bytes[i] = 0x18;
+                W4Sprite::from_packed(8, 8, BitsPerPixel::One, bytes).unwrap()
+            })
+            .collect();
+        let odd = W4Sprite::from_packed(16, 8, BitsPerPixel::One, vec![0xff; 16]).unwrap();
+
+        let mut loaded: Vec<_> = frames
+            .iter()
+            .enumerate()
+            .map(|(slot, f)| (slot, String::new(), f.clone()))
+            .collect();
+        loaded.insert(2, (2, String::new(), odd));
+
+        let sprites: Vec<_> = frame_changes(loaded)
+            .into_iter()
+            .map(|(_, name, frame)| convert_sprite_with(name, &frame, Method::Auto).unwrap())
+            .collect();
+        assert_eq!(sprites.len(), frames.len());
+
+        let bytes = write_bundle(&sprites, false, &[], true).unwrap();
+        let mut buf = vec![0u8; 16];
+        let mut player =
+            AnimationPlayer::new(Decompressor::new(&mut buf), Bundle::new(&bytes).unwrap())
+                .unwrap();
+        assert_eq!(player.frame_count(), frames.len());
+
+        //Twice round, to check it loops back to the first frame
+        for i in 0..frames.len() * 2 {
+            assert_eq!(player.frame(), i % frames.len());
+            assert_eq!(player.current().bytes, frames[i % frames.len()].get_bytes());
+            player.advance().unwrap();
+        }
+
+        let mut small = vec![0u8; 8];
+        assert!(
+            AnimationPlayer::new(Decompressor::new(&mut small), Bundle::new(&bytes).unwrap())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_budget() {
+        let sprite = W4Sprite::from_packed(8, 8, BitsPerPixel::One, vec![0x18; 8]).unwrap();
+        let sprites = vec![ConvertedSprite::uncompressed("dot.png".to_owned(), &sprite)];
+        let sprite_bytes = sprites[0].bytes.len();
+
+        let check = |out_type, budget| {
+            let converter = Converter::new("", "", out_type, false).with_budget(Some(budget));
+            *converter.dictionary.lock().unwrap() = vec![0; 10];
+            converter.check_budget(&sprites)
+        };
+
+        //Exactly the size of the bundle, with its header, offset table and dictionary
+        for name_hashes in [false, true] {
+            let bundle = write_bundle(&sprites, name_hashes, &[0; 10], false).unwrap();
+            assert!(check(OutputType::Bundle { name_hashes }, bundle.len()));
+            assert!(!check(OutputType::Bundle { name_hashes }, bundle.len() - 1));
+        }
+
+        //Raw files keep the .ws header, while source code leaves it out of uncompressed sprites.
+        //Neither stores the dictionary
+        assert!(check(OutputType::Raw, sprite_bytes));
+        assert!(!check(OutputType::Raw, sprite_bytes - 1));
+        assert!(check(OutputType::Source(Language::Rust), sprite_bytes - 4));
+        assert!(!check(OutputType::Source(Language::Rust), sprite_bytes - 5));
+    }
+}
@@ -0,0 +1,28 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::wasm4png::W4Sprite;
+
+//Decompresses a .ws file and writes it back out as a png
+pub fn extract_file(input: &Path, output: &Path, palette: &[[u8; 4]]) -> Result<()> {
+    let ws = std::fs::read(input).with_context(|| format!("Failed to read {}", input.display()))?;
+    let sprite = W4Sprite::from_ws(&ws)
+        .with_context(|| format!("{} is not a valid sprite", input.display()))?;
+
+    let out =
+        File::create(output).with_context(|| format!("Failed to create {}", output.display()))?;
+    sprite.write_png(BufWriter::new(out), palette)?;
+
+    println!(
+        "Extracted {}x{} sprite at {} bpp to {}",
+        sprite.width,
+        sprite.height,
+        sprite.bpp.get_num(),
+        output.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,205 @@
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::convert::ConvertedSprite;
+use crate::source::{identifiers, pascal_case};
+
+//Where the sprites referenced by an index module live
+pub enum IndexSource<'a> {
+    //A single bundle, with each sprite at its offset
+    Bundle(&'a Path),
+    //One .ws file per sprite
+    Files(Vec<PathBuf>),
+}
+
+pub fn write_index<W: Write>(
+    out: &mut W,
+    index_path: &Path,
+    source: &IndexSource,
+    sprites: &[ConvertedSprite],
+    offsets: &[usize],
+) -> Result<()> {
+    let variants = identifiers(sprites, pascal_case)?;
+
+    //The module is usually pulled in with include!, where inner attributes aren't allowed, so
+    //every item gets its own
+    writeln!(out, "// Generated by w4-pnger, do not edit")?;
+    writeln!(out)?;
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(out, "#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]")?;
+    writeln!(out, "#[repr(u16)]")?;
+    writeln!(out, "pub enum SpriteId {{")?;
+    for (variant, sprite) in variants.iter().zip(sprites) {
+        writeln!(out, "    {variant}, // {}", sprite.name)?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(out, "pub struct SpriteInfo {{")?;
+    writeln!(out, "    pub offset: usize,")?;
+    writeln!(out, "    pub len: usize,")?;
+    writeln!(out, "    pub width: u16,")?;
+    writeln!(out, "    pub height: u16,")?;
+    writeln!(out, "    pub flags: u8,")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(
+        out,
+        "pub const SPRITES: [SpriteInfo; {}] = [",
+        sprites.len()
+    )?;
+    for (sprite, offset) in sprites.iter().zip(offsets) {
+        writeln!(
+            out,
+            "    SpriteInfo {{ offset: {offset}, len: {}, width: {}, height: {}, flags: {} }},",
+            sprite.bytes.len(),
+            sprite.width,
+            sprite.height,
+            sprite.flags
+        )?;
+    }
+    writeln!(out, "];")?;
+    writeln!(out)?;
+
+    let index_dir = index_path.parent().unwrap_or(Path::new(""));
+    match source {
+        IndexSource::Bundle(bundle) => {
+            writeln!(out, "#[allow(dead_code)]")?;
+            writeln!(
+                out,
+                "pub static BUNDLE: &[u8] = include_bytes!({:?});",
+                relative_path(index_dir, bundle)?
+            )?;
+        }
+        IndexSource::Files(files) => {
+            writeln!(out, "#[allow(dead_code)]")?;
+            writeln!(out, "static FILES: [&[u8]; {}] = [", files.len())?;
+            for file in files {
+                writeln!(
+                    out,
+                    "    include_bytes!({:?}),",
+                    relative_path(index_dir, file)?
+                )?;
+            }
+            writeln!(out, "];")?;
+        }
+    }
+
+    writeln!(out)?;
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(out, "impl SpriteId {{")?;
+    writeln!(out, "    pub const ALL: [SpriteId; {}] = [", sprites.len())?;
+    for variant in &variants {
+        writeln!(out, "        SpriteId::{variant},")?;
+    }
+    writeln!(out, "    ];")?;
+    writeln!(out)?;
+    writeln!(out, "    pub const fn info(self) -> &'static SpriteInfo {{")?;
+    writeln!(out, "        &SPRITES[self as usize]")?;
+    writeln!(out, "    }}")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "    //The sprite in .ws format, ready for Decompressor::decompress"
+    )?;
+    writeln!(out, "    pub fn bytes(self) -> &'static [u8] {{")?;
+    match source {
+        IndexSource::Bundle(_) => {
+            writeln!(out, "        let info = self.info();")?;
+            writeln!(out, "        &BUNDLE[info.offset..info.offset + info.len]")?;
+        }
+        IndexSource::Files(_) => {
+            writeln!(out, "        FILES[self as usize]")?;
+        }
+    }
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")?;
+
+    Ok(())
+}
+
+//include_bytes! resolves paths relative to the file it's written in
+fn relative_path(from_dir: &Path, to: &Path) -> Result<String> {
+    let from_dir = absolute_dir(from_dir)?;
+    let to_dir = absolute_dir(to.parent().unwrap_or(Path::new("")))?;
+    let to = match to.file_name() {
+        Some(file_name) => to_dir.join(file_name),
+        None => bail!("{} is not a file", to.display()),
+    };
+
+    let from: Vec<Component> = from_dir.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &to[common..] {
+        relative.push(component);
+    }
+
+    //Forward slashes work with include_bytes! everywhere
+    Ok(relative.to_string_lossy().replace('\\', "/"))
+}
+
+fn absolute_dir(dir: &Path) -> Result<PathBuf> {
+    if dir.as_os_str().is_empty() {
+        Ok(std::env::current_dir()?.canonicalize()?)
+    } else {
+        Ok(dir.canonicalize()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use w4_pnger_common::CompType;
+
+    use super::{relative_path, write_index, IndexSource};
+    use crate::convert::ConvertedSprite;
+
+    #[test]
+    fn test_relative_path() {
+        let dir = std::env::temp_dir().join("w4-pnger-index-test");
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+
+        assert_eq!(
+            relative_path(&dir.join("src"), &dir.join("assets/sprites.wsb")).unwrap(),
+            "../assets/sprites.wsb"
+        );
+        assert_eq!(
+            relative_path(&dir, &dir.join("sprites.wsb")).unwrap(),
+            "sprites.wsb"
+        );
+        assert!(relative_path(&dir, Path::new("/does/not/exist/a.wsb")).is_err());
+    }
+
+    #[test]
+    fn test_write_index() {
+        let dir = std::env::temp_dir().join("w4-pnger-index-write-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let sprite = ConvertedSprite {
+            name: "wide.png".to_owned(),
+            width: 320,
+            height: 8,
+            flags: 0,
+            comp_type: CompType::Uncompressed,
+            bytes: vec![0; 324],
+        };
+
+        let mut out = Vec::new();
+        let source = IndexSource::Bundle(&dir.join("sprites.wsb"));
+        write_index(&mut out, &dir.join("sprites.rs"), &source, &[sprite], &[5]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(!out.contains("#!["));
+        assert!(out.contains("    pub width: u16,\n"));
+        assert!(out.contains("SpriteInfo { offset: 5, len: 324, width: 320, height: 8, flags: 0 }"));
+    }
+}
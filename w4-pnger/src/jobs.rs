@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::num::NonZeroUsize;
+use std::sync::mpsc;
+use std::sync::Mutex;
+
+//Output from a single job, held back until every earlier job's output has been printed
+#[derive(Default)]
+pub struct Log {
+    lines: Vec<Line>,
+}
+
+enum Line {
+    Out(String),
+    Err(String),
+}
+
+impl Log {
+    pub fn out(&mut self, line: String) {
+        self.lines.push(Line::Out(line));
+    }
+
+    pub fn err(&mut self, line: String) {
+        self.lines.push(Line::Err(line));
+    }
+
+    pub fn print(self) {
+        for line in self.lines {
+            match line {
+                Line::Out(line) => println!("{line}"),
+                Line::Err(line) => eprintln!("{line}"),
+            }
+        }
+    }
+}
+
+//The number of jobs to use when none is given
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+//Runs `job` on every item across `jobs` threads. Results come back in the same order as
+//the items, and each job's log is printed as soon as all of the jobs before it are done
+pub fn run_ordered<T, R, F>(jobs: usize, items: Vec<T>, job: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> (R, Log) + Sync,
+{
+    let count = items.len();
+    let jobs = jobs.clamp(1, count.max(1));
+
+    if jobs == 1 {
+        return items
+            .into_iter()
+            .map(|item| {
+                let (result, log) = job(item);
+                log.print();
+                result
+            })
+            .collect();
+    }
+
+    let queue = Mutex::new(items.into_iter().enumerate());
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let queue = &queue;
+            let job = &job;
+
+            scope.spawn(move || loop {
+                let next = queue.lock().expect("a job panicked").next();
+                let Some((index, item)) = next else {
+                    break;
+                };
+
+                let (result, log) = job(item);
+                if sender.send((index, result, log)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut results = Vec::with_capacity(count);
+        let mut pending = BTreeMap::new();
+
+        for (index, result, log) in receiver {
+            pending.insert(index, (result, log));
+
+            while let Some((result, log)) = pending.remove(&results.len()) {
+                log.print();
+                results.push(result);
+            }
+        }
+
+        results
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_ordered, Log};
+
+    #[test]
+    fn test_run_ordered() {
+        let items: Vec<u64> = (0..100).collect();
+
+        for jobs in [1, 4] {
+            let results = run_ordered(jobs, items.clone(), |i| {
+                //Finish out of order
+                std::thread::sleep(std::time::Duration::from_micros((100 - i) * 10));
+                (i * 2, Log::default())
+            });
+
+            assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Converts png files into WASM-4 sprite data, optionally compressed for use with
+//! `w4-tiny-decomp`.
+//!
+//! Everything the `w4-pnger` command line tool does is available here, so sprites can be
+//! converted from a `build.rs` instead of committing generated files:
+//!
+//! ```no_run
+//! use std::{env, fs::File, path::Path};
+//!
+//! use w4_pnger::{convert_png, source::write_source, Language};
+//!
+//! println!("cargo:rerun-if-changed=assets");
+//!
+//! let sprites = vec![
+//!     convert_png("assets/player.png", true).unwrap(),
+//!     convert_png("assets/enemy.png", true).unwrap(),
+//! ];
+//!
+//! let out = Path::new(&env::var("OUT_DIR").unwrap()).join("sprites.rs");
+//! write_source(&mut File::create(out).unwrap(), Language::Rust, "", &sprites).unwrap();
+//! ```
+//!
+//! The game then pulls the module in with
+//! `include!(concat!(env!("OUT_DIR"), "/sprites.rs"));`.
+
+pub mod analyze;
+pub mod aseprite;
+pub mod budget;
+pub mod bundle;
+pub mod cache;
+pub mod carve;
+pub mod compare;
+pub mod compress;
+pub mod convert;
+pub mod extract;
+pub mod index;
+pub mod jobs;
+pub mod manifest;
+pub mod pngstream;
+pub mod slice;
+pub mod source;
+pub mod template;
+pub mod tiled;
+pub mod tilemap;
+pub mod verify;
+pub mod wasm4png;
+
+use std::path::Path;
+
+use anyhow::Result;
+
+use compress::{compress_with, compressors};
+
+pub use compress::huffcomp::HuffComp;
+pub use compress::lzcomp::{LzComp, LzDictComp};
+pub use compress::pkcomp::PkComp;
+pub use compress::{CompressionResult, Compressor, Method};
+pub use convert::{ConvertedSprite, Converter, OutputType};
+pub use slice::{SliceNaming, Slicer};
+pub use source::Language;
+pub use template::Template;
+pub use w4_pnger_common::{BitsPerPixel, CompType};
+pub use wasm4png::W4Sprite;
+
+/// Reads the png at `path` and converts it into `.ws` data, named after the file.
+pub fn convert_png<P: AsRef<Path>>(path: P, compress: bool) -> Result<ConvertedSprite> {
+    let path = path.as_ref();
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    convert_sprite(name, &W4Sprite::from_path(path)?, compress)
+}
+
+/// Converts png file contents into `.ws` data.
+pub fn convert_png_bytes(name: &str, png: &[u8], compress: bool) -> Result<ConvertedSprite> {
+    convert_sprite(name.to_owned(), &W4Sprite::from_png_bytes(png)?, compress)
+}
+
+/// Turns an already decoded sprite into `.ws` data, compressing it with [`PkComp`] if asked.
+pub fn convert_sprite(name: String, sprite: &W4Sprite, compress: bool) -> Result<ConvertedSprite> {
+    convert_sprite_with(name, sprite, compress.into())
+}
+
+/// Turns an already decoded sprite into `.ws` data, compressed as `method` asks.
+/// [`Method::Auto`] picks whichever compressor makes the smallest sprite, leaving it
+/// uncompressed if none of them help.
+pub fn convert_sprite_with(
+    name: String,
+    sprite: &W4Sprite,
+    method: Method,
+) -> Result<ConvertedSprite> {
+    let bytes = sprite.get_bytes();
+    match compress_with(method, &compressors(), &bytes, |compressor| {
+        compressor.compress(&bytes)
+    })? {
+        Some((comp_type, compressed)) => Ok(ConvertedSprite::compressed(
+            name, sprite, comp_type, compressed,
+        )),
+        None => Ok(ConvertedSprite::uncompressed(name, sprite)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use w4_tiny_decomp::Decompressor;
+
+    use crate::{convert_png, convert_png_bytes, W4Sprite};
+
+    #[test]
+    fn test_convert_png() {
+        let sprite = W4Sprite::from_path("../test.png").unwrap();
+
+        for compress in [false, true] {
+            let from_path = convert_png("../test.png", compress).unwrap();
+            let from_bytes =
+                convert_png_bytes("test.png", &std::fs::read("../test.png").unwrap(), compress)
+                    .unwrap();
+
+            assert_eq!(from_path.name, "test.png");
+            assert_eq!(from_path.bytes, from_bytes.bytes);
+
+            let mut buf = vec![0; 1024];
+            let mut decomp = Decompressor::new(&mut buf);
+            let decompressed = decomp.decompress(&from_path.bytes).unwrap();
+            assert_eq!(decompressed.bytes, sprite.get_bytes());
+        }
+    }
+}
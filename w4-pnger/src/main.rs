@@ -1,45 +1,221 @@
-use clap::{arg, ArgAction, ArgGroup, ArgMatches, Command};
+use clap::builder::PossibleValuesParser;
+use clap::{arg, Arg, ArgAction, ArgGroup, ArgMatches, Command};
 
-mod analyze;
-mod compress;
-mod convert;
-mod pngstream;
-mod wasm4png;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 
-use analyze::Analyzer;
-use convert::{Converter, OutputType};
+use w4_pnger::analyze::Analyzer;
+use w4_pnger::budget::parse_size;
+use w4_pnger::cache::{Cache, DEFAULT_CACHE_DIR};
+use w4_pnger::carve::{carve_file, RawSprites};
+use w4_pnger::compress::method_names;
+use w4_pnger::extract::extract_file;
+use w4_pnger::jobs::default_jobs;
+use w4_pnger::manifest::Manifest;
+use w4_pnger::slice::{parse_dimensions, parse_frames};
+use w4_pnger::tilemap::convert_tilemap;
+use w4_pnger::verify::Verifier;
+use w4_pnger::wasm4png::{parse_color, WASM4_PALETTE};
+use w4_pnger::{
+    BitsPerPixel, CompType, Converter, LzDictComp, Method, OutputType, SliceNaming, Slicer,
+};
+
+const DEFAULT_DICTIONARY_SIZE: &str = "512";
+
+const OUTPUT_TYPES: [&str; 8] = ["rs", "c", "zig", "as", "go", "odin", "raw", "text"];
 
 fn main() {
     let matches = cmd().get_matches();
 
     match matches.subcommand() {
         Some(("convert", submatches)) => {
-            let path = get_path(&submatches);
-
-            let compress: bool = *submatches.get_one("compress").expect("defaulted by clap");
+            let path = get_path(submatches);
+            exit_on_err(check_not_method(path));
 
-            let (output_type, output_file) = if let Some(output_file) =
-                submatches.get_one::<String>("raw")
-            {
-                ("raw", output_file.as_str())
-            } else if let Some(output_file) = submatches.get_one::<String>("text") {
-                ("text", output_file.as_str())
-            } else {
-                unreachable!("clap requires either --rs or --raw or --text are set with the appropriate parameter passed")
+            let compress: Method = match submatches.get_one::<String>("compress") {
+                Some(method) => method.parse().expect("clap only allows valid methods"),
+                None => Method::None,
             };
 
-            Converter::new(
-                path,
-                output_file,
-                OutputType::from_str(output_type),
-                compress,
-            )
-            .run();
+            let (output_type, output_file) =
+                if let Some(mut template) = submatches.get_many::<String>("template") {
+                    let template_file = template.next().expect("clap requires two values");
+                    let output_file = template.next().expect("clap requires two values");
+                    (OutputType::Template(template_file.clone()), output_file)
+                } else if let Some(output_file) = submatches.get_one::<String>("bundle") {
+                    let name_hashes = *submatches
+                        .get_one("name-hashes")
+                        .expect("defaulted by clap");
+                    (OutputType::Bundle { name_hashes }, output_file)
+                } else {
+                    OUTPUT_TYPES
+                        .iter()
+                        .find_map(|output_type| {
+                            submatches
+                                .get_one::<String>(output_type)
+                                .map(|output_file| {
+                                    let output_type: OutputType =
+                                        output_type.parse().expect("OUTPUT_TYPES are all valid");
+                                    (output_type, output_file)
+                                })
+                        })
+                        .expect("clap requires one of the output types is set with a file passed")
+                };
+
+            let watch: bool = *submatches.get_one("watch").expect("defaulted by clap");
+            let index = submatches.get_one::<String>("index").map(|i| i.as_str());
+            let package = submatches.get_one::<String>("package").map(|p| p.as_str());
+            let jobs = get_jobs(submatches);
+            let cache = submatches.get_one::<String>("cache").map(Cache::new);
+
+            let budget = get_budget(submatches);
+
+            let slice = get_slicer(submatches);
+
+            let animation = submatches.get_flag("animation");
+            if animation && !matches!(output_type, OutputType::Bundle { .. }) {
+                eprintln!("--animation needs --bundle output to store the frames in");
+                std::process::exit(1);
+            }
+
+            let dictionary = submatches.get_one::<usize>("dictionary").copied();
+            let dictionary_method =
+                matches!(compress, Method::Auto | Method::Only(CompType::LzDict));
+            if dictionary.is_some() && !matches!(output_type, OutputType::Bundle { .. }) {
+                eprintln!("--dictionary needs --bundle output to store the dictionary in");
+                std::process::exit(1);
+            }
+            if dictionary.is_some() && !dictionary_method {
+                eprintln!("--dictionary needs --compress=auto or --compress=lz-dict");
+                std::process::exit(1);
+            }
+            if dictionary.is_none() && compress == Method::Only(CompType::LzDict) {
+                eprintln!("--compress=lz-dict needs a dictionary from --dictionary");
+                std::process::exit(1);
+            }
+
+            let fits = Converter::new(path, output_file.as_str(), output_type, compress)
+                .with_index(index)
+                .with_package(package)
+                .with_watch(watch)
+                .with_cache(cache)
+                .with_jobs(jobs)
+                .with_budget(budget)
+                .with_dictionary(dictionary)
+                .with_animation(animation)
+                .with_slice(slice)
+                .run();
+            if !fits {
+                std::process::exit(1);
+            }
         }
         Some(("analyze", submatches)) => {
-            let path = get_path(&submatches);
+            let path = get_path(submatches);
+
+            let format = submatches
+                .get_one::<String>("format")
+                .expect("defaulted by clap")
+                .parse()
+                .expect("clap only allows valid formats");
+
+            let fits = Analyzer::new(path)
+                .with_jobs(get_jobs(submatches))
+                .with_format(format)
+                .with_budget(get_budget(submatches))
+                .with_compare(submatches.get_flag("compare"))
+                .run();
+            if !fits {
+                std::process::exit(1);
+            }
+        }
+        Some(("verify", submatches)) => {
+            let path = get_path(submatches);
+
+            if !Verifier::new(path).with_jobs(get_jobs(submatches)).run() {
+                std::process::exit(1);
+            }
+        }
+        Some(("extract", submatches)) => {
+            let input = Path::new(
+                submatches
+                    .get_one::<String>("FILE")
+                    .expect("clap requires this argument to be present"),
+            );
+            let output = match submatches.get_one::<String>("output") {
+                Some(output) => PathBuf::from(output),
+                None => input.with_extension("png"),
+            };
+
+            let result =
+                get_palette(submatches).and_then(|palette| extract_file(input, &output, &palette));
+            if let Err(e) = result {
+                eprintln!("{e:#}");
+                std::process::exit(1);
+            }
+        }
+        Some(("carve", submatches)) => {
+            let cart = Path::new(
+                submatches
+                    .get_one::<String>("CART")
+                    .expect("clap requires this argument to be present"),
+            );
+            let out_dir = match submatches.get_one::<String>("output") {
+                Some(out_dir) => PathBuf::from(out_dir),
+                None => cart.with_extension("sprites"),
+            };
+
+            let result = get_raw_sprites(submatches).and_then(|raw| {
+                let palette = get_palette(submatches)?;
+                carve_file(cart, &out_dir, &palette, raw.as_ref())
+            });
+            if let Err(e) = result {
+                eprintln!("{e:#}");
+                std::process::exit(1);
+            }
+        }
+        Some(("build", submatches)) => {
+            let manifest_path = Path::new(
+                submatches
+                    .get_one::<String>("MANIFEST")
+                    .expect("defaulted by clap"),
+            );
+            let dir = manifest_path.parent().unwrap_or(Path::new(""));
+
+            if let Err(e) =
+                Manifest::load(manifest_path).and_then(|m| m.build(dir, get_jobs(submatches)))
+            {
+                eprintln!("{e:#}");
+                std::process::exit(1);
+            }
+        }
+
+        Some(("tilemap", submatches)) => {
+            let input = submatches
+                .get_one::<String>("MAP")
+                .expect("clap requires this argument to be present");
+            exit_on_err(check_not_method(input));
+            let input = Path::new(input);
+            let map_output = match submatches.get_one::<String>("map") {
+                Some(map_output) => PathBuf::from(map_output),
+                None => input.with_extension("wsm"),
+            };
+            let tiles_output = match submatches.get_one::<String>("tiles") {
+                Some(tiles_output) => PathBuf::from(tiles_output),
+                None => {
+                    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+                    input.with_file_name(format!("{stem}_tiles.wsb"))
+                }
+            };
+            let compress: Method = match submatches.get_one::<String>("compress") {
+                Some(method) => method.parse().expect("clap only allows valid methods"),
+                None => Method::None,
+            };
+            let layer = submatches.get_one::<String>("layer").map(|l| l.as_str());
 
-            Analyzer::new(path).run();
+            if let Err(e) = convert_tilemap(input, &map_output, &tiles_output, layer, compress) {
+                eprintln!("{e:#}");
+                std::process::exit(1);
+            }
         }
 
         _ => unreachable!("clap will exit the program if a valid subcommand is not reached"),
@@ -54,22 +230,279 @@ fn cmd() -> Command {
         .arg_required_else_help(true)
         .subcommand(
             Command::new("convert")
-                .about("Converts a .png file for use with WASM-4")
-                .arg(arg!(-c --compress "Compress these files?").action(ArgAction::SetTrue))
+                .about("Converts .png and .aseprite files for use with WASM-4")
+                .arg(
+                    arg!(-c --compress [METHOD] "Compress these files, with pk unless another method is given. The method must follow an =, as in --compress=auto")
+                        .require_equals(true)
+                        .default_missing_value("pk")
+                        .value_parser(PossibleValuesParser::new(method_names())),
+                )
+                .arg(
+                    arg!(-w --watch "Keep running and reconvert sprites when their files change")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    arg!(--cache [DIR] "Reuse compression results from earlier runs, stored in DIR")
+                        .default_missing_value(DEFAULT_CACHE_DIR)
+                        .requires("compress"),
+                )
+                .arg(
+                    arg!(--dictionary [SIZE] "Train a dictionary of up to SIZE bytes, 512 by default, from all of the sprites and store it in the bundle for lz-dict")
+                        .require_equals(true)
+                        .default_missing_value(DEFAULT_DICTIONARY_SIZE)
+                        .value_parser(parse_size)
+                        .requires("bundle"),
+                )
+                .arg(
+                    arg!(--slice <SIZE> "Cut each file into a grid of WIDTHxHEIGHT sprites, converted as if they were separate files")
+                        .value_parser(parse_dimensions),
+                )
+                .arg(
+                    arg!(--margin <PIXELS> "Pixels between the edge of each sheet and the first sprites")
+                        .value_parser(clap::value_parser!(u16))
+                        .requires("slice"),
+                )
+                .arg(
+                    arg!(--spacing <PIXELS> "Pixels between the sprites in each sheet")
+                        .value_parser(clap::value_parser!(u16))
+                        .requires("slice"),
+                )
+                .arg(
+                    arg!(--frames <FRAMES> "Only keep these sprites from each sheet, counted in reading order, like 0-3,8")
+                        .value_parser(parse_frames)
+                        .requires("slice"),
+                )
+                .arg(
+                    arg!(--"slice-names" <NAMING> "Name sprites <sheet>_<index>, or <sheet>_<row>_<column> with grid")
+                        .value_parser(["index", "grid"])
+                        .default_value("index")
+                        .requires("slice"),
+                )
+                .arg(
+                    arg!(--animation "Store the sprites as frames of an animation in the bundle, each as its changes from the frame before")
+                        .requires("bundle"),
+                )
+                .arg(jobs_arg())
+                .arg(budget_arg())
+                .arg(arg!(--rs <FILE> "Generate a Rust source file with sprites"))
+                .arg(arg!(--c <FILE> "Generate a C/C++ header with sprites"))
+                .arg(arg!(--zig <FILE> "Generate a Zig source file with sprites"))
+                .arg(
+                    Arg::new("as")
+                        .long("as")
+                        .value_name("FILE")
+                        .help("Generate an AssemblyScript source file with sprites"),
+                )
+                .arg(arg!(--go <FILE> "Generate a Go source file with sprites"))
+                .arg(arg!(--odin <FILE> "Generate an Odin source file with sprites"))
+                .arg(
+                    arg!(--package <NAME> "The package for --go or --odin output, defaults to main")
+                        .requires("packaged"),
+                )
+                .arg(
+                    Arg::new("template")
+                        .long("template")
+                        .num_args(2)
+                        .value_names(["TEMPLATE", "FILE"])
+                        .help("Generate a file with sprites from a template"),
+                )
+                .arg(arg!(--bundle <FILE> "Generate a single .wsb bundle with all sprites"))
+                .arg(
+                    arg!(--"name-hashes" "Store name hashes in the bundle for Bundle::find")
+                        .action(ArgAction::SetTrue)
+                        .requires("bundle"),
+                )
                 .arg(arg!(--raw <FILE> "Generate a raw file with sprites"))
+                .arg(
+                    arg!(--index <FILE> "Generate a Rust module with a SpriteId enum for the raw files or bundle")
+                        .requires("indexable"),
+                )
                 .arg(arg!(--text <FILE> "Generate a text file with sprites"))
                 .group(
                     ArgGroup::new("output")
                         .required(true)
-                        .args(&["raw", "text"]),
+                        .args(OUTPUT_TYPES)
+                        .arg("template")
+                        .arg("bundle"),
                 )
+                .group(ArgGroup::new("indexable").args(["raw", "bundle"]))
+                .group(ArgGroup::new("packaged").args(["go", "odin"]))
                 .arg(arg!([PATH]).required(true)),
         )
         .subcommand(
             Command::new("analyze")
                 .about("Analyzes a .png file and reports its compression statistics")
+                .arg(jobs_arg())
+                .arg(budget_arg())
+                .arg(
+                    arg!(--format <FORMAT> "Report as prose, or as json or csv records for other tools")
+                        .value_parser(["text", "json", "csv"])
+                        .default_value("text"),
+                )
+                .arg(arg!(--compare "Compare every compression method, and whether each is worth its decompressor"))
+                .arg(arg!([PATH]).required(true)),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Checks that every sprite comes back unchanged after compression and decompression")
+                .arg(jobs_arg())
                 .arg(arg!([PATH]).required(true)),
         )
+        .subcommand(
+            Command::new("extract")
+                .about("Converts a .ws file back into a .png file")
+                .arg(arg!(-o --output <FILE> "The png to write, defaults to FILE with a .png extension"))
+                .arg(palette_arg())
+                .arg(arg!([FILE]).required(true)),
+        )
+        .subcommand(
+            Command::new("carve")
+                .about("Finds sprites in a compiled WASM-4 cart and writes them out as .png files")
+                .arg(arg!(-o --output <DIR> "Directory to write sprites to, defaults to CART with a .sprites extension"))
+                .arg(palette_arg())
+                .arg(arg!(--raw <SIZE> "Look for WIDTHxHEIGHT sprites without a .ws header instead, like png2src writes"))
+                .arg(
+                    arg!(--bpp <BPP> "Bits per pixel of the --raw sprites")
+                        .value_parser(["1", "2"])
+                        .default_value("1")
+                        .requires("raw"),
+                )
+                .arg(
+                    arg!(--address <ADDRESSES> "Comma separated memory addresses of --raw sprites, instead of splitting each data segment into sprites")
+                        .value_delimiter(',')
+                        .requires("raw"),
+                )
+                .arg(arg!([CART]).required(true)),
+        )
+        .subcommand(
+            Command::new("tilemap")
+                .about("Converts a layer of a Tiled .tmx or .tmj map into a tilemap, along with a bundle of the tiles it uses")
+                .arg(
+                    arg!(-c --compress [METHOD] "Compress the tilemap's rows and tiles, with pk unless another method is given. The method must follow an =, as in --compress=auto")
+                        .require_equals(true)
+                        .default_missing_value("pk")
+                        .value_parser(PossibleValuesParser::new(
+                            method_names().into_iter().filter(|m| *m != LzDictComp::NAME),
+                        )),
+                )
+                .arg(arg!(--layer <NAME> "The tile layer to convert, defaults to the first visible one"))
+                .arg(arg!(--map <FILE> "The tilemap to write, defaults to MAP with a .wsm extension"))
+                .arg(arg!(--tiles <FILE> "The tile bundle to write, defaults to MAP with _tiles.wsb in place of its extension"))
+                .arg(arg!([MAP]).required(true)),
+        )
+        .subcommand(
+            Command::new("build")
+                .about("Converts every sprite described in a w4-pnger.toml manifest")
+                .arg(jobs_arg())
+                .arg(arg!([MANIFEST]).default_value("w4-pnger.toml")),
+        )
+}
+
+fn jobs_arg() -> Arg {
+    arg!(-j --jobs <N> "Number of sprites to process at once, defaults to the number of CPUs")
+        .value_parser(clap::value_parser!(usize))
+}
+
+fn palette_arg() -> Arg {
+    arg!(--palette <COLORS> "Comma separated colors for each palette index, defaults to the WASM-4 palette")
+        .value_delimiter(',')
+}
+
+fn get_palette(matches: &ArgMatches) -> anyhow::Result<Vec<[u8; 4]>> {
+    match matches.get_many::<String>("palette") {
+        Some(colors) => colors.map(|c| parse_color(c)).collect(),
+        None => Ok(WASM4_PALETTE.to_vec()),
+    }
+}
+
+fn get_slicer(matches: &ArgMatches) -> Option<Slicer> {
+    let &(width, height) = matches.get_one::<(u16, u16)>("slice")?;
+    let naming: SliceNaming = matches
+        .get_one::<String>("slice-names")
+        .expect("defaulted by clap")
+        .parse()
+        .expect("clap only allows valid names");
+
+    Some(
+        Slicer::new(width, height)
+            .with_margin(matches.get_one("margin").copied().unwrap_or(0))
+            .with_spacing(matches.get_one("spacing").copied().unwrap_or(0))
+            .with_frames(
+                matches
+                    .get_one::<Vec<RangeInclusive<usize>>>("frames")
+                    .cloned()
+                    .unwrap_or_default(),
+            )
+            .with_naming(naming),
+    )
+}
+
+fn get_raw_sprites(matches: &ArgMatches) -> anyhow::Result<Option<RawSprites>> {
+    let Some(size) = matches.get_one::<String>("raw") else {
+        return Ok(None);
+    };
+
+    let (width, height) = parse_dimensions(size)?;
+    let bpp = match matches.get_one::<String>("bpp").map(|b| b.as_str()) {
+        Some("2") => BitsPerPixel::Two,
+        _ => BitsPerPixel::One,
+    };
+    let addresses = matches
+        .get_many::<String>("address")
+        .into_iter()
+        .flatten()
+        .map(|a| parse_address(a))
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(Some(RawSprites {
+        width,
+        height,
+        bpp,
+        addresses,
+    }))
+}
+
+fn parse_address(address: &str) -> anyhow::Result<u32> {
+    let address = address.trim();
+    match address.strip_prefix("0x") {
+        Some(hex) => Ok(u32::from_str_radix(hex, 16)?),
+        None => Ok(address.parse()?),
+    }
+}
+
+fn budget_arg() -> Arg {
+    arg!(--budget <BYTES> "Fail if the sprites take up more than this many bytes, like 48KiB")
+        .value_parser(parse_size)
+}
+
+fn get_budget(matches: &ArgMatches) -> Option<usize> {
+    matches.get_one::<usize>("budget").copied()
+}
+
+fn get_jobs(matches: &ArgMatches) -> usize {
+    matches
+        .get_one::<usize>("jobs")
+        .copied()
+        .unwrap_or_else(default_jobs)
+}
+
+//--compress takes its method after an =, so that a bare --compress can come before the path.
+//That means --compress auto reads auto as the path, which would otherwise match nothing
+fn check_not_method(path: &str) -> anyhow::Result<()> {
+    if method_names().contains(&path) && !Path::new(path).exists() {
+        anyhow::bail!(
+            "{path} is a compression method, not a file. Use --compress={path} to pick it"
+        );
+    }
+
+    Ok(())
+}
+
+fn exit_on_err(result: anyhow::Result<()>) {
+    if let Err(e) = result {
+        eprintln!("{e:#}");
+        std::process::exit(1);
+    }
 }
 
 fn get_path(matches: &ArgMatches) -> &str {
@@ -83,3 +516,39 @@ fn get_path(matches: &ArgMatches) -> &str {
 fn verify_cmd() {
     cmd().debug_assert();
 }
+
+#[test]
+fn test_compress_arg() {
+    let compress = |args: &[&str]| {
+        let matches = cmd().try_get_matches_from(args).unwrap();
+        let (_, submatches) = matches.subcommand().unwrap();
+        let method = submatches.get_one::<String>("compress").cloned();
+        (method, get_path(submatches).to_owned())
+    };
+
+    assert_eq!(
+        compress(&[
+            "w4-pnger",
+            "convert",
+            "--compress=auto",
+            "a.png",
+            "--rs",
+            "out"
+        ]),
+        (Some("auto".to_owned()), "a.png".to_owned())
+    );
+    assert_eq!(
+        compress(&["w4-pnger", "convert", "-c", "a.png", "--rs", "out"]),
+        (Some("pk".to_owned()), "a.png".to_owned())
+    );
+    assert_eq!(
+        compress(&["w4-pnger", "convert", "a.png", "--rs", "out"]),
+        (None, "a.png".to_owned())
+    );
+
+    //Without the =, the method is taken as the path
+    let (method, path) = compress(&["w4-pnger", "convert", "--compress", "auto", "--rs", "out"]);
+    assert_eq!(method.as_deref(), Some("pk"));
+    assert!(check_not_method(&path).is_err());
+    assert!(check_not_method("a.png").is_ok());
+}
@@ -0,0 +1,448 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use w4_pnger_common::CompType;
+
+use crate::budget::{report_budget, BudgetSize};
+use crate::cache::Cache;
+use crate::compress::Method;
+use crate::convert::{ConvertedSprite, Converter, OutputType};
+use crate::slice::{parse_dimensions, parse_frames, Slicer};
+use crate::wasm4png::parse_color;
+
+//A w4-pnger.toml describing every sprite in a cart, for example
+//
+//  cache = ".w4-pnger-cache"
+//  budget = "48KiB"
+//
+//  [[sprites]]
+//  input = "assets/ui/*.png"
+//  output = "src/ui"
+//  format = "rs"
+//  compress = true
+//
+//  [[sprites]]
+//  input = "assets/tiles.png"
+//  output = "src/tiles"
+//  format = "rs"
+//  slice = { size = "8x8", spacing = 1, frames = "0-15" }
+//
+//  [[sprites]]
+//  input = "assets/player/*.png"
+//  palette = ["#e0f8cf", "#86c06c", "#306850", "#071821"]
+//  bundle = "game"
+//
+//  [bundles.game]
+//  output = "assets/game.wsb"
+//  name_hashes = true
+//  index = "src/sprites.rs"
+//
+//Paths are relative to the manifest.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    //Directory to keep compression results in between builds
+    pub cache: Option<String>,
+    //Fail the build if the sprites take up more than this
+    pub budget: Option<BudgetSize>,
+    #[serde(default)]
+    pub sprites: Vec<SpriteEntry>,
+    #[serde(default)]
+    pub bundles: BTreeMap<String, BundleEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpriteEntry {
+    pub input: String,
+    //The output file or prefix, as passed to convert
+    pub output: Option<String>,
+    //Any of convert's output types, such as "rs", "raw" or "text", or "template"
+    pub format: Option<String>,
+    pub template: Option<String>,
+    #[serde(default)]
+    pub compress: Compression,
+    //Colors in draw color order, instead of sorting by brightness
+    pub palette: Option<Vec<String>>,
+    pub index: Option<String>,
+    //The package for go and odin output, main by default
+    pub package: Option<String>,
+    //The name of a [bundles] entry to add these sprites to
+    pub bundle: Option<String>,
+    //Cuts each input into a grid of sprites, like convert --slice
+    pub slice: Option<SliceEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SliceEntry {
+    //The size of each sprite, like "16x16"
+    pub size: String,
+    #[serde(default)]
+    pub margin: u16,
+    #[serde(default)]
+    pub spacing: u16,
+    //Which sprites to keep, like "0-3,8"
+    pub frames: Option<String>,
+    //"index" or "grid"
+    pub names: Option<String>,
+}
+
+impl SliceEntry {
+    pub fn slicer(&self) -> Result<Slicer> {
+        let (width, height) = parse_dimensions(&self.size)?;
+        let mut slicer = Slicer::new(width, height)
+            .with_margin(self.margin)
+            .with_spacing(self.spacing);
+        if let Some(frames) = &self.frames {
+            slicer = slicer.with_frames(parse_frames(frames)?);
+        }
+        if let Some(names) = &self.names {
+            slicer = slicer.with_naming(names.parse()?);
+        }
+
+        Ok(slicer)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BundleEntry {
+    pub output: String,
+    #[serde(default)]
+    pub name_hashes: bool,
+    pub index: Option<String>,
+}
+
+//Either `compress = true` or a compression method, like `compress = "pk"` or `compress = "auto"`
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Compression {
+    Enabled(bool),
+    Method(String),
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Enabled(false)
+    }
+}
+
+impl Compression {
+    pub fn method(&self) -> Result<Method> {
+        match self {
+            Compression::Enabled(enabled) => Ok((*enabled).into()),
+            Compression::Method(method) => method.parse(),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let manifest = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        Self::parse(&manifest).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn parse(manifest: &str) -> Result<Self> {
+        let manifest: Manifest = toml::from_str(manifest)?;
+
+        if let Some(budget) = &manifest.budget {
+            budget.bytes()?;
+        }
+
+        for entry in &manifest.sprites {
+            if entry.output.is_none() && entry.bundle.is_none() {
+                bail!("{} needs an output, a bundle, or both", entry.input);
+            }
+            if entry.output.is_some() != entry.format.is_some() {
+                bail!("{} needs both output and format, or neither", entry.input);
+            }
+            if let Some(bundle) = &entry.bundle {
+                if !manifest.bundles.contains_key(bundle) {
+                    bail!(
+                        "{} uses bundle {bundle}, which isn't in [bundles]",
+                        entry.input
+                    );
+                }
+            }
+            //Manifests have no way to train a dictionary, so every lz-dict sprite would fail
+            if entry.compress.method()? == Method::Only(CompType::LzDict) {
+                bail!(
+                    "{} uses compress = \"lz-dict\", which needs a dictionary from convert --dictionary",
+                    entry.input
+                );
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    //Converts every entry, then writes each bundle once all of its sprites are ready
+    pub fn build(&self, dir: &Path, jobs: usize) -> Result<()> {
+        let mut groups: BTreeMap<&str, Vec<ConvertedSprite>> = BTreeMap::new();
+        let mut sizes = Vec::new();
+
+        for entry in &self.sprites {
+            println!("Building {}...", entry.input);
+
+            let input = resolve(dir, &entry.input);
+            let compress = entry.compress.method()?;
+            let palette = match &entry.palette {
+                Some(palette) => Some(
+                    palette
+                        .iter()
+                        .map(|c| parse_color(c))
+                        .collect::<Result<Vec<_>>>()?,
+                ),
+                None => None,
+            };
+
+            let (out_type, output) = match (&entry.output, &entry.format) {
+                (Some(output), Some(format)) => {
+                    (output_type(entry, format, dir)?, resolve(dir, output))
+                }
+                //Sprites that only go into a bundle don't write anything themselves
+                _ => (OutputType::Bundle { name_hashes: false }, String::new()),
+            };
+            let index = entry.index.as_ref().map(|i| resolve(dir, i));
+            let slice = entry.slice.as_ref().map(SliceEntry::slicer).transpose()?;
+
+            let converter = Converter::new(&input, &output, out_type, compress)
+                .with_index(index.as_deref())
+                .with_package(entry.package.as_deref())
+                .with_palette(palette)
+                .with_slice(slice)
+                .with_jobs(jobs)
+                .with_cache(self.cache.as_ref().map(|c| Cache::new(resolve(dir, c))));
+
+            let sprites = converter.convert()?;
+
+            if entry.output.is_some() {
+                converter.write(&sprites)?;
+
+                //Sprites which also go into a bundle are counted with the bundle
+                if entry.bundle.is_none() {
+                    sizes.extend(converter.written_sizes(&sprites));
+                }
+            }
+
+            if let Some(bundle) = &entry.bundle {
+                groups.entry(bundle).or_default().extend(sprites);
+            }
+        }
+
+        for (name, bundle) in &self.bundles {
+            let sprites = groups.remove(name.as_str()).unwrap_or_default();
+            println!("Writing bundle {name} with {} sprites...", sprites.len());
+
+            let output = resolve(dir, &bundle.output);
+            let index = bundle.index.as_ref().map(|i| resolve(dir, i));
+            let out_type = OutputType::Bundle {
+                name_hashes: bundle.name_hashes,
+            };
+
+            let converter =
+                Converter::new("", &output, out_type, false).with_index(index.as_deref());
+            converter.write(&sprites)?;
+            sizes.extend(converter.written_sizes(&sprites));
+        }
+
+        if let Some(budget) = &self.budget {
+            let budget = budget.bytes()?;
+            if !report_budget(&mut std::io::stdout().lock(), budget, &sizes)? {
+                bail!("Sprites are over the budget of {budget} bytes");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn output_type(entry: &SpriteEntry, format: &str, dir: &Path) -> Result<OutputType> {
+    match (format, &entry.template) {
+        ("template", Some(template)) => Ok(OutputType::Template(resolve(dir, template))),
+        ("template", None) => bail!(
+            "{} uses format = \"template\" without a template",
+            entry.input
+        ),
+        (_, Some(_)) => bail!("{} has a template, but format = \"{format}\"", entry.input),
+        ("bundle", None) => Err(anyhow!(
+            "{} should use bundle = \"name\" and a [bundles] entry instead of format = \"bundle\"",
+            entry.input
+        )),
+        (format, None) => format.parse(),
+    }
+}
+
+fn resolve(dir: &Path, path: &str) -> String {
+    dir.join(path).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use w4_pnger_common::CompType;
+    use w4_tiny_decomp::Bundle;
+
+    use super::Manifest;
+    use crate::compress::Method;
+    use crate::slice::{SliceNaming, Slicer};
+
+    #[test]
+    fn test_parse() {
+        let manifest = Manifest::parse(
+            r##"
+            [[sprites]]
+            input = "ui/*.png"
+            output = "src/ui"
+            format = "rs"
+            compress = "pk"
+
+            [[sprites]]
+            input = "items/*.png"
+            output = "items"
+            format = "raw"
+            compress = "auto"
+
+            [[sprites]]
+            input = "player/*.png"
+            palette = ["#e0f8cf", "#86c06c"]
+            bundle = "game"
+            slice = { size = "16x16", spacing = 1, frames = "0-3", names = "grid" }
+
+            [bundles.game]
+            output = "game.wsb"
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.sprites.len(), 3);
+        assert!(manifest.sprites[0].slice.is_none());
+        assert_eq!(
+            manifest.sprites[2]
+                .slice
+                .as_ref()
+                .unwrap()
+                .slicer()
+                .unwrap(),
+            Slicer::new(16, 16)
+                .with_spacing(1)
+                .with_frames(vec![0..=3])
+                .with_naming(SliceNaming::Grid)
+        );
+        assert_eq!(
+            manifest.sprites[0].compress.method().unwrap(),
+            Method::Only(CompType::Pk)
+        );
+        assert_eq!(manifest.sprites[1].compress.method().unwrap(), Method::Auto);
+        assert_eq!(manifest.sprites[2].compress.method().unwrap(), Method::None);
+        assert!(!manifest.bundles["game"].name_hashes);
+
+        assert!(Manifest::parse("[[sprites]]\ninput = \"a.png\"").is_err());
+        assert!(Manifest::parse("budget = \"lots\"").is_err());
+        assert_eq!(
+            Manifest::parse("budget = \"1KiB\"")
+                .unwrap()
+                .budget
+                .unwrap()
+                .bytes()
+                .unwrap(),
+            1024
+        );
+        assert!(Manifest::parse("[[sprites]]\ninput = \"a.png\"\nbundle = \"b\"").is_err());
+        assert!(Manifest::parse("[[sprites]]\ninput = \"a.png\"\noutput = \"a\"").is_err());
+        assert!(Manifest::parse(
+            "[[sprites]]\ninput = \"a.png\"\noutput = \"a\"\nformat = \"raw\"\ncompress = \"zip\""
+        )
+        .is_err());
+        let lz_dict = Manifest::parse(
+            "[[sprites]]\ninput = \"a.png\"\noutput = \"a\"\nformat = \"raw\"\ncompress = \"lz-dict\"",
+        );
+        assert!(lz_dict.is_err_and(|e| e.to_string().contains("convert --dictionary")));
+    }
+
+    #[test]
+    fn test_build() {
+        let dir = std::env::temp_dir().join("w4-pnger-manifest-test");
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::copy("../test.png", dir.join("assets/a.png")).unwrap();
+        std::fs::copy("../test.png", dir.join("assets/b.png")).unwrap();
+
+        //Exactly the size of the bundle, so a's bytes mustn't be counted for both a.h and the
+        //bundle
+        let budget = crate::convert_png("../test.png", false)
+            .unwrap()
+            .bytes
+            .len()
+            + crate::convert_png("../test.png", true).unwrap().bytes.len()
+            + crate::bundle::data_start(2, false, 0);
+        Manifest::parse(&format!(
+            r#"
+            budget = {budget}
+
+            [[sprites]]
+            input = "assets/a.png"
+            output = "a"
+            format = "c"
+            bundle = "all"
+
+            [[sprites]]
+            input = "assets/b.png"
+            compress = true
+            bundle = "all"
+
+            [bundles.all]
+            output = "all.wsb"
+            "#,
+        ))
+        .unwrap()
+        .build(&dir, 2)
+        .unwrap();
+
+        assert!(dir.join("a.h").exists());
+
+        let bytes = std::fs::read(dir.join("all.wsb")).unwrap();
+        assert_eq!(bytes.len(), budget);
+        let bundle = Bundle::new(&bytes).unwrap();
+        assert_eq!(bundle.len(), 2);
+        assert_eq!(
+            bundle.get(1).unwrap(),
+            &crate::convert_png("../test.png", true).unwrap().bytes[..]
+        );
+    }
+
+    #[test]
+    fn test_build_failure() {
+        let dir = std::env::temp_dir().join("w4-pnger-manifest-failure-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy("../test.png", dir.join("good.png")).unwrap();
+
+        //Five colors is one more than WASM-4 can draw
+        let mut encoder = png::Encoder::new(File::create(dir.join("bad.png")).unwrap(), 5, 1);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&[0, 60, 120, 180, 240])
+            .unwrap();
+
+        let result = Manifest::parse(
+            r#"
+            [[sprites]]
+            input = "*.png"
+            output = "sprites"
+            format = "rs"
+            "#,
+        )
+        .unwrap()
+        .build(&dir, 1);
+
+        assert!(result.is_err());
+        assert!(!dir.join("sprites.rs").exists());
+    }
+}
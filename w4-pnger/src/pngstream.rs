@@ -1,4 +1,5 @@
 use std::fs::File;
+use std::path::Path;
 
 use glob::{glob, Paths};
 use png::{Decoder, Reader};
@@ -26,16 +27,14 @@ impl Iterator for PngStream {
             Err(e) => return Some(Err(anyhow!(e))),
         };
 
-        let file = match File::open(&path) {
-            Ok(f) => f,
-            Err(e) => return Some(Err(anyhow!(e))),
-        };
+        Some(open_png(&path))
+    }
+}
 
-        let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+pub fn open_png(path: &Path) -> Result<(String, Reader<File>)> {
+    let file = File::open(path)?;
 
-        match Decoder::new(file).read_info() {
-            Ok(r) => Some(Ok((name, r))),
-            Err(e) => Some(Err(anyhow!(e))),
-        }
-    }
+    let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+
+    Ok((name, Decoder::new(file).read_info()?))
 }
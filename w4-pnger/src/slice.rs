@@ -0,0 +1,277 @@
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::wasm4png::IndexedSprite;
+
+//How the sprites cut from a sheet are named, after the sheet's file name
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SliceNaming {
+    //player_0.png, player_1.png, ... in reading order
+    #[default]
+    Index,
+    //player_0_0.png, player_0_1.png, ... by row, then column
+    Grid,
+}
+
+impl FromStr for SliceNaming {
+    type Err = anyhow::Error;
+
+    fn from_str(from: &str) -> Result<Self> {
+        match from {
+            "index" => Ok(SliceNaming::Index),
+            "grid" => Ok(SliceNaming::Grid),
+            _ => bail!("Invalid slice naming {from}, expected index or grid"),
+        }
+    }
+}
+
+//Cuts sprite sheets into a grid of equally sized sprites. The sheet is read as one image first,
+//so every sprite shares the sheet's palette and bits per pixel
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Slicer {
+    width: u16,
+    height: u16,
+    margin: u16,
+    spacing: u16,
+    frames: Vec<RangeInclusive<usize>>,
+    naming: SliceNaming,
+}
+
+impl Slicer {
+    pub fn new(width: u16, height: u16) -> Self {
+        Slicer {
+            width,
+            height,
+            margin: 0,
+            spacing: 0,
+            frames: Vec::new(),
+            naming: SliceNaming::Index,
+        }
+    }
+
+    //Pixels between the edge of the sheet and the first row and column of sprites
+    pub fn with_margin(mut self, margin: u16) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    //Pixels between neighbouring sprites
+    pub fn with_spacing(mut self, spacing: u16) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    //Keeps only the sprites whose index is in one of these ranges, or every sprite if empty
+    pub fn with_frames(mut self, frames: Vec<RangeInclusive<usize>>) -> Self {
+        self.frames = frames;
+        self
+    }
+
+    pub fn with_naming(mut self, naming: SliceNaming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    //Returns the sprites in reading order, each named after the sheet
+    pub fn slice(
+        &self,
+        sheet_name: &str,
+        sheet: &IndexedSprite,
+    ) -> Result<Vec<(String, IndexedSprite)>> {
+        if self.width == 0 || self.height == 0 {
+            bail!("Slices must be at least 1x1");
+        }
+
+        let columns = grid_count(sheet.width, self.width, self.margin, self.spacing);
+        let rows = grid_count(sheet.height, self.height, self.margin, self.spacing);
+        let count = columns * rows;
+        if count == 0 {
+            bail!(
+                "A {}x{} sheet is too small for {}x{} slices",
+                sheet.width,
+                sheet.height,
+                self.width,
+                self.height
+            );
+        }
+        if let Some(past) = self.frames.iter().find(|range| *range.end() >= count) {
+            bail!(
+                "Frames {}-{} go past the sheet's {count} slices",
+                past.start(),
+                past.end()
+            );
+        }
+
+        let path = Path::new(sheet_name);
+        let stem = path
+            .file_stem()
+            .map_or(sheet_name.into(), |s| s.to_string_lossy());
+        let extension = path
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+
+        let mut sprites = Vec::new();
+        for index in 0..count {
+            if !self.frames.is_empty() && !self.frames.iter().any(|r| r.contains(&index)) {
+                continue;
+            }
+
+            let (row, column) = (index / columns, index % columns);
+            let corner = self
+                .offset(column, self.width)
+                .zip(self.offset(row, self.height));
+            let Some((x, y)) = corner else {
+                bail!("Slice {index} is too far into the sheet");
+            };
+
+            let name = match self.naming {
+                SliceNaming::Index => format!("{stem}_{index}{extension}"),
+                SliceNaming::Grid => format!("{stem}_{row}_{column}{extension}"),
+            };
+            sprites.push((name, sheet.crop(x, y, self.width, self.height)?));
+        }
+
+        Ok(sprites)
+    }
+
+    //Where the slice in this row or column starts, or None if that overflows
+    fn offset(&self, position: usize, size: u16) -> Option<u32> {
+        let stride = size as u32 + self.spacing as u32;
+        u32::try_from(position)
+            .ok()?
+            .checked_mul(stride)?
+            .checked_add(self.margin.into())
+    }
+}
+
+//How many whole slices fit along one side of a sheet
+fn grid_count(size: u16, slice: u16, margin: u16, spacing: u16) -> usize {
+    (size.saturating_sub(margin) as usize + spacing as usize) / (slice as usize + spacing as usize)
+}
+
+//Parses a size like "16x16" into a width and height
+pub fn parse_dimensions(size: &str) -> Result<(u16, u16)> {
+    let (width, height) = size
+        .split_once('x')
+        .ok_or_else(|| anyhow!("{size} is not a size, expected WIDTHxHEIGHT"))?;
+
+    Ok((width.trim().parse()?, height.trim().parse()?))
+}
+
+//Parses comma separated frames and ranges of frames, like "0-3,8,10-12"
+pub fn parse_frames(frames: &str) -> Result<Vec<RangeInclusive<usize>>> {
+    frames
+        .split(',')
+        .map(|range| {
+            let range = range.trim();
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (start.trim().parse()?, end.trim().parse()?),
+                None => (range.parse()?, range.parse()?),
+            };
+            if end < start {
+                bail!("Frames {range} end before they start");
+            }
+
+            Ok(start..=end)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use w4_pnger_common::BitsPerPixel;
+
+    use crate::wasm4png::{IndexedSprite, W4Sprite};
+
+    use super::{parse_dimensions, parse_frames, SliceNaming, Slicer};
+
+    #[test]
+    fn test_slice() {
+        //A 3x2 grid of 4x4 sprites, each filled with its own index, with a 2 pixel margin and 2
+        //pixels between them
+        let (width, height) = (2 + 3 * 4 + 2 * 2 + 2, 2 + 2 * 4 + 2 + 2);
+        let indices: Vec<u8> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let (column, row) = ((x as i32 - 2) / 6, (y as i32 - 2) / 6);
+                let inside = x >= 2 && y >= 2 && (x - 2) % 6 < 4 && (y - 2) % 6 < 4;
+                if inside && column < 3 && row < 2 {
+                    (row * 3 + column) as u8 % 4
+                } else {
+                    3
+                }
+            })
+            .collect();
+        let sheet = IndexedSprite::new(width, height, BitsPerPixel::Two, indices).unwrap();
+
+        let slicer = Slicer::new(4, 4).with_margin(2).with_spacing(2);
+        let sprites = slicer.slice("sheet.png", &sheet).unwrap();
+        assert_eq!(sprites.len(), 6);
+        for (i, (name, sprite)) in sprites.iter().enumerate() {
+            assert_eq!(name, &format!("sheet_{i}.png"));
+            assert_eq!((sprite.width, sprite.height), (4, 4));
+            assert_eq!(sprite.bpp, BitsPerPixel::Two);
+            assert!(sprite.indices.iter().all(|&p| p == i as u8 % 4));
+        }
+
+        let sprites = slicer
+            .clone()
+            .with_frames(parse_frames("1-2,5").unwrap())
+            .with_naming(SliceNaming::Grid)
+            .slice("sheet.png", &sheet)
+            .unwrap();
+        let names: Vec<&str> = sprites.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["sheet_0_1.png", "sheet_0_2.png", "sheet_1_2.png"]);
+
+        assert!(slicer
+            .clone()
+            .with_frames(parse_frames("6").unwrap())
+            .slice("sheet.png", &sheet)
+            .is_err());
+        assert!(Slicer::new(32, 32).slice("sheet.png", &sheet).is_err());
+
+        //The width and spacing add up to more than a u16 holds
+        let sprites = Slicer::new(4, 4)
+            .with_spacing(u16::MAX)
+            .slice("sheet.png", &sheet)
+            .unwrap();
+        assert_eq!(sprites.len(), 1);
+
+        assert!(parse_frames("3-1").is_err());
+        assert_eq!(parse_dimensions("16x8").unwrap(), (16, 8));
+        assert!(parse_dimensions("16").is_err());
+    }
+
+    #[test]
+    fn test_slice_odd_width() {
+        //A row of three 4x2 sprites at 1 bpp, with a 1 pixel margin and spacing, so neither the
+        //sheet's rows nor the sprites' rows fill whole bytes. Sprite i has one dark pixel at i, 1
+        let (width, height) = (1 + 3 * 4 + 2 + 1, 1 + 2 + 1);
+        let indices: Vec<u8> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let dark = (0..3).any(|sprite| (x, y) == (1 + sprite * 5 + sprite, 2));
+                dark as u8
+            })
+            .collect();
+        let sheet = IndexedSprite::new(width, height, BitsPerPixel::One, indices).unwrap();
+
+        let sprites = Slicer::new(4, 2)
+            .with_margin(1)
+            .with_spacing(1)
+            .slice("sheet.png", &sheet)
+            .unwrap();
+        assert_eq!(sprites.len(), 3);
+        for (i, (_, sprite)) in sprites.iter().enumerate() {
+            let packed = sprite.pack();
+            assert_eq!(packed.get_bytes(), [0x08 >> i]);
+
+            let unpacked = W4Sprite::from_packed(4, 2, BitsPerPixel::One, packed.get_bytes());
+            assert_eq!(unpacked.unwrap().indices(), sprite.indices);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use w4_pnger_common::CompType;
+
+use crate::convert::ConvertedSprite;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    Rust,
+    C,
+    Zig,
+    AssemblyScript,
+    Go,
+    Odin,
+}
+
+impl Language {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Language::Rust => "rs",
+            Language::C => "h",
+            Language::Zig => "zig",
+            Language::AssemblyScript => "ts",
+            Language::Go => "go",
+            Language::Odin => "odin",
+        }
+    }
+
+    //The name of a sprite's data, which its other constants are named after
+    pub fn identifier(&self, words: &[String]) -> String {
+        match self {
+            Language::Rust => screaming_snake_case(words),
+            Language::C | Language::AssemblyScript => camel_case(words),
+            Language::Zig | Language::Odin => snake_case(words),
+            Language::Go => pascal_case(words),
+        }
+    }
+}
+
+//The package Go and Odin output goes in unless another is given
+pub const DEFAULT_PACKAGE: &str = "main";
+
+//Go and Odin output is put in package, which other languages ignore
+pub fn write_source<W: Write>(
+    out: &mut W,
+    language: Language,
+    package: &str,
+    sprites: &[ConvertedSprite],
+) -> Result<()> {
+    let names = identifiers(sprites, |words| language.identifier(words))?;
+
+    writeln!(out, "// Generated by w4-pnger, do not edit")?;
+
+    match language {
+        Language::C => {
+            writeln!(out, "#pragma once")?;
+            writeln!(out)?;
+            writeln!(out, "#include <stdint.h>")?;
+        }
+        Language::Go | Language::Odin => {
+            writeln!(out)?;
+            writeln!(out, "package {package}")?;
+        }
+        Language::Rust | Language::Zig | Language::AssemblyScript => (),
+    }
+
+    for (name, sprite) in names.iter().zip(sprites) {
+        writeln!(out)?;
+        writeln!(out, "// {}", sprite.name)?;
+        write_sprite(out, language, name, sprite)?;
+    }
+
+    Ok(())
+}
+
+fn write_sprite<W: Write>(
+    out: &mut W,
+    language: Language,
+    name: &str,
+    sprite: &ConvertedSprite,
+) -> Result<()> {
+    //Rust output is read back with w4-tiny-decomp, so it always keeps the .ws header. Other
+    //languages get png2src-compatible pixel data when there is nothing to decompress
+    let data = match language {
+        Language::Rust => &sprite.bytes[..],
+        _ => sprite.data(),
+    };
+
+    if language != Language::Rust && sprite.comp_type != CompType::Uncompressed {
+        writeln!(out, "// Compressed .ws data, decompress before blitting")?;
+    }
+
+    let (width, height, flags, len) = (sprite.width, sprite.height, sprite.flags, data.len());
+    let blit = if sprite.flags == 0 {
+        "BLIT_1BPP"
+    } else {
+        "BLIT_2BPP"
+    };
+
+    match language {
+        Language::Rust => {
+            writeln!(out, "pub const {name}_WIDTH: u32 = {width};")?;
+            writeln!(out, "pub const {name}_HEIGHT: u32 = {height};")?;
+            writeln!(out, "pub const {name}_FLAGS: u32 = {flags};")?;
+            writeln!(out, "pub const {name}: [u8; {len}] = [")?;
+            write_bytes(out, data, "    ", ",")?;
+            writeln!(out, "];")?;
+        }
+        Language::C => {
+            writeln!(out, "#define {name}Width {width}")?;
+            writeln!(out, "#define {name}Height {height}")?;
+            writeln!(out, "#define {name}Flags {flags} // {blit}")?;
+            writeln!(out, "static const uint8_t {name}[{len}] = {{")?;
+            write_bytes(out, data, "    ", ",")?;
+            writeln!(out, "}};")?;
+        }
+        Language::Zig => {
+            writeln!(out, "pub const {name}_width = {width};")?;
+            writeln!(out, "pub const {name}_height = {height};")?;
+            writeln!(out, "pub const {name}_flags = {flags}; // {blit}")?;
+            writeln!(out, "pub const {name} = [{len}]u8{{")?;
+            write_bytes(out, data, "    ", ",")?;
+            writeln!(out, "}};")?;
+        }
+        Language::AssemblyScript => {
+            writeln!(out, "export const {name}Width = {width};")?;
+            writeln!(out, "export const {name}Height = {height};")?;
+            writeln!(out, "export const {name}Flags = {flags}; // {blit}")?;
+            writeln!(out, "export const {name} = memory.data<u8>([")?;
+            write_bytes(out, data, "    ", ",")?;
+            writeln!(out, "]);")?;
+        }
+        Language::Go => {
+            writeln!(out, "const {name}Width = {width}")?;
+            writeln!(out, "const {name}Height = {height}")?;
+            writeln!(out, "const {name}Flags = {flags} // {blit}")?;
+            writeln!(out, "var {name} = [{len}]byte{{")?;
+            write_bytes(out, data, "\t", ",")?;
+            writeln!(out, "}}")?;
+        }
+        Language::Odin => {
+            writeln!(out, "{name}_width :: {width}")?;
+            writeln!(out, "{name}_height :: {height}")?;
+            writeln!(out, "{name}_flags :: {flags} // {blit}")?;
+            writeln!(out, "{name} := [{len}]u8{{")?;
+            write_bytes(out, data, "\t", ",")?;
+            writeln!(out, "}}")?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_bytes<W: Write>(out: &mut W, bytes: &[u8], indent: &str, separator: &str) -> Result<()> {
+    for line in bytes.chunks(16) {
+        let line: Vec<String> = line
+            .iter()
+            .map(|b| format!("0x{b:02x}{separator}"))
+            .collect();
+        writeln!(out, "{indent}{}", line.join(" "))?;
+    }
+
+    Ok(())
+}
+
+//Names every sprite in the given style, failing if two sprites end up with the same name, as
+//player-idle.png and player_idle.png would
+pub fn identifiers(
+    sprites: &[ConvertedSprite],
+    style: impl Fn(&[String]) -> String,
+) -> Result<Vec<String>> {
+    let mut seen: HashMap<String, &str> = HashMap::new();
+
+    sprites
+        .iter()
+        .map(|sprite| {
+            let name = style(&words(&sprite.name));
+            if let Some(other) = seen.insert(name.clone(), &sprite.name) {
+                bail!("{other} and {} would both be named {name}", sprite.name);
+            }
+            Ok(name)
+        })
+        .collect()
+}
+
+//Splits an image file name like "player-idle.png" into ["player", "idle"]
+pub fn words(image_name: &str) -> Vec<String> {
+    let stem = Path::new(image_name)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(image_name);
+
+    let mut words: Vec<String> = stem
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_ascii_lowercase())
+        .collect();
+
+    if words.is_empty() {
+        words.push("sprite".to_owned());
+    }
+
+    words
+}
+
+fn fix_leading_digit(mut name: String) -> String {
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+
+    name
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+        None => String::new(),
+    }
+}
+
+pub fn screaming_snake_case(words: &[String]) -> String {
+    fix_leading_digit(words.join("_").to_ascii_uppercase())
+}
+
+pub fn snake_case(words: &[String]) -> String {
+    fix_leading_digit(words.join("_"))
+}
+
+pub fn camel_case(words: &[String]) -> String {
+    let rest: String = words[1..].iter().map(|w| capitalize(w)).collect();
+    fix_leading_digit(words[0].clone() + &rest)
+}
+
+pub fn pascal_case(words: &[String]) -> String {
+    fix_leading_digit(words.iter().map(|w| capitalize(w)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use w4_pnger_common::CompType;
+
+    use crate::convert::ConvertedSprite;
+
+    use super::{camel_case, pascal_case, screaming_snake_case, words, write_source, Language};
+
+    fn dot(comp_type: CompType) -> ConvertedSprite {
+        ConvertedSprite {
+            name: "dot.png".to_owned(),
+            width: 8,
+            height: 1,
+            flags: 0,
+            comp_type,
+            bytes: vec![comp_type as u8, 8, 1, 0, 0x18],
+        }
+    }
+
+    fn render(language: Language, sprite: ConvertedSprite) -> String {
+        let mut out = Vec::new();
+        write_source(&mut out, language, "main", &[sprite]).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(screaming_snake_case(&words("player.png")), "PLAYER");
+        assert_eq!(
+            screaming_snake_case(&words("player-idle 2.png")),
+            "PLAYER_IDLE_2"
+        );
+        assert_eq!(screaming_snake_case(&words("8ball.png")), "_8BALL");
+        assert_eq!(camel_case(&words("Player_Idle.png")), "playerIdle");
+        assert_eq!(pascal_case(&words("player_idle.png")), "PlayerIdle");
+    }
+
+    #[test]
+    fn test_write_rust() {
+        assert_eq!(
+            render(Language::Rust, dot(CompType::Uncompressed)),
+            "// Generated by w4-pnger, do not edit\n\
+            \n\
+            // dot.png\n\
+            pub const DOT_WIDTH: u32 = 8;\n\
+            pub const DOT_HEIGHT: u32 = 1;\n\
+            pub const DOT_FLAGS: u32 = 0;\n\
+            pub const DOT: [u8; 5] = [\n    0x00, 0x08, 0x01, 0x00, 0x18,\n];\n"
+        );
+    }
+
+    #[test]
+    fn test_write_c() {
+        assert_eq!(
+            render(Language::C, dot(CompType::Uncompressed)),
+            "// Generated by w4-pnger, do not edit\n\
+            #pragma once\n\
+            \n\
+            #include <stdint.h>\n\
+            \n\
+            // dot.png\n\
+            #define dotWidth 8\n\
+            #define dotHeight 1\n\
+            #define dotFlags 0 // BLIT_1BPP\n\
+            static const uint8_t dot[1] = {\n    0x18,\n};\n"
+        );
+    }
+
+    #[test]
+    fn test_compressed_keeps_header() {
+        let out = render(Language::Zig, dot(CompType::Pk));
+        assert!(out.contains("// Compressed .ws data, decompress before blitting\n"));
+        assert!(out.contains("pub const dot = [5]u8{\n    0x01, 0x08, 0x01, 0x00, 0x18,\n};\n"));
+    }
+
+    #[test]
+    fn test_go_package() {
+        let mut out = Vec::new();
+        write_source(
+            &mut out,
+            Language::Go,
+            "sprites",
+            &[dot(CompType::Uncompressed)],
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("// Generated by w4-pnger, do not edit\n\npackage sprites\n"));
+        assert!(out.contains("var Dot = [1]byte{\n\t0x18,\n}\n"));
+    }
+
+    #[test]
+    fn test_name_collision() {
+        let mut a = dot(CompType::Uncompressed);
+        a.name = "player-idle.png".to_owned();
+        let mut b = dot(CompType::Uncompressed);
+        b.name = "player_idle.png".to_owned();
+
+        for language in [Language::Rust, Language::Go] {
+            let sprites = [a.clone(), b.clone()];
+            assert!(write_source(&mut Vec::new(), language, "main", &sprites).is_err());
+        }
+        b.name = "player_run.png".to_owned();
+        assert!(write_source(&mut Vec::new(), Language::Go, "main", &[a, b]).is_ok());
+    }
+}
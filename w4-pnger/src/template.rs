@@ -0,0 +1,408 @@
+use anyhow::{anyhow, bail, Result};
+use w4_pnger_common::CompType;
+
+use crate::convert::ConvertedSprite;
+use crate::source::{camel_case, pascal_case, screaming_snake_case, snake_case, words};
+
+//A small mustache-like templating language for sprite output.
+//
+//  {{#sprites}} ... {{/sprites}}    repeated once per sprite
+//  {{#compressed}} ... {{/compressed}}, {{^compressed}} ... {{/compressed}}
+//  {{^last}} ... {{/last}}          skipped for the final sprite, for separators
+//  {{name}}                         the sprite name, with an optional case modifier:
+//                                   {{name:camel}}, {{name:pascal}}, {{name:snake}},
+//                                   {{name:screaming}}
+//  {{file}}, {{width}}, {{height}}, {{flags}}, {{bpp}}, {{blit}}, {{compression}},
+//  {{length}}, {{index}}
+//  {{bytes}}                        the sprite's data as the source outputs write it, so
+//                                   plain pixels when uncompressed and .ws bytes otherwise,
+//                                   configured with options:
+//                                   per_line=16 indent="    " separator=", "
+//                                   format=hex|HEX|dec
+//
+//Section tags which sit alone on a line don't leave an empty line behind.
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+enum Node {
+    Text(String),
+    Var(Var),
+    Section {
+        name: String,
+        inverted: bool,
+        children: Vec<Node>,
+    },
+}
+
+struct Var {
+    key: String,
+    modifier: Option<String>,
+    options: Vec<(String, String)>,
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut stack: Vec<(String, bool, Vec<Node>)> = Vec::new();
+        let mut nodes = Vec::new();
+        let mut rest = source;
+        //Whether nothing but whitespace has been seen since the last newline
+        let mut line_clean = true;
+
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..]
+                .find("}}")
+                .map(|e| start + e)
+                .ok_or_else(|| anyhow!("Unclosed tag at \"{}\"", snippet(&rest[start..])))?;
+
+            let tag = rest[start + 2..end].trim();
+            let mut text = &rest[..start];
+            let mut after = end + 2;
+
+            let mut standalone = false;
+            if tag.starts_with(['#', '^', '/']) {
+                //Standalone section tags swallow their own line
+                let line_start = text.rfind('\n').map(|i| i + 1).unwrap_or(0);
+                let line_end = rest[after..].find('\n').map(|i| after + i + 1);
+                let before_blank =
+                    text[line_start..].trim().is_empty() && (line_clean || text.contains('\n'));
+                let after_blank = match line_end {
+                    Some(line_end) => rest[after..line_end].trim().is_empty(),
+                    None => rest[after..].trim().is_empty(),
+                };
+                if before_blank && after_blank {
+                    text = &text[..line_start];
+                    after = line_end.unwrap_or(rest.len());
+                    standalone = true;
+                }
+            }
+            line_clean = standalone;
+
+            if !text.is_empty() {
+                nodes.push(Node::Text(text.to_owned()));
+            }
+
+            if let Some(name) = tag.strip_prefix('#') {
+                stack.push((name.trim().to_owned(), false, std::mem::take(&mut nodes)));
+            } else if let Some(name) = tag.strip_prefix('^') {
+                stack.push((name.trim().to_owned(), true, std::mem::take(&mut nodes)));
+            } else if let Some(name) = tag.strip_prefix('/') {
+                let (open, inverted, parent) = stack.pop().ok_or_else(|| {
+                    anyhow!("Closing tag {{{{{tag}}}}} has no matching opening tag")
+                })?;
+                if open != name.trim() {
+                    bail!("Expected {{{{/{open}}}}}, found {{{{{tag}}}}}");
+                }
+                let children = std::mem::replace(&mut nodes, parent);
+                nodes.push(Node::Section {
+                    name: open,
+                    inverted,
+                    children,
+                });
+            } else {
+                nodes.push(Node::Var(parse_var(tag)?));
+            }
+
+            rest = &rest[after..];
+        }
+
+        if let Some((open, _, _)) = stack.last() {
+            bail!("Section {{{{#{open}}}}} is never closed");
+        }
+
+        if !rest.is_empty() {
+            nodes.push(Node::Text(rest.to_owned()));
+        }
+
+        Ok(Template { nodes })
+    }
+
+    pub fn render(&self, sprites: &[ConvertedSprite]) -> Result<String> {
+        let mut out = String::new();
+        render_nodes(&self.nodes, sprites, None, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn snippet(s: &str) -> &str {
+    match s.char_indices().nth(16) {
+        Some((i, _)) => &s[..i],
+        None => s,
+    }
+}
+
+fn parse_var(tag: &str) -> Result<Var> {
+    let mut tokens = tokenize(tag)?.into_iter();
+
+    let head = tokens.next().ok_or_else(|| anyhow!("Empty tag {{{{}}}}"))?;
+    let (key, modifier) = match head.split_once(':') {
+        Some((key, modifier)) => (key.to_owned(), Some(modifier.to_owned())),
+        None => (head, None),
+    };
+
+    let options = tokens
+        .map(|token| match token.split_once('=') {
+            Some((option, value)) => Ok((option.to_owned(), value.to_owned())),
+            None => Err(anyhow!(
+                "Expected option=value in {{{{{tag}}}}}, found {token}"
+            )),
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(Var {
+        key,
+        modifier,
+        options,
+    })
+}
+
+//Splits on whitespace, keeping "quoted strings" together and handling \n, \t, \" and \\
+fn tokenize(tag: &str) -> Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut in_token = false;
+    let mut chars = tag.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_token = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => token.push('\n'),
+                            Some('t') => token.push('\t'),
+                            Some(c) => token.push(c),
+                            None => bail!("Unterminated string in {{{{{tag}}}}}"),
+                        },
+                        Some(c) => token.push(c),
+                        None => bail!("Unterminated string in {{{{{tag}}}}}"),
+                    }
+                }
+            }
+            c if c.is_whitespace() => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut token));
+                    in_token = false;
+                }
+            }
+            c => {
+                in_token = true;
+                token.push(c);
+            }
+        }
+    }
+
+    if in_token {
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    sprites: &[ConvertedSprite],
+    current: Option<usize>,
+    out: &mut String,
+) -> Result<()> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var(var) => {
+                let index = current.ok_or_else(|| {
+                    anyhow!("{{{{{}}}}} used outside of {{{{#sprites}}}}", var.key)
+                })?;
+                render_var(var, &sprites[index], index, out)?;
+            }
+            Node::Section {
+                name,
+                inverted,
+                children,
+            } => {
+                if name == "sprites" {
+                    if *inverted {
+                        if sprites.is_empty() {
+                            render_nodes(children, sprites, current, out)?;
+                        }
+                    } else {
+                        for index in 0..sprites.len() {
+                            render_nodes(children, sprites, Some(index), out)?;
+                        }
+                    }
+                    continue;
+                }
+
+                let index = current
+                    .ok_or_else(|| anyhow!("{{{{#{name}}}}} used outside of {{{{#sprites}}}}"))?;
+                let value = match name.as_str() {
+                    "compressed" => sprites[index].comp_type != CompType::Uncompressed,
+                    "last" => index == sprites.len() - 1,
+                    "first" => index == 0,
+                    _ => bail!("Unknown section {{{{#{name}}}}}"),
+                };
+
+                if value != *inverted {
+                    render_nodes(children, sprites, current, out)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn render_var(var: &Var, sprite: &ConvertedSprite, index: usize, out: &mut String) -> Result<()> {
+    if var.key != "name" && var.modifier.is_some() {
+        bail!("Only {{{{name}}}} takes a case modifier");
+    }
+    if var.key != "bytes" && !var.options.is_empty() {
+        bail!("Only {{{{bytes}}}} takes options");
+    }
+
+    let value = match var.key.as_str() {
+        "name" => {
+            let words = words(&sprite.name);
+            match var.modifier.as_deref() {
+                None => words.join("_"),
+                Some("camel") => camel_case(&words),
+                Some("pascal") => pascal_case(&words),
+                Some("snake") => snake_case(&words),
+                Some("screaming") => screaming_snake_case(&words),
+                Some(m) => bail!("Unknown name modifier {m}"),
+            }
+        }
+        "file" => sprite.name.clone(),
+        "width" => sprite.width.to_string(),
+        "height" => sprite.height.to_string(),
+        "flags" => sprite.flags.to_string(),
+        "bpp" => (sprite.flags + 1).to_string(),
+        "blit" => if sprite.flags == 0 {
+            "BLIT_1BPP"
+        } else {
+            "BLIT_2BPP"
+        }
+        .to_owned(),
+        "compression" => match sprite.comp_type {
+            CompType::Uncompressed => "none",
+            CompType::Pk => "pk",
+            CompType::Lz => "lz",
+            CompType::Huffman => "huffman",
+            CompType::LzDict => "lz-dict",
+        }
+        .to_owned(),
+        "length" => sprite.data().len().to_string(),
+        "index" => index.to_string(),
+        "bytes" => format_bytes(sprite.data(), &var.options)?,
+        key => bail!("Unknown variable {{{{{key}}}}}"),
+    };
+
+    out.push_str(&value);
+
+    Ok(())
+}
+
+fn format_bytes(bytes: &[u8], options: &[(String, String)]) -> Result<String> {
+    let mut per_line = 0;
+    let mut indent = "";
+    let mut separator = ", ";
+    let mut format = "hex";
+
+    for (option, value) in options {
+        match option.as_str() {
+            "per_line" => per_line = value.parse()?,
+            "indent" => indent = value,
+            "separator" => separator = value,
+            "format" => format = value,
+            _ => bail!("Unknown bytes option {option}"),
+        }
+    }
+
+    let formatted: Vec<String> = bytes
+        .iter()
+        .map(|b| match format {
+            "hex" => Ok(format!("0x{b:02x}")),
+            "HEX" => Ok(format!("0x{b:02X}")),
+            "dec" => Ok(b.to_string()),
+            _ => Err(anyhow!("Unknown bytes format {format}")),
+        })
+        .collect::<Result<_>>()?;
+
+    if per_line == 0 {
+        return Ok(formatted.join(separator));
+    }
+
+    //Separators stay at the end of each line so the output drops into array literals
+    let lines: Vec<String> = formatted
+        .chunks(per_line)
+        .map(|line| indent.to_owned() + &line.join(separator))
+        .collect();
+
+    Ok(lines.join(&(separator.trim_end().to_owned() + "\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use w4_pnger_common::CompType;
+
+    use crate::convert::ConvertedSprite;
+
+    use super::Template;
+
+    fn sprites() -> Vec<ConvertedSprite> {
+        vec![
+            ConvertedSprite {
+                name: "big-dot.png".to_owned(),
+                width: 8,
+                height: 1,
+                flags: 0,
+                comp_type: CompType::Uncompressed,
+                bytes: vec![0, 8, 1, 0, 0x3c],
+            },
+            ConvertedSprite {
+                name: "small_dot.png".to_owned(),
+                width: 8,
+                height: 1,
+                flags: 1,
+                comp_type: CompType::Pk,
+                bytes: vec![1, 8, 1, 1, 0, 4, 0xff],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_render() {
+        let template = Template::parse(
+            "// {{#sprites}}{{file}}{{^last}}, {{/last}}{{/sprites}}\n\
+            {{#sprites}}\n\
+            const {{name:screaming}}: [u8; {{length}}] = [\n\
+            {{bytes per_line=4 indent=\"  \" separator=\", \"}}\n\
+            ]; // {{width}}x{{height}} {{blit}} {{#compressed}}{{compression}}{{/compressed}}\n\
+            {{/sprites}}\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            template.render(&sprites()).unwrap(),
+            "// big-dot.png, small_dot.png\n\
+            const BIG_DOT: [u8; 1] = [\n  0x3c\n]; // 8x1 BLIT_1BPP \n\
+            const SMALL_DOT: [u8; 7] = [\n  0x01, 0x08, 0x01, 0x01,\n  0x00, 0x04, 0xff\n]; // 8x1 BLIT_2BPP pk\n"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Template::parse("{{#sprites}}").is_err());
+        assert!(Template::parse("{{#sprites}}{{/last}}").is_err());
+        assert!(Template::parse("{{bytes per_line}}").is_err());
+        assert!(Template::parse("{{width}}")
+            .unwrap()
+            .render(&sprites())
+            .is_err());
+        assert!(Template::parse("{{#sprites}}{{size}}{{/sprites}}")
+            .unwrap()
+            .render(&sprites())
+            .is_err());
+    }
+}
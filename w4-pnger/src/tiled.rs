@@ -0,0 +1,446 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use roxmltree::{Document, Node};
+use serde::Deserialize;
+
+//Tiled keeps whether a tile is flipped or rotated in the top bits of its id
+pub const FLIP_FLAGS: u32 = 0xf000_0000;
+
+//The parts of a Tiled map (.tmx or .tmj) needed to build a tilemap from it
+pub struct TiledMap {
+    pub width: u32,
+    pub height: u32,
+    pub tilesets: Vec<Tileset>,
+    pub layers: Vec<TileLayer>,
+}
+
+pub struct Tileset {
+    //The id of the first tile in the tileset, which the map's tile ids count from
+    pub first_gid: u32,
+    pub image: PathBuf,
+    pub tile_width: u16,
+    pub tile_height: u16,
+    pub margin: u16,
+    pub spacing: u16,
+    pub columns: u32,
+}
+
+pub struct TileLayer {
+    pub name: String,
+    //False if the layer or any group it's in is hidden
+    pub visible: bool,
+    //The id of every tile, row by row, with 0 for empty cells
+    pub tiles: Vec<u32>,
+}
+
+impl TiledMap {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        match extension(path).as_str() {
+            "tmx" => Self::from_tmx(&text, dir),
+            "tmj" | "json" => Self::from_tmj(&text, dir),
+            _ => bail!("{} is not a .tmx or .tmj map", path.display()),
+        }
+    }
+
+    //Reads a map saved as XML, with paths relative to dir
+    pub fn from_tmx(text: &str, dir: &Path) -> Result<Self> {
+        let doc = Document::parse(text)?;
+        let map = doc.root_element();
+        if !map.has_tag_name("map") {
+            bail!("Expected a <map>, found <{}>", map.tag_name().name());
+        }
+        if map.attribute("infinite") == Some("1") {
+            bail!("Infinite maps aren't supported");
+        }
+
+        let tilesets = map
+            .children()
+            .filter(|node| node.has_tag_name("tileset"))
+            .map(|node| {
+                let first_gid = attr(node, "firstgid")?;
+                match node.attribute("source") {
+                    Some(source) => Tileset::from_path(dir.join(source), first_gid),
+                    None => Tileset::from_tsx_node(node, dir, first_gid),
+                }
+            })
+            .collect::<Result<_>>()?;
+
+        let layers = map
+            .descendants()
+            .filter(|node| node.has_tag_name("layer"))
+            .map(|node| {
+                let data = node
+                    .children()
+                    .find(|child| child.has_tag_name("data"))
+                    .ok_or_else(|| anyhow!("<layer> has no <data>"))?;
+
+                let tiles = match data.attribute("encoding") {
+                    None => data
+                        .children()
+                        .filter(|child| child.has_tag_name("tile"))
+                        .map(|tile| Ok(tile.attribute("gid").unwrap_or("0").parse()?))
+                        .collect::<Result<_>>()?,
+                    encoding => decode_tiles(
+                        data.text().unwrap_or_default(),
+                        encoding,
+                        data.attribute("compression"),
+                    )?,
+                };
+
+                Ok(TileLayer {
+                    name: node.attribute("name").unwrap_or_default().to_owned(),
+                    visible: node
+                        .ancestors()
+                        .all(|node| node.attribute("visible") != Some("0")),
+                    tiles,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(TiledMap {
+            width: attr(map, "width")?,
+            height: attr(map, "height")?,
+            tilesets,
+            layers,
+        })
+    }
+
+    //Reads a map saved as JSON, with paths relative to dir
+    pub fn from_tmj(text: &str, dir: &Path) -> Result<Self> {
+        let map: TmjMap = serde_json::from_str(text)?;
+        if map.infinite {
+            bail!("Infinite maps aren't supported");
+        }
+
+        let tilesets = map
+            .tilesets
+            .into_iter()
+            .map(|tileset| {
+                let first_gid = tileset.firstgid;
+                match &tileset.source {
+                    Some(source) => Tileset::from_path(dir.join(source), first_gid),
+                    None => tileset.into_tileset(dir, first_gid),
+                }
+            })
+            .collect::<Result<_>>()?;
+
+        let mut layers = Vec::new();
+        flatten_layers(map.layers, true, &mut layers)?;
+
+        Ok(TiledMap {
+            width: map.width,
+            height: map.height,
+            tilesets,
+            layers,
+        })
+    }
+
+    //The layer with this name, or the first visible tile layer
+    pub fn layer(&self, name: Option<&str>) -> Result<&TileLayer> {
+        match name {
+            Some(name) => self
+                .layers
+                .iter()
+                .find(|layer| layer.name == name)
+                .ok_or_else(|| anyhow!("The map has no tile layer named {name}")),
+            None => self
+                .layers
+                .iter()
+                .find(|layer| layer.visible)
+                .ok_or_else(|| anyhow!("The map has no visible tile layers")),
+        }
+    }
+
+    //The tileset a tile id belongs to
+    pub fn tileset_for(&self, gid: u32) -> Option<&Tileset> {
+        self.tilesets
+            .iter()
+            .filter(|tileset| tileset.first_gid <= gid)
+            .max_by_key(|tileset| tileset.first_gid)
+    }
+}
+
+impl Tileset {
+    //Reads an external tileset, a .tsx or .tsj file
+    pub fn from_path<P: AsRef<Path>>(path: P, first_gid: u32) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read tileset {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        match extension(path).as_str() {
+            "tsx" => {
+                let doc = Document::parse(&text)?;
+                Self::from_tsx_node(doc.root_element(), dir, first_gid)
+            }
+            "tsj" | "json" => {
+                serde_json::from_str::<TmjTileset>(&text)?.into_tileset(dir, first_gid)
+            }
+            _ => bail!("{} is not a .tsx or .tsj tileset", path.display()),
+        }
+    }
+
+    fn from_tsx_node(node: Node, dir: &Path, first_gid: u32) -> Result<Self> {
+        let image = node
+            .children()
+            .find(|child| child.has_tag_name("image"))
+            .and_then(|image| image.attribute("source"))
+            .ok_or_else(|| {
+                anyhow!("Tilesets need a single image, collections of images aren't supported")
+            })?;
+
+        Ok(Tileset {
+            first_gid,
+            image: dir.join(image),
+            tile_width: attr(node, "tilewidth")?,
+            tile_height: attr(node, "tileheight")?,
+            margin: node.attribute("margin").unwrap_or("0").parse()?,
+            spacing: node.attribute("spacing").unwrap_or("0").parse()?,
+            columns: attr(node, "columns")?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct TmjMap {
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    infinite: bool,
+    tilesets: Vec<TmjTileset>,
+    layers: Vec<TmjLayer>,
+}
+
+#[derive(Deserialize)]
+struct TmjTileset {
+    //Missing from .tsj files, where the map says where the tileset starts
+    #[serde(default)]
+    firstgid: u32,
+    source: Option<String>,
+    image: Option<String>,
+    tilewidth: Option<u16>,
+    tileheight: Option<u16>,
+    #[serde(default)]
+    margin: u16,
+    #[serde(default)]
+    spacing: u16,
+    columns: Option<u32>,
+}
+
+impl TmjTileset {
+    fn into_tileset(self, dir: &Path, first_gid: u32) -> Result<Tileset> {
+        let missing = |key| anyhow!("Tileset is missing {key}");
+
+        Ok(Tileset {
+            first_gid,
+            image: dir.join(self.image.ok_or_else(|| {
+                anyhow!("Tilesets need a single image, collections of images aren't supported")
+            })?),
+            tile_width: self.tilewidth.ok_or_else(|| missing("tilewidth"))?,
+            tile_height: self.tileheight.ok_or_else(|| missing("tileheight"))?,
+            margin: self.margin,
+            spacing: self.spacing,
+            columns: self.columns.ok_or_else(|| missing("columns"))?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct TmjLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default = "visible_by_default")]
+    visible: bool,
+    data: Option<TmjData>,
+    encoding: Option<String>,
+    compression: Option<String>,
+    //The layers in a group
+    #[serde(default)]
+    layers: Vec<TmjLayer>,
+}
+
+fn visible_by_default() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TmjData {
+    Tiles(Vec<u32>),
+    Encoded(String),
+}
+
+//Collects the tile layers from inside groups, which are hidden along with their group
+fn flatten_layers(layers: Vec<TmjLayer>, visible: bool, out: &mut Vec<TileLayer>) -> Result<()> {
+    for layer in layers {
+        let visible = visible && layer.visible;
+        match layer.kind.as_str() {
+            "tilelayer" => {
+                let tiles = match layer.data {
+                    Some(TmjData::Tiles(tiles)) => tiles,
+                    Some(TmjData::Encoded(data)) => decode_tiles(
+                        &data,
+                        layer.encoding.as_deref(),
+                        layer.compression.as_deref(),
+                    )?,
+                    None => bail!("Tile layer {} has no data", layer.name),
+                };
+                out.push(TileLayer {
+                    name: layer.name,
+                    visible,
+                    tiles,
+                });
+            }
+            "group" => flatten_layers(layer.layers, visible, out)?,
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+//Reads tile ids saved as csv, or as little endian u32s in base64, optionally compressed
+fn decode_tiles(data: &str, encoding: Option<&str>, compression: Option<&str>) -> Result<Vec<u32>> {
+    match encoding {
+        Some("csv") => data
+            .split(',')
+            .map(|id| {
+                id.trim()
+                    .parse()
+                    .with_context(|| format!("{} is not a tile id", id.trim()))
+            })
+            .collect(),
+        Some("base64") => {
+            let bytes = decode_base64(data)?;
+            let bytes = match compression.unwrap_or_default() {
+                "" => bytes,
+                "zlib" => {
+                    let mut out = Vec::new();
+                    ZlibDecoder::new(&bytes[..]).read_to_end(&mut out)?;
+                    out
+                }
+                "gzip" => {
+                    let mut out = Vec::new();
+                    GzDecoder::new(&bytes[..]).read_to_end(&mut out)?;
+                    out
+                }
+                other => bail!(
+                    "Tile layers compressed with {other} aren't supported, save the map as csv or zlib instead"
+                ),
+            };
+
+            if bytes.len() % 4 != 0 {
+                bail!("Tile layer data isn't a whole number of tile ids");
+            }
+            Ok(bytes
+                .chunks(4)
+                .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+                .collect())
+        }
+        Some(other) => bail!("Unknown tile layer encoding {other}"),
+        None => bail!("Tile layer data has no encoding"),
+    }
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+
+    for c in data
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b'=')
+    {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => bail!("{} is not valid base64", c as char),
+        };
+
+        bits = bits << 6 | value as u32;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            out.push((bits >> bit_count) as u8);
+        }
+    }
+
+    Ok(out)
+}
+
+fn attr<T: FromStr>(node: Node, name: &str) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let value = node
+        .attribute(name)
+        .ok_or_else(|| anyhow!("<{}> is missing {name}", node.tag_name().name()))?;
+    value
+        .parse()
+        .with_context(|| format!("{name} on <{}> is not valid", node.tag_name().name()))
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{decode_base64, TiledMap};
+
+    #[test]
+    fn test_read_maps() {
+        let tmx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" width="3" height="2" tilewidth="8" tileheight="8" infinite="0">
+ <tileset firstgid="1" name="tiles" tilewidth="8" tileheight="8" spacing="1" columns="4">
+  <image source="tiles.png" width="35" height="8"/>
+ </tileset>
+ <group name="hidden" visible="0">
+  <layer name="below" width="3" height="2"><data encoding="csv">1,1,1,1,1,1</data></layer>
+ </group>
+ <layer name="ground" width="3" height="2">
+  <data encoding="base64" compression="zlib">eJxjYGBgYARiJgYIYAZiFiAGAAB0AAs=</data>
+ </layer>
+</map>"#;
+        let map = TiledMap::from_tmx(tmx, Path::new("levels")).unwrap();
+        assert_eq!((map.width, map.height), (3, 2));
+        assert_eq!(map.tilesets[0].image, Path::new("levels/tiles.png"));
+        assert_eq!((map.tilesets[0].spacing, map.tilesets[0].columns), (1, 4));
+        assert!(!map.layers[0].visible);
+        assert_eq!(map.layer(None).unwrap().name, "ground");
+        assert_eq!(map.layer(None).unwrap().tiles, [0, 1, 2, 0, 3, 4]);
+
+        let tmj = r#"{"width": 3, "height": 2, "infinite": false,
+            "tilesets": [{"firstgid": 1, "image": "tiles.png", "tilewidth": 8, "tileheight": 8, "columns": 4}],
+            "layers": [
+                {"type": "objectgroup", "name": "things", "objects": []},
+                {"type": "group", "name": "level", "layers": [
+                    {"type": "tilelayer", "name": "ground", "visible": true, "data": [0, 1, 2, 0, 3, 4]}
+                ]}
+            ]}"#;
+        let map = TiledMap::from_tmj(tmj, Path::new("")).unwrap();
+        assert_eq!(map.layer(Some("ground")).unwrap().tiles, [0, 1, 2, 0, 3, 4]);
+        assert!(map.layer(Some("sky")).is_err());
+        assert_eq!(map.tileset_for(3).unwrap().first_gid, 1);
+
+        assert_eq!(decode_base64("AQID\nBA==").unwrap(), [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use w4_pnger_common::{BitsPerPixel, TILEMAP_HEADER_SIZE, TILEMAP_WIDE_CELLS};
+
+use crate::bundle::write_bundle;
+use crate::compress::Method;
+use crate::convert::ConvertedSprite;
+use crate::convert_sprite_with;
+use crate::tiled::{TileLayer, TiledMap, FLIP_FLAGS};
+use crate::wasm4png::{IndexedSprite, W4Sprite};
+
+//Rows are stored as 8 pixel wide sprites at 1 bpp, one byte to a pixel row, and sprites can be
+//at most 255 pixels tall
+const MAX_ROW_BYTES: usize = u8::MAX as usize;
+
+//A tile layer, with every tile it uses cut out of its tileset
+pub struct Tilemap {
+    pub width: u16,
+    pub height: u16,
+    //0 where the layer is empty, and n + 1 for tiles[n]
+    pub cells: Vec<u16>,
+    pub tiles: Vec<W4Sprite>,
+}
+
+impl Tilemap {
+    //Tiles are kept in the order they first appear, and tiles which look the same share a cell
+    //value even if they come from different places in the tileset. Tiles the layer doesn't use
+    //are left out
+    pub fn from_layer(map: &TiledMap, layer: &TileLayer) -> Result<Self> {
+        let width: u16 = map.width.try_into().context("The map is too wide")?;
+        let height: u16 = map.height.try_into().context("The map is too tall")?;
+        if layer.tiles.len() != width as usize * height as usize {
+            bail!(
+                "Layer {} has {} tiles, but the map is {width}x{height}",
+                layer.name,
+                layer.tiles.len()
+            );
+        }
+
+        let mut sheets: HashMap<u32, IndexedSprite> = HashMap::new();
+        let mut gid_cells: HashMap<u32, u16> = HashMap::new();
+        let mut seen: HashMap<(u8, Vec<u8>), u16> = HashMap::new();
+        let mut tiles: Vec<W4Sprite> = Vec::new();
+        let mut cells = Vec::with_capacity(layer.tiles.len());
+
+        for (i, &gid) in layer.tiles.iter().enumerate() {
+            let (x, y) = (i % width as usize, i / width as usize);
+            if gid == 0 {
+                cells.push(0);
+                continue;
+            }
+            if gid & FLIP_FLAGS != 0 {
+                bail!("The tile at {x}, {y} is flipped or rotated, which tilemaps don't support");
+            }
+            if let Some(&cell) = gid_cells.get(&gid) {
+                cells.push(cell);
+                continue;
+            }
+
+            let Some(tileset) = map.tileset_for(gid) else {
+                bail!("The tile at {x}, {y} isn't in any tileset");
+            };
+            let sheet = match sheets.entry(tileset.first_gid) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(IndexedSprite::from_path(&tileset.image).with_context(|| {
+                        format!("Failed to convert tileset {}", tileset.image.display())
+                    })?)
+                }
+            };
+
+            let id = gid - tileset.first_gid;
+            let (column, row) = (id % tileset.columns.max(1), id / tileset.columns.max(1));
+            //Tiles are cut from the tileset's palette indices, as packed rows don't always start
+            //on a byte
+            let tile_x = column
+                .checked_mul(tileset.tile_width as u32 + tileset.spacing as u32)
+                .and_then(|x| x.checked_add(tileset.margin as u32));
+            let tile_y = row
+                .checked_mul(tileset.tile_height as u32 + tileset.spacing as u32)
+                .and_then(|y| y.checked_add(tileset.margin as u32));
+            let tile = tile_x
+                .zip(tile_y)
+                .and_then(|(tile_x, tile_y)| {
+                    sheet
+                        .crop(tile_x, tile_y, tileset.tile_width, tileset.tile_height)
+                        .ok()
+                })
+                .with_context(|| format!("Tile {id} doesn't fit in its tileset"))?
+                .pack();
+            if let Some(first) = tiles.first() {
+                if (first.width, first.height) != (tile.width, tile.height) {
+                    bail!(
+                        "The tile at {x}, {y} is {}x{}, but the others are {}x{}",
+                        tile.width,
+                        tile.height,
+                        first.width,
+                        first.height
+                    );
+                }
+            }
+
+            let key = (tile.bpp.get_flags(), tile.get_bytes());
+            let cell = match seen.get(&key) {
+                Some(&cell) => cell,
+                None => {
+                    if tiles.len() == u16::MAX as usize {
+                        bail!("Tilemaps can use at most {} different tiles", u16::MAX);
+                    }
+                    tiles.push(tile);
+                    seen.insert(key, tiles.len() as u16);
+                    tiles.len() as u16
+                }
+            };
+            gid_cells.insert(gid, cell);
+            cells.push(cell);
+        }
+
+        Ok(Tilemap {
+            width,
+            height,
+            cells,
+            tiles,
+        })
+    }
+
+    //Compresses each row separately, behind a table of where each starts, in the layout
+    //w4_tiny_decomp::TileMap reads
+    pub fn write_map(&self, method: Method) -> Result<Vec<u8>> {
+        let wide = self.tiles.len() > u8::MAX as usize;
+        let cell_size = if wide { 2 } else { 1 };
+        if self.width as usize * cell_size > MAX_ROW_BYTES {
+            bail!(
+                "Tilemaps can be at most {} tiles wide when using {} tiles",
+                MAX_ROW_BYTES / cell_size,
+                self.tiles.len()
+            );
+        }
+
+        let mut rows = Vec::with_capacity(self.height as usize);
+        for (y, row) in self.cells.chunks(self.width.max(1) as usize).enumerate() {
+            let bytes: Vec<u8> = if wide {
+                row.iter().flat_map(|cell| cell.to_le_bytes()).collect()
+            } else {
+                row.iter().map(|&cell| cell as u8).collect()
+            };
+            let sprite = W4Sprite::from_packed(8, bytes.len() as u16, BitsPerPixel::One, bytes)?;
+            rows.push(convert_sprite_with(format!("row {y}"), &sprite, method)?.bytes);
+        }
+
+        let data_start = TILEMAP_HEADER_SIZE + (rows.len() + 1) * 2;
+        let total_size = data_start + rows.iter().map(|row| row.len()).sum::<usize>();
+        if total_size > u16::MAX as usize {
+            bail!(
+                "Tilemap would be {total_size} bytes, but offsets are limited to {} bytes",
+                u16::MAX
+            );
+        }
+
+        let mut out = Vec::with_capacity(total_size);
+        out.push(if wide { TILEMAP_WIDE_CELLS } else { 0 });
+        out.extend_from_slice(&self.width.to_le_bytes());
+        out.extend_from_slice(&self.height.to_le_bytes());
+
+        let mut offset = data_start;
+        for row in &rows {
+            out.extend_from_slice(&(offset as u16).to_le_bytes());
+            offset += row.len();
+        }
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+
+        for row in rows {
+            out.extend_from_slice(&row);
+        }
+
+        Ok(out)
+    }
+
+    //Every tile as a sprite named {name}_{index}, for a bundle where cell n + 1 is sprite n
+    pub fn convert_tiles(&self, name: &str, method: Method) -> Result<Vec<ConvertedSprite>> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .map(|(i, tile)| convert_sprite_with(format!("{name}_{i}"), tile, method))
+            .collect()
+    }
+}
+
+//Converts a layer of a Tiled map into a tilemap, and the tiles it uses into a bundle
+pub fn convert_tilemap(
+    input: &Path,
+    map_output: &Path,
+    tiles_output: &Path,
+    layer: Option<&str>,
+    method: Method,
+) -> Result<()> {
+    let map = TiledMap::from_path(input)?;
+    let tilemap = Tilemap::from_layer(&map, map.layer(layer)?)?;
+
+    let name = input
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let map_bytes = tilemap.write_map(method)?;
+    let tile_bytes = write_bundle(&tilemap.convert_tiles(&name, method)?, false, &[], false)?;
+
+    std::fs::write(map_output, &map_bytes)
+        .with_context(|| format!("Failed to write {}", map_output.display()))?;
+    std::fs::write(tiles_output, &tile_bytes)
+        .with_context(|| format!("Failed to write {}", tiles_output.display()))?;
+
+    println!(
+        "Converted {}x{} map into {} bytes at {}, using {} different tiles taking up {} bytes at {}",
+        tilemap.width,
+        tilemap.height,
+        map_bytes.len(),
+        map_output.display(),
+        tilemap.tiles.len(),
+        tile_bytes.len(),
+        tiles_output.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use w4_pnger_common::CompType;
+    use w4_tiny_decomp::{Bundle, Decompressor, TileMap};
+
+    use super::Tilemap;
+    use crate::compress::Method;
+    use crate::tiled::TiledMap;
+    use crate::wasm4png::IndexedSprite;
+
+    #[test]
+    fn test_tilemap() {
+        //test.png cut into 8x8 tiles, with tiles 5 and 6 repeated
+        let tmj = r#"{"width": 4, "height": 3,
+            "tilesets": [{"firstgid": 1, "image": "../test.png", "tilewidth": 8, "tileheight": 8, "columns": 4}],
+            "layers": [{"type": "tilelayer", "name": "ground", "data": [
+                6, 7, 6, 7,
+                0, 0, 1, 0,
+                6, 7, 6, 12
+            ]}]}"#;
+        let map = TiledMap::from_tmj(tmj, Path::new("")).unwrap();
+        let tilemap = Tilemap::from_layer(&map, map.layer(None).unwrap()).unwrap();
+
+        let sheet = IndexedSprite::from_path("../test.png").unwrap();
+        let expected_tiles = [(1, 1), (2, 1), (0, 0), (3, 2)];
+        assert_eq!(tilemap.tiles.len(), 4);
+        for (tile, (column, row)) in tilemap.tiles.iter().zip(expected_tiles) {
+            let expected = sheet.crop(column * 8, row * 8, 8, 8).unwrap().pack();
+            assert_eq!(tile.get_bytes(), expected.get_bytes());
+        }
+        let cells = [1, 2, 1, 2, 0, 0, 3, 0, 1, 2, 1, 4];
+        assert_eq!(tilemap.cells, cells);
+
+        for method in [Method::None, Method::Auto, Method::Only(CompType::Lz)] {
+            let bytes = tilemap.write_map(method).unwrap();
+            let mut buf = [0; 4];
+            let mut reader = TileMap::new(Decompressor::new(&mut buf), &bytes).unwrap();
+            assert_eq!((reader.width(), reader.height()), (4, 3));
+            for (i, &cell) in cells.iter().enumerate() {
+                assert_eq!(reader.cell(i % 4, i / 4), Ok(cell));
+            }
+        }
+
+        let tiles = crate::bundle::write_bundle(
+            &tilemap.convert_tiles("level", Method::Auto).unwrap(),
+            false,
+            &[],
+            false,
+        )
+        .unwrap();
+        let bundle = Bundle::new(&tiles).unwrap();
+        assert_eq!(bundle.len(), 4);
+        let mut buf = vec![0; 16];
+        let tile = Decompressor::new(&mut buf)
+            .decompress(bundle.get(3).unwrap())
+            .unwrap()
+            .bytes
+            .to_vec();
+        assert_eq!(tile, tilemap.tiles[3].get_bytes());
+    }
+
+    #[test]
+    fn test_tileset_spacing() {
+        //Three 3x2 tiles at 1 bpp with a 1 pixel margin and spacing, so the tileset is 13 pixels
+        //wide and neither its rows nor the tiles' rows fill whole bytes. Tile i has one dark
+        //pixel at i, 1
+        let (width, height) = (13, 4);
+        let mut gray = vec![0xff; width * height];
+        for tile in 0..3 {
+            gray[2 * width + 1 + tile * 4 + tile] = 0;
+        }
+
+        let dir = std::env::temp_dir().join("w4-pnger-tilemap-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&gray)
+            .unwrap();
+        std::fs::write(dir.join("tiles.png"), png).unwrap();
+
+        let tmj = r#"{"width": 3, "height": 1,
+            "tilesets": [{"firstgid": 1, "image": "tiles.png", "tilewidth": 3, "tileheight": 2,
+                "margin": 1, "spacing": 1, "columns": 3}],
+            "layers": [{"type": "tilelayer", "name": "ground", "data": [3, 2, 1]}]}"#;
+        let map = TiledMap::from_tmj(tmj, &dir).unwrap();
+        let tilemap = Tilemap::from_layer(&map, map.layer(None).unwrap()).unwrap();
+
+        let tiles: Vec<Vec<u8>> = tilemap.tiles.iter().map(|t| t.get_bytes()).collect();
+        assert_eq!(tiles, [[0x04], [0x08], [0x10]]);
+        assert_eq!(tilemap.cells, [1, 2, 3]);
+    }
+}
@@ -0,0 +1,247 @@
+use anyhow::{anyhow, Result};
+use glob::glob;
+use w4_pnger_common::BitsPerPixel;
+use w4_tiny_decomp::Decompressor;
+
+use crate::compress::compressors;
+use crate::convert::{load_matched, ConvertedSprite};
+use crate::jobs::{run_ordered, Log};
+use crate::wasm4png::{IndexedSprite, W4Sprite};
+
+//How many differing pixels to list for each sprite
+const MAX_LISTED_PIXELS: usize = 10;
+
+pub struct Verifier<'a> {
+    path: &'a str,
+    jobs: usize,
+}
+
+impl<'a> Verifier<'a> {
+    pub fn new(path: &'a str) -> Self {
+        Verifier { path, jobs: 1 }
+    }
+
+    //Verifies up to this many sprites at once
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    //Returns whether every sprite survived being compressed and decompressed
+    pub fn run(self) -> bool {
+        let paths: Vec<_> = glob(self.path).expect("Must be a valid pattern").collect();
+
+        let results = run_ordered(self.jobs, paths, |path_res| {
+            let mut log = Log::default();
+
+            //A file that can't be read counts as one failed sprite
+            let passed = match load_matched(path_res) {
+                Ok(sprites) => sprites
+                    .iter()
+                    .map(|(name, sprite)| verify_png(name, sprite, &mut log))
+                    .collect(),
+                Err(e) => {
+                    log.err(format!("{e}"));
+                    vec![false]
+                }
+            };
+
+            (passed, log)
+        });
+        let results: Vec<bool> = results.into_iter().flatten().collect();
+
+        let failed = results.iter().filter(|passed| !**passed).count();
+        if results.is_empty() {
+            eprintln!("No files matched {}", self.path);
+            return false;
+        } else if failed == 0 {
+            println!("All {} sprites verified", results.len());
+        } else {
+            eprintln!("{failed} of {} sprites failed verification", results.len());
+        }
+
+        failed == 0
+    }
+}
+
+fn verify_png(name: &str, source: &IndexedSprite, log: &mut Log) -> bool {
+    let sprite = &source.pack();
+    let mut passed = true;
+    let mut converted = vec![(
+        "uncompressed",
+        ConvertedSprite::uncompressed(name.to_owned(), sprite),
+    )];
+    for compressor in compressors() {
+        match compressor.compress(&sprite.get_bytes()) {
+            Ok(compressed) => converted.push((
+                compressor.name(),
+                ConvertedSprite::compressed(
+                    name.to_owned(),
+                    sprite,
+                    compressor.comp_type(),
+                    compressed,
+                ),
+            )),
+            Err(e) => {
+                passed = false;
+                log.err(format!(
+                    "{name}: failed to compress with {}: {e}",
+                    compressor.name()
+                ));
+            }
+        }
+    }
+
+    for (kind, converted) in converted {
+        match round_trip(source, &converted.bytes) {
+            Ok(diff) if diff.is_empty() => log.out(format!(
+                "{name}: {kind} ok, {} bytes",
+                converted.bytes.len()
+            )),
+            Ok(diff) => {
+                passed = false;
+                log.err(format!(
+                    "{name}: {kind} has {} pixels which differ",
+                    diff.len()
+                ));
+                for pixel in diff.iter().take(MAX_LISTED_PIXELS) {
+                    log.err(format!(
+                        "    ({}, {}): expected {}, decompressed {}",
+                        pixel.x, pixel.y, pixel.expected, pixel.actual
+                    ));
+                }
+                if diff.len() > MAX_LISTED_PIXELS {
+                    log.err(format!(
+                        "    ...and {} more",
+                        diff.len() - MAX_LISTED_PIXELS
+                    ));
+                }
+            }
+            Err(e) => {
+                passed = false;
+                log.err(format!("{name}: {kind} failed to decompress: {e}"));
+            }
+        }
+    }
+
+    passed
+}
+
+pub struct PixelDiff {
+    pub x: u16,
+    pub y: u16,
+    pub expected: u8,
+    pub actual: u8,
+}
+
+//Decompresses .ws bytes and compares them to the palette indices read from the source image,
+//returning every pixel whose palette index changed. Comparing against the source rather than the
+//packed sprite catches mistakes in packing as well as compression
+pub fn round_trip(sprite: &IndexedSprite, ws: &[u8]) -> Result<Vec<PixelDiff>> {
+    let len = sprite
+        .bpp
+        .packed_len(sprite.width.into(), sprite.height.into());
+    let mut buf = vec![0; len];
+    let mut decompressor = Decompressor::new(&mut buf);
+    let handle = decompressor.decompress(ws).map_err(|e| anyhow!(e))?;
+
+    if handle.bytes.len() != len {
+        return Err(anyhow!(
+            "decompressed to {} bytes, expected {len}",
+            handle.bytes.len()
+        ));
+    }
+
+    if (handle.width as u16, handle.height as u16, handle.flags)
+        != (sprite.width, sprite.height, sprite.bpp.get_flags())
+    {
+        return Err(anyhow!(
+            "header is {}x{} with flags {}, expected {}x{} with flags {}",
+            handle.width,
+            handle.height,
+            handle.flags,
+            sprite.width,
+            sprite.height,
+            sprite.bpp.get_flags()
+        ));
+    }
+
+    let bpp = BitsPerPixel::try_from_flags(handle.flags).map_err(|e| anyhow!(e))?;
+    let decompressed =
+        W4Sprite::from_packed(sprite.width, sprite.height, bpp, handle.bytes.to_vec())?;
+
+    Ok(sprite
+        .indices
+        .iter()
+        .copied()
+        .zip(decompressed.indices())
+        .enumerate()
+        .filter(|(_, (expected, actual))| expected != actual)
+        .map(|(i, (expected, actual))| PixelDiff {
+            x: (i % sprite.width as usize) as u16,
+            y: (i / sprite.width as usize) as u16,
+            expected,
+            actual,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use w4_pnger_common::{BitsPerPixel, CompType};
+
+    use super::round_trip;
+    use crate::compress::{pkcomp::PkComp, Compressor};
+    use crate::convert::ConvertedSprite;
+    use crate::wasm4png::{IndexedSprite, W4Sprite};
+
+    #[test]
+    fn test_round_trip() {
+        let sprite = IndexedSprite::from_path("../test.png").unwrap();
+        let mut ws = ConvertedSprite::uncompressed("test".to_owned(), &sprite.pack()).bytes;
+        assert!(round_trip(&sprite, &ws).unwrap().is_empty());
+
+        //Decompressing to fewer bytes than the sprite needs
+        assert!(round_trip(&sprite, &ws[..ws.len() - 1]).is_err());
+
+        //A packed sprite that doesn't match the image it came from
+        let mut changed = sprite.clone();
+        changed.indices[0] ^= 1;
+        let changed_ws = ConvertedSprite::uncompressed("test".to_owned(), &changed.pack()).bytes;
+        assert_eq!(round_trip(&sprite, &changed_ws).unwrap().len(), 1);
+
+        //Flip the second pixel of the second row
+        ws[4 + 32 / 4] ^= 0b0011_0000;
+        let diff = round_trip(&sprite, &ws).unwrap();
+        assert_eq!(diff.len(), 1);
+        assert_eq!((diff[0].x, diff[0].y), (1, 1));
+        assert_eq!(diff[0].expected ^ diff[0].actual, 0b11);
+
+        ws[1] = 16;
+        assert!(round_trip(&sprite, &ws).is_err());
+    }
+
+    #[test]
+    fn test_pk_edge_cases() {
+        //Each of these used to come back from the decompressor with the wrong pixels
+        let cases: [(u16, u16, BitsPerPixel, &[u8]); 3] = [
+            //Ends with a single pair after a run of zeroes
+            (16, 1, BitsPerPixel::Two, &[0x63, 0x3a, 0xdb, 0x3a]),
+            //An odd number of bytes can't be split into bitplanes
+            (8, 3, BitsPerPixel::One, &[0x8c, 0x00, 0x00]),
+            //Smaller than most jump sizes
+            (8, 1, BitsPerPixel::One, &[0x01]),
+        ];
+
+        for (width, height, bpp, bytes) in cases {
+            let sprite = W4Sprite::from_packed(width, height, bpp, bytes.to_vec()).unwrap();
+            let compressed = PkComp {}.compress(bytes).unwrap();
+            let ws =
+                ConvertedSprite::compressed("case".to_owned(), &sprite, CompType::Pk, compressed)
+                    .bytes;
+
+            let source = IndexedSprite::new(width.into(), height.into(), bpp, sprite.indices());
+            assert!(round_trip(&source.unwrap(), &ws).unwrap().is_empty());
+        }
+    }
+}
@@ -1,9 +1,18 @@
-use std::{collections::HashMap, convert::TryInto, fs::File, hash::Hash};
-
-use anyhow::{bail, Result};
-use png::{BitDepth, ColorType, OutputInfo, Reader};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    fs::File,
+    hash::Hash,
+    io::{Read, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+use png::{BitDepth, ColorType, Decoder, Encoder, OutputInfo, Reader, Transformations};
 use w4_pnger_common::BitsPerPixel;
+use w4_tiny_decomp::Decompressor;
 
+#[derive(Clone)]
 pub struct W4Sprite {
     bytes: Vec<u8>,
     pub width: u16,
@@ -11,8 +20,38 @@ pub struct W4Sprite {
     pub bpp: BitsPerPixel,
 }
 
-impl W4Sprite {
-    pub fn from_reader(reader: &mut Reader<File>) -> Result<Self> {
+//The palette index of every pixel, row by row, as read from an image before it's packed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedSprite {
+    pub width: u16,
+    pub height: u16,
+    pub bpp: BitsPerPixel,
+    pub indices: Vec<u8>,
+}
+
+impl IndexedSprite {
+    pub fn new(width: u32, height: u32, bpp: BitsPerPixel, indices: Vec<u8>) -> Result<Self> {
+        if indices.len() as u64 != width as u64 * height as u64 {
+            bail!(
+                "A {width}x{height} sprite has {} pixels, not {}",
+                width as u64 * height as u64,
+                indices.len()
+            );
+        }
+
+        Ok(Self {
+            width: width.try_into()?,
+            height: height.try_into()?,
+            bpp,
+            indices,
+        })
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_reader(&mut Decoder::new(File::open(path)?).read_info()?)
+    }
+
+    pub fn from_reader<R: Read>(reader: &mut Reader<R>) -> Result<Self> {
         let mut buf = vec![0; reader.output_buffer_size()];
 
         let info = reader.next_frame(&mut buf)?;
@@ -20,8 +59,8 @@ impl W4Sprite {
         let png = &buf[..info.buffer_size()];
 
         let (palette, bpp, components) = match info.color_type {
-            png::ColorType::Grayscale => get_palette_bpp(&info, &png, 1).unwrap(),
-            png::ColorType::Rgb => get_palette_bpp(&info, &png, 3).unwrap(),
+            png::ColorType::Grayscale => get_palette_bpp(&info, png, 1)?,
+            png::ColorType::Rgb => get_palette_bpp(&info, png, 3)?,
             png::ColorType::Indexed => {
                 let mut palette = HashMap::new();
 
@@ -32,65 +71,135 @@ impl W4Sprite {
                         let idx = bytes_per_component * (y * info.width + x) as usize;
 
                         if !palette.contains_key(&png[idx..idx + bytes_per_component]) {
-                            palette.insert(
-                                &png[idx..idx + bytes_per_component],
-                                assume_u8(&png[idx..idx + bytes_per_component], info.bit_depth)
-                                    as usize,
-                            );
+                            let index =
+                                assume_u8(&png[idx..idx + bytes_per_component], info.bit_depth);
+                            if index >= 4 {
+                                bail!(
+                                    "Palette index {index} at {x}, {y} is past the 4 colors WASM-4 can draw"
+                                );
+                            }
+
+                            palette.insert(&png[idx..idx + bytes_per_component], index as usize);
                         }
                     }
                 }
 
-                let bpp = if palette.len() > 2 {
-                    BitsPerPixel::Two
-                } else {
+                let bpp = if palette.values().all(|&index| index < 2) {
                     BitsPerPixel::One
+                } else {
+                    BitsPerPixel::Two
                 };
 
                 (palette, bpp, 1)
             }
-            png::ColorType::GrayscaleAlpha => get_palette_bpp(&info, &png, 2).unwrap(),
-            png::ColorType::Rgba => get_palette_bpp(&info, &png, 4).unwrap(),
+            png::ColorType::GrayscaleAlpha => get_palette_bpp(&info, png, 2)?,
+            png::ColorType::Rgba => get_palette_bpp(&info, png, 4)?,
         };
-        //Reimplementation of Aduros' png packing in WASM-4
-
-        let mut out_bytes: Vec<u8> =
-            vec![0; (info.width * info.height * bpp.get_num() / 8) as usize];
-
         let bytes_per_coponent = bit_depth_to_bytes(info.bit_depth);
 
+        let mut indices = Vec::with_capacity((info.width * info.height) as usize);
         for y in 0..info.height {
             for x in 0..info.width {
                 let idx = bytes_per_coponent * components * (info.width * y + x) as usize;
-                let palette_index = *palette
-                    .get(&png[idx..idx + bytes_per_coponent * components])
-                    .unwrap() as u8;
-
-                let (out_idx, shift, mask) = match bpp {
-                    BitsPerPixel::One => {
-                        let out_idx = ((y * info.width + x) >> 3) as usize;
-                        let shift = 7 - (x & 0x7);
-                        let mask = 0x1 << shift;
-                        (out_idx, shift, mask)
-                    }
-                    BitsPerPixel::Two => {
-                        let out_idx = ((y * info.width + x) >> 2) as usize;
-                        let shift = 6 - ((x & 0x3) << 1);
-                        let mask = 0x3 << shift;
-                        (out_idx, shift, mask)
-                    }
-                };
-
-                out_bytes[out_idx] = (palette_index << shift) | (out_bytes[out_idx] & (!mask));
+                indices.push(
+                    *palette
+                        .get(&png[idx..idx + bytes_per_coponent * components])
+                        .unwrap() as u8,
+                );
             }
         }
 
-        Ok(Self {
+        Self::new(info.width, info.height, bpp, indices)
+    }
+
+    //Maps each pixel to the position of its color in `palette` rather than sorting colors by
+    //brightness, so every sprite in a set can share the same draw colors
+    pub fn from_path_with_palette<P: AsRef<Path>>(path: P, palette: &[[u8; 4]]) -> Result<Self> {
+        Self::from_rgba_decoder(Decoder::new(File::open(path)?), palette)
+    }
+
+    fn from_rgba_decoder<R: Read>(decoder: Decoder<R>, palette: &[[u8; 4]]) -> Result<Self> {
+        if palette.is_empty() || palette.len() > 4 {
+            bail!("Palettes must have between 1 and 4 colors");
+        }
+
+        let (width, height, pixels) = decode_rgba(decoder)?;
+        let indices = palette_indices(width, &pixels, palette)?;
+
+        let bpp = if indices.iter().all(|&i| i < 2) {
+            BitsPerPixel::One
+        } else {
+            BitsPerPixel::Two
+        };
+
+        Self::new(width, height, bpp, indices)
+    }
+
+    //Cuts out the width x height area with its top left corner at x, y, keeping this sprite's
+    //bits per pixel
+    pub fn crop(&self, x: u32, y: u32, width: u16, height: u16) -> Result<Self> {
+        if x as u64 + width as u64 > self.width as u64
+            || y as u64 + height as u64 > self.height as u64
+        {
+            bail!(
+                "A {width}x{height} area at {x}, {y} doesn't fit in a {}x{} image",
+                self.width,
+                self.height
+            );
+        }
+
+        let (x, y) = (x as usize, y as usize);
+        let cropped: Vec<u8> = (y..y + height as usize)
+            .flat_map(|row| {
+                let start = row * self.width as usize + x;
+                self.indices[start..start + width as usize].iter().copied()
+            })
+            .collect();
+
+        Self::new(width.into(), height.into(), self.bpp, cropped)
+    }
+
+    //Reimplementation of Aduros' png packing in WASM-4
+    pub fn pack(&self) -> W4Sprite {
+        let bits = self.bpp.get_num() as usize;
+        let mut out_bytes: Vec<u8> =
+            vec![0; self.bpp.packed_len(self.width.into(), self.height.into())];
+
+        //Rows aren't padded, so a pixel's bits are found from its position in the whole sprite
+        //rather than its column
+        for (i, &palette_index) in self.indices.iter().enumerate() {
+            let bit = i * bits;
+            out_bytes[bit / 8] |= palette_index << (8 - bits - bit % 8);
+        }
+
+        W4Sprite {
             bytes: out_bytes,
-            width: info.width.try_into()?,
-            height: info.height.try_into()?,
-            bpp,
-        })
+            width: self.width,
+            height: self.height,
+            bpp: self.bpp,
+        }
+    }
+}
+
+impl W4Sprite {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(IndexedSprite::from_path(path)?.pack())
+    }
+
+    pub fn from_png_bytes(png: &[u8]) -> Result<Self> {
+        Self::from_reader(&mut Decoder::new(png).read_info()?)
+    }
+
+    pub fn from_reader<R: Read>(reader: &mut Reader<R>) -> Result<Self> {
+        Ok(IndexedSprite::from_reader(reader)?.pack())
+    }
+
+    pub fn from_path_with_palette<P: AsRef<Path>>(path: P, palette: &[[u8; 4]]) -> Result<Self> {
+        Ok(IndexedSprite::from_path_with_palette(path, palette)?.pack())
+    }
+
+    pub fn from_png_bytes_with_palette(png: &[u8], palette: &[[u8; 4]]) -> Result<Self> {
+        Ok(IndexedSprite::from_rgba_decoder(Decoder::new(png), palette)?.pack())
     }
 
     pub fn get_bytes(&self) -> Vec<u8> {
@@ -103,13 +212,186 @@ impl W4Sprite {
     pub fn get_header_bytes(&self) -> Vec<u8> {
         vec![self.width as u8, self.height as u8, self.bpp.get_flags()]
     }
+
+    //Decompresses a sprite in .ws format
+    pub fn from_ws(ws: &[u8]) -> Result<Self> {
+        let mut buf = vec![0; MAX_SPRITE_BYTES];
+        let mut decompressor = Decompressor::new(&mut buf);
+        let sprite = decompressor.decompress(ws).map_err(|e| anyhow!(e))?;
+
+        let bpp = BitsPerPixel::try_from_flags(sprite.flags).map_err(|e| anyhow!(e))?;
+        Self::from_packed(
+            sprite.width.into(),
+            sprite.height.into(),
+            bpp,
+            sprite.bytes.to_vec(),
+        )
+    }
+
+    //Wraps already packed pixels, as WASM-4's blit expects them
+    pub fn from_packed(width: u16, height: u16, bpp: BitsPerPixel, bytes: Vec<u8>) -> Result<Self> {
+        let expected = bpp.packed_len(width.into(), height.into());
+        if bytes.len() != expected {
+            bail!(
+                "A {width}x{height} sprite at {} bpp is {expected} bytes, not {}",
+                bpp.get_num(),
+                bytes.len()
+            );
+        }
+
+        Ok(Self {
+            bytes,
+            width,
+            height,
+            bpp,
+        })
+    }
+
+    //The palette index of every pixel, row by row
+    pub fn indices(&self) -> Vec<u8> {
+        let bpp = self.bpp.get_num() as usize;
+        let mask = (1 << bpp) - 1;
+
+        (0..self.width as usize * self.height as usize)
+            .map(|i| {
+                let bit = i * bpp;
+                match self.bytes.get(bit / 8) {
+                    Some(byte) => (byte >> (8 - bpp - bit % 8)) & mask,
+                    None => 0,
+                }
+            })
+            .collect()
+    }
+
+    //Writes an RGBA png, with palette[i] as the color of index i
+    pub fn write_png<W: Write>(&self, out: W, palette: &[[u8; 4]]) -> Result<()> {
+        let colors = 1 << self.bpp.get_num();
+        if palette.len() < colors {
+            bail!(
+                "A {} bpp sprite needs {colors} colors, but the palette has {}",
+                self.bpp.get_num(),
+                palette.len()
+            );
+        }
+
+        let pixels: Vec<u8> = self
+            .indices()
+            .into_iter()
+            .flat_map(|i| palette[i as usize])
+            .collect();
+
+        let mut encoder = Encoder::new(out, self.width.into(), self.height.into());
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&pixels)?;
+
+        Ok(())
+    }
+}
+
+//The default WASM-4 palette, from brightest to darkest, in the order png2src assigns colors
+pub const WASM4_PALETTE: [[u8; 4]; 4] = [
+    [0xe0, 0xf8, 0xcf, 0xff],
+    [0x86, 0xc0, 0x6c, 0xff],
+    [0x30, 0x68, 0x50, 0xff],
+    [0x07, 0x18, 0x21, 0xff],
+];
+
+//A 255x255 sprite at 2 bpp
+const MAX_SPRITE_BYTES: usize = (255 * 255 * 2_usize).div_ceil(8);
+
+type PaletteMap<'a> = HashMap<&'a [u8], usize>;
+
+//Decodes any png into 8-bit RGBA pixels
+fn decode_rgba<R: Read>(mut decoder: Decoder<R>) -> Result<(u32, u32, Vec<[u8; 4]>)> {
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    let png = &buf[..info.buffer_size()];
+
+    let pixels = match info.color_type {
+        ColorType::Grayscale => png.iter().map(|&g| [g, g, g, 0xff]).collect(),
+        ColorType::GrayscaleAlpha => png.chunks(2).map(|c| [c[0], c[0], c[0], c[1]]).collect(),
+        ColorType::Rgb => png.chunks(3).map(|c| [c[0], c[1], c[2], 0xff]).collect(),
+        ColorType::Rgba => png.chunks(4).map(|c| [c[0], c[1], c[2], c[3]]).collect(),
+        ColorType::Indexed => bail!("Indexed pngs should have been expanded to RGB"),
+    };
+
+    Ok((info.width, info.height, pixels))
+}
+
+//The position of each pixel's color in the palette
+pub(crate) fn palette_indices(
+    width: u32,
+    pixels: &[[u8; 4]],
+    palette: &[[u8; 4]],
+) -> Result<Vec<u8>> {
+    let mut indices = Vec::with_capacity(pixels.len());
+    for (i, pixel) in pixels.iter().enumerate() {
+        match palette.iter().position(|color| color == pixel) {
+            Some(index) => indices.push(index as u8),
+            None => bail!(
+                "Color {} at {}, {} is not in the palette",
+                format_color(pixel),
+                i as u32 % width,
+                i as u32 / width
+            ),
+        }
+    }
+
+    Ok(indices)
+}
+
+//Orders colors from brightest to darkest, the same way pngs without a palette are
+pub(crate) fn sort_by_brightness(colors: &mut [[u8; 4]]) {
+    let brightness = |c: &[u8; 4]| {
+        Color {
+            r: c[0],
+            g: c[1],
+            b: c[2],
+            a: c[3],
+        }
+        .brightness()
+    };
+    colors.sort_by(|c1, c2| brightness(c2).total_cmp(&brightness(c1)));
+}
+
+//Parses "#rrggbb", "rrggbb" or "0xrrggbb", optionally followed by an alpha byte
+pub fn parse_color(color: &str) -> Result<[u8; 4]> {
+    let hex = color.trim();
+    let hex = hex
+        .strip_prefix('#')
+        .or_else(|| hex.strip_prefix("0x"))
+        .unwrap_or(hex);
+
+    if (hex.len() != 6 && hex.len() != 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("{color} is not a color, expected #rrggbb or #rrggbbaa");
+    }
+
+    let byte = |i: usize| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16);
+    let alpha = if hex.len() == 8 { byte(3)? } else { 0xff };
+
+    Ok([byte(0)?, byte(1)?, byte(2)?, alpha])
+}
+
+pub fn format_color(color: &[u8; 4]) -> String {
+    if color[3] == 0xff {
+        format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+    } else {
+        format!(
+            "#{:02x}{:02x}{:02x}{:02x}",
+            color[0], color[1], color[2], color[3]
+        )
+    }
 }
 
 fn get_palette_bpp<'a>(
     info: &OutputInfo,
     png: &'a [u8],
     components: usize,
-) -> Result<(HashMap<&'a [u8], usize>, BitsPerPixel, usize)> {
+) -> Result<(PaletteMap<'a>, BitsPerPixel, usize)> {
     let mut palette = HashMap::new();
     let bytes_per_component = bit_depth_to_bytes(info.bit_depth);
     for y in 0..info.height {
@@ -119,7 +401,7 @@ fn get_palette_bpp<'a>(
             let slice = &png[idx..idx + bytes_per_component * components];
 
             if !palette.contains_key(slice) {
-                if palette.len() > 4 {
+                if palette.len() == 4 {
                     bail!(
                         "Too many colors, first instance of fifth color found at {}, {}",
                         x,
@@ -132,7 +414,7 @@ fn get_palette_bpp<'a>(
         }
     }
 
-    let mut palette_keys: Vec<&[u8]> = palette.keys().map(|x| x.clone()).collect();
+    let mut palette_keys: Vec<&[u8]> = palette.keys().copied().collect();
     palette_keys.sort_by(|c1, c2| {
         Color::from_slice(c2, info.bit_depth, info.color_type)
             .unwrap()
@@ -175,7 +457,7 @@ impl Color {
                 let g = compress_to_u8(&slice[0..bytes_per_component], bit_depth);
                 Some(Self {
                     r: g,
-                    g: g,
+                    g,
                     b: g,
                     a: 1,
                 })
@@ -183,7 +465,7 @@ impl Color {
             ColorType::Rgb => Some(Self {
                 r: compress_to_u8(&slice[0..bytes_per_component], bit_depth),
                 g: compress_to_u8(
-                    &slice[(bytes_per_component * 1)..(bytes_per_component * 2)],
+                    &slice[bytes_per_component..(bytes_per_component * 2)],
                     bit_depth,
                 ),
                 b: compress_to_u8(
@@ -197,7 +479,7 @@ impl Color {
                 let g = compress_to_u8(&slice[0..bytes_per_component], bit_depth);
                 Some(Self {
                     r: g,
-                    g: g,
+                    g,
                     b: g,
                     a: compress_to_u8(
                         &slice[bytes_per_component..(bytes_per_component + 1)],
@@ -208,7 +490,7 @@ impl Color {
             ColorType::Rgba => Some(Self {
                 r: compress_to_u8(&slice[0..bytes_per_component], bit_depth),
                 g: compress_to_u8(
-                    &slice[(bytes_per_component * 1)..(bytes_per_component * 2)],
+                    &slice[bytes_per_component..(bytes_per_component * 2)],
                     bit_depth,
                 ),
                 b: compress_to_u8(
@@ -264,3 +546,150 @@ fn bit_depth_to_bytes(bit_depth: BitDepth) -> usize {
         BitDepth::Sixteen => 2,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use w4_pnger_common::{BitsPerPixel, CompType};
+
+    use png::{BitDepth, ColorType, Encoder};
+
+    use super::{parse_color, IndexedSprite, W4Sprite, WASM4_PALETTE};
+    use crate::compress::{compressors, pkcomp::PkComp, Compressor};
+    use crate::convert::ConvertedSprite;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#e0f8cf").unwrap(), [0xe0, 0xf8, 0xcf, 0xff]);
+        assert_eq!(parse_color("0x07182100").unwrap(), [0x07, 0x18, 0x21, 0x00]);
+        assert!(parse_color("#e0f8c").is_err());
+        assert!(parse_color("#e0f8cg").is_err());
+    }
+
+    #[test]
+    fn test_palette_override() {
+        let palette = [
+            parse_color("#e0f8cf").unwrap(),
+            parse_color("#86c06c").unwrap(),
+            parse_color("#306850").unwrap(),
+            parse_color("#071821").unwrap(),
+        ];
+
+        //test.png uses the default WASM-4 palette, which is already sorted by brightness
+        let sprite = W4Sprite::from_path("../test.png").unwrap();
+        let with_palette = W4Sprite::from_path_with_palette("../test.png", &palette).unwrap();
+        assert_eq!(sprite.get_bytes(), with_palette.get_bytes());
+
+        let mut reversed = palette;
+        reversed.reverse();
+        let reversed = W4Sprite::from_path_with_palette("../test.png", &reversed).unwrap();
+        let inverted: Vec<u8> = sprite.get_bytes().iter().map(|b| !b).collect();
+        assert_eq!(reversed.get_bytes(), inverted);
+
+        assert!(W4Sprite::from_path_with_palette("../test.png", &palette[..3]).is_err());
+    }
+
+    fn encode_png(
+        width: u32,
+        height: u32,
+        color_type: ColorType,
+        palette: Option<&[u8]>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut png = Vec::new();
+        let mut encoder = Encoder::new(&mut png, width, height);
+        encoder.set_color(color_type);
+        encoder.set_depth(BitDepth::Eight);
+        if let Some(palette) = palette {
+            encoder.set_palette(palette.to_vec());
+        }
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(data)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn test_too_many_colors() {
+        let rgb = [0, 0, 0, 85, 85, 85, 170, 170, 170, 255, 255, 255, 255, 0, 0];
+        let four = encode_png(4, 1, ColorType::Rgb, None, &rgb[..12]);
+        assert!(W4Sprite::from_png_bytes(&four).is_ok());
+        let five = encode_png(5, 1, ColorType::Rgb, None, &rgb);
+        assert!(W4Sprite::from_png_bytes(&five).is_err());
+
+        let indexed = encode_png(5, 1, ColorType::Indexed, Some(&rgb), &[0, 1, 2, 3, 4]);
+        assert!(W4Sprite::from_png_bytes(&indexed).is_err());
+        let indexed = encode_png(2, 1, ColorType::Indexed, Some(&rgb), &[0, 3]);
+        assert!(W4Sprite::from_png_bytes(&indexed).is_ok());
+    }
+
+    #[test]
+    fn test_odd_widths() {
+        //Rows run on without padding, so the second row starts halfway through the second byte
+        let mut gray = [0xff; 24];
+        gray[0] = 0;
+        gray[12] = 0;
+        let sprite =
+            W4Sprite::from_png_bytes(&encode_png(12, 2, ColorType::Grayscale, None, &gray))
+                .unwrap();
+        assert_eq!(sprite.get_bytes(), [0x80, 0x08, 0x00]);
+        assert_eq!(
+            sprite.indices(),
+            gray.iter().map(|&g| (g == 0) as u8).collect::<Vec<_>>()
+        );
+
+        //9 pixels at 1 bpp only partly fill the second byte
+        let indices = [1, 0, 1, 0, 1, 0, 1, 0, 1];
+        let sprite = IndexedSprite::new(3, 3, BitsPerPixel::One, indices.to_vec())
+            .unwrap()
+            .pack();
+        assert_eq!(sprite.get_bytes(), [0xaa, 0x80]);
+
+        let mut converted = vec![ConvertedSprite::uncompressed("odd".to_owned(), &sprite)];
+        for compressor in compressors() {
+            if let Ok(result) = compressor.compress(&sprite.get_bytes()) {
+                converted.push(ConvertedSprite::compressed(
+                    "odd".to_owned(),
+                    &sprite,
+                    compressor.comp_type(),
+                    result,
+                ));
+            }
+        }
+        for ws in converted {
+            let extracted = W4Sprite::from_ws(&ws.bytes).unwrap();
+            assert_eq!(extracted.indices(), indices);
+        }
+    }
+
+    #[test]
+    fn test_from_ws() {
+        let sprite = W4Sprite::from_path("../test.png").unwrap();
+        let uncompressed = ConvertedSprite::uncompressed("test".to_owned(), &sprite);
+        let compressed = ConvertedSprite::compressed(
+            "test".to_owned(),
+            &sprite,
+            CompType::Pk,
+            PkComp {}.compress(&sprite.get_bytes()).unwrap(),
+        );
+
+        for ws in [uncompressed.bytes, compressed.bytes] {
+            let extracted = W4Sprite::from_ws(&ws).unwrap();
+            assert_eq!(extracted.get_bytes(), sprite.get_bytes());
+            assert_eq!(extracted.get_header_bytes(), sprite.get_header_bytes());
+
+            let mut png = Vec::new();
+            extracted.write_png(&mut png, &WASM4_PALETTE).unwrap();
+            let reconverted = W4Sprite::from_png_bytes(&png).unwrap();
+            assert_eq!(reconverted.get_bytes(), sprite.get_bytes());
+
+            for len in 0..ws.len() - 1 {
+                _ = W4Sprite::from_ws(&ws[..len]);
+            }
+        }
+
+        assert!(W4Sprite::from_ws(&[0, 8, 8, 0, 0xff]).is_err());
+        assert!(W4Sprite::from_ws(&[9, 8, 8, 0]).is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use super::*;
+
+//Plays an animation bundle, where every frame after the first is stored XORed with the frame
+//before it. The current frame stays at the start of the decompressor's buffer and each frame's
+//changes are decompressed just after it, so the buffer must hold two frames
+pub struct AnimationPlayer<'a> {
+    decompressor: Decompressor<'a>,
+    frames: Bundle<'a>,
+    frame: usize,
+    len: usize,
+    width: u8,
+    height: u8,
+    flags: u8,
+}
+
+impl<'a> AnimationPlayer<'a> {
+    pub fn new(decompressor: Decompressor<'a>, frames: Bundle<'a>) -> Result<Self, &'static str> {
+        if !frames.is_animation() {
+            return Err("Bundle is not an animation");
+        }
+
+        let mut player = Self {
+            decompressor,
+            frames,
+            frame: 0,
+            len: 0,
+            width: 0,
+            height: 0,
+            flags: 0,
+        };
+        player.restart()?;
+
+        Ok(player)
+    }
+
+    //The index of the current frame
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn current(&self) -> SpriteHandle<'_> {
+        SpriteHandle {
+            bytes: &self.decompressor.buf[..self.len],
+            width: self.width,
+            height: self.height,
+            flags: self.flags,
+        }
+    }
+
+    //Moves on to the next frame, applying its changes to the current one, and goes back to the
+    //first frame after the last
+    pub fn advance(&mut self) -> Result<SpriteHandle<'_>, &'static str> {
+        let next = self.frame + 1;
+        if next == self.frames.len() {
+            self.restart()?;
+            return Ok(self.current());
+        }
+
+        let bytes = self
+            .frames
+            .get(next)
+            .ok_or("Animation is missing a frame")?;
+        let (frame, scratch) = self.decompressor.buf.split_at_mut(self.len);
+        let mut decompressor = Decompressor::new(scratch);
+        let changes = decompressor.decompress_with_dictionary(bytes, self.frames.dictionary())?;
+        if (changes.width, changes.height, changes.flags) != (self.width, self.height, self.flags) {
+            return Err("Animation frames must all be the same size");
+        }
+
+        xor_bitplanes(changes.bytes, frame);
+        self.frame = next;
+
+        Ok(self.current())
+    }
+
+    //Decompresses the first frame again
+    pub fn restart(&mut self) -> Result<(), &'static str> {
+        let bytes = self.frames.get(0).ok_or("Animation has no frames")?;
+        let first = self
+            .decompressor
+            .decompress_with_dictionary(bytes, self.frames.dictionary())?;
+        let (len, width, height, flags) =
+            (first.bytes.len(), first.width, first.height, first.flags);
+
+        if len * 2 > self.decompressor.buf.len() {
+            return Err("Decompression buffer must hold two frames of the animation");
+        }
+
+        self.frame = 0;
+        self.len = len;
+        self.width = width;
+        self.height = height;
+        self.flags = flags;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use w4_pnger_common::{
+    BUNDLE_ANIMATION, BUNDLE_DICTIONARY, BUNDLE_HEADER_SIZE, BUNDLE_NAME_HASHES,
+};
+
+#[derive(Clone, Copy)]
+pub struct Bundle<'a> {
+    bytes: &'a [u8],
+    count: usize,
+    has_hashes: bool,
+    dictionary: &'a [u8],
+}
+
+impl<'a> Bundle<'a> {
+    pub fn new(bytes: &'a [u8]) -> Result<Self, &'static str> {
+        if bytes.len() < BUNDLE_HEADER_SIZE {
+            return Err("Bundle is too short");
+        }
+
+        let has_hashes = bytes[0] & BUNDLE_NAME_HASHES != 0;
+        let count = read_u16(bytes, 1) as usize;
+
+        let table_end =
+            BUNDLE_HEADER_SIZE + (count + 1) * 2 + if has_hashes { count * 4 } else { 0 };
+        if bytes.len() < table_end {
+            return Err("Bundle is too short for its offset table");
+        }
+
+        let dictionary = if bytes[0] & BUNDLE_DICTIONARY != 0 {
+            if bytes.len() < table_end + 2 {
+                return Err("Bundle is too short for its dictionary");
+            }
+            let len = read_u16(bytes, table_end) as usize;
+            bytes
+                .get(table_end + 2..table_end + 2 + len)
+                .ok_or("Bundle is too short for its dictionary")?
+        } else {
+            &[]
+        };
+
+        let bundle = Self {
+            bytes,
+            count,
+            has_hashes,
+            dictionary,
+        };
+
+        if bundle.offset(count) > bytes.len() {
+            return Err("Bundle is too short for its contents");
+        }
+
+        Ok(bundle)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&'a [u8]> {
+        if index >= self.count {
+            return None;
+        }
+
+        let start = self.offset(index);
+        let end = self.offset(index + 1);
+
+        self.bytes.get(start..end)
+    }
+
+    pub fn find(&self, name_hash: u32) -> Option<&'a [u8]> {
+        if !self.has_hashes {
+            return None;
+        }
+
+        let hashes_start = BUNDLE_HEADER_SIZE + (self.count + 1) * 2;
+
+        (0..self.count)
+            .find(|i| read_u32(self.bytes, hashes_start + i * 4) == name_hash)
+            .and_then(|i| self.get(i))
+    }
+
+    //The dictionary shared by the bundle's LzDict sprites, or an empty slice if there isn't one
+    pub fn dictionary(&self) -> &'a [u8] {
+        self.dictionary
+    }
+
+    //Whether the sprites are animation frames, to be played with an AnimationPlayer
+    pub fn is_animation(&self) -> bool {
+        self.bytes[0] & BUNDLE_ANIMATION != 0
+    }
+
+    fn offset(&self, index: usize) -> usize {
+        read_u16(self.bytes, BUNDLE_HEADER_SIZE + index * 2) as usize
+    }
+}
+
+pub(crate) fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use w4_pnger_common::name_hash;
+
+    use super::Bundle;
+
+    #[test]
+    fn test_bundle() {
+        let hash = name_hash("b").to_le_bytes();
+        let bytes = [
+            1, 2, 0, //Flags and count
+            17, 0, 19, 0, 22, 0, //Offsets
+            0, 0, 0, 0, hash[0], hash[1], hash[2], hash[3], //Hashes
+            0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        ];
+
+        let bundle = Bundle::new(&bytes[..]).unwrap();
+        assert_eq!(bundle.len(), 2);
+        assert_eq!(bundle.get(0), Some(&[0xaa, 0xbb][..]));
+        assert_eq!(bundle.get(1), Some(&[0xcc, 0xdd, 0xee][..]));
+        assert_eq!(bundle.get(2), None);
+        assert_eq!(bundle.find(name_hash("b")), Some(&[0xcc, 0xdd, 0xee][..]));
+        assert_eq!(bundle.find(name_hash("c")), None);
+
+        assert!(Bundle::new(&bytes[..20]).is_err());
+    }
+}
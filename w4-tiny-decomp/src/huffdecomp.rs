@@ -0,0 +1,106 @@
+use tiny_bitfiddle::{BitReader, BitSliceWriter, BitWriter};
+use w4_pnger_common::{BitsPerPixel, HUFFMAN_MAX_CODE_LEN};
+
+use super::*;
+
+pub fn decompress<'a>(
+    decompressor: &'a mut Decompressor,
+    bytes: &[u8],
+) -> Result<SpriteHandle<'a>, &'static str> {
+    if bytes.len() < 4 {
+        return Err("Sprite is too short to have a compression header");
+    }
+
+    let width = bytes[0] as usize;
+    let height = bytes[1] as usize;
+    let flags = bytes[2];
+    let symbol_bits: u8 = match bytes[3] & 0b11 {
+        0 => 2,
+        1 => 4,
+        2 => 8,
+        _ => return Err("Invalid Huffman symbol size"),
+    };
+    let split = bytes[3] & (1 << 2) != 0;
+    let delta = bytes[3] & (1 << 3) != 0;
+
+    let byte_end = BitsPerPixel::try_from_flags(flags)?.packed_len(width, height);
+    if byte_end > decompressor.buf.len() {
+        return Err("Sprite does not fit in the decompression buffer");
+    }
+
+    //The code table, which fits on the stack as there are at most 256 symbols
+    let mut reader = BitReader::new(&bytes[4..]);
+    let max_len = read_bits(&mut reader, 4)?;
+    let mut counts = [0; HUFFMAN_MAX_CODE_LEN + 1];
+    let mut num_symbols = 0;
+    for count in counts[1..=max_len].iter_mut() {
+        *count = read_bits(&mut reader, symbol_bits + 1)?;
+        num_symbols += *count;
+    }
+    if num_symbols > 1 << symbol_bits {
+        return Err("Huffman table has more codes than symbols");
+    }
+
+    let mut symbols = [0; 256];
+    for symbol in symbols[..num_symbols].iter_mut() {
+        *symbol = read_bits(&mut reader, symbol_bits)? as u8;
+    }
+
+    let table_bits = 4 + max_len * (symbol_bits as usize + 1) + num_symbols * symbol_bits as usize;
+    let codes = bytes
+        .get(4 + table_bits.div_ceil(8)..)
+        .ok_or("Compressed data ends before the sprite does")?;
+
+    let bit_end = byte_end * 8;
+    let mut writer = BitSliceWriter::new(&mut decompressor.buf[..byte_end]);
+    let mut reader = BitReader::new(codes);
+
+    while writer.get_end() < bit_end {
+        let symbol = read_symbol(&mut reader, &counts[..=max_len], &symbols)?;
+        for i in (0..symbol_bits).rev() {
+            writer.write_bit((symbol >> i) & 1);
+        }
+    }
+
+    let written_bytes = &mut decompressor.buf[..byte_end];
+
+    if delta {
+        delta_decode_in_place(written_bytes);
+    }
+
+    if split && !written_bytes.is_empty() {
+        assemble_bitplanes_in_place(written_bytes);
+    }
+
+    Ok(SpriteHandle {
+        bytes: written_bytes,
+        width: width as u8,
+        height: height as u8,
+        flags,
+    })
+}
+
+//Canonical codes of each length are consecutive, so a code can be decoded one bit at a time
+//knowing only how many codes there are of each length
+fn read_symbol(
+    reader: &mut BitReader,
+    counts: &[usize],
+    symbols: &[u8],
+) -> Result<u8, &'static str> {
+    let mut code = 0;
+    let mut first = 0;
+    let mut index = 0;
+
+    for &count in &counts[1..] {
+        code |= read_bits(reader, 1)?;
+        if code - first < count {
+            return Ok(symbols[index + code - first]);
+        }
+
+        index += count;
+        first = (first + count) << 1;
+        code <<= 1;
+    }
+
+    Err("Invalid Huffman code")
+}
@@ -1,11 +1,20 @@
 #![cfg_attr(not(test), no_std)]
 
+mod animation;
+mod bundle;
+mod huffdecomp;
+mod lzdecomp;
 mod pkdecomp;
+mod tilemap;
 use core::convert::TryInto;
 
+pub use animation::*;
+pub use bundle::*;
 pub use pkdecomp::*;
+pub use tilemap::*;
+pub use w4_pnger_common::name_hash;
 
-use tiny_bitfiddle::{BitSliceWriter, BitWriter};
+use tiny_bitfiddle::{BitReader, BitSliceWriter, BitWriter};
 use w4_pnger_common::CompType;
 
 pub struct Decompressor<'a> {
@@ -17,7 +26,21 @@ impl<'a> Decompressor<'a> {
         Self { buf }
     }
 
-    pub fn decompress(&mut self, bytes: &[u8]) -> Result<SpriteHandle, &'static str> {
+    pub fn decompress(&mut self, bytes: &[u8]) -> Result<SpriteHandle<'_>, &'static str> {
+        self.decompress_with_dictionary(bytes, &[])
+    }
+
+    //Decompresses a sprite from a bundle with a dictionary, passing `Bundle::dictionary`.
+    //Sprites which don't use the dictionary decompress as usual
+    pub fn decompress_with_dictionary(
+        &mut self,
+        bytes: &[u8],
+        dictionary: &[u8],
+    ) -> Result<SpriteHandle<'_>, &'static str> {
+        if bytes.len() < 4 {
+            return Err("Sprite is too short to have a header");
+        }
+
         let kind: CompType = bytes[0].try_into()?;
 
         match kind {
@@ -25,15 +48,24 @@ impl<'a> Decompressor<'a> {
                 let width = bytes[1];
                 let height = bytes[2];
                 let flags = bytes[3];
-                
+
+                let len = bytes.len() - 4;
+                if len > self.buf.len() {
+                    return Err("Sprite does not fit in the decompression buffer");
+                }
+                self.buf[..len].copy_from_slice(&bytes[4..]);
+
                 Ok(SpriteHandle {
-                    bytes: &self.buf[4..],
+                    bytes: &self.buf[..len],
                     width,
                     height,
                     flags,
                 })
             }
             CompType::Pk => pkdecomp::decompress(self, &bytes[1..]),
+            CompType::Lz => lzdecomp::decompress(self, &bytes[1..], &[]),
+            CompType::LzDict => lzdecomp::decompress(self, &bytes[1..], dictionary),
+            CompType::Huffman => huffdecomp::decompress(self, &bytes[1..]),
         }
     }
 }
@@ -45,9 +77,22 @@ pub struct SpriteHandle<'a> {
     pub flags: u8,
 }
 
+//Reads count bits as a number, high bit first
+pub(crate) fn read_bits(reader: &mut BitReader, count: u8) -> Result<usize, &'static str> {
+    let mut value = 0;
+    for _ in 0..count {
+        let bit = reader
+            .read_bit()
+            .ok_or("Compressed data ends before the sprite does")?;
+        value = value << 1 | bit as usize;
+    }
+
+    Ok(value)
+}
+
 pub(crate) fn xor_bitplanes(bp1: &[u8], bp2: &mut [u8]) {
     for (b1, b2) in bp1.iter().zip(bp2.iter_mut()) {
-        *b2 = *b1 ^ *b2;
+        *b2 ^= *b1;
     }
 }
 
@@ -136,13 +181,13 @@ mod tests {
         assemble_bitplanes_in_place, delta_decode_in_place, jump_delta_decode_in_place,
         xor_bitplanes,
     };
-    use tiny_bitfiddle::{BitReader, BitSliceWriter, BitWriter};
+    use tiny_bitfiddle::BitSliceWriter;
 
     #[test]
     fn test_xor_bitplanes() {
-        let mut bp1 = vec![0b11001100, 0b00110011u8];
+        let bp1 = [0b11001100, 0b00110011u8];
         let mut bp2 = vec![0b10101010, 0b00001111u8];
-        let mut bpr = vec![0b01100110, 0b00111100u8];
+        let bpr = vec![0b01100110, 0b00111100u8];
 
         xor_bitplanes(&bp1[..], &mut bp2[..]);
         assert_eq!(bp2, bpr);
@@ -151,7 +196,7 @@ mod tests {
     #[test]
     fn test_rotate() {
         let mut bytes = vec![0b11001100, 0b00110011u8];
-        let mut outtt = vec![0b00111100, 0b00110011u8];
+        let outtt = vec![0b00111100, 0b00110011u8];
         let mut writer = BitSliceWriter::new(&mut bytes[..]);
 
         writer.rotate_right(4, 8, 2);
@@ -161,7 +206,7 @@ mod tests {
     #[test]
     fn test_assemble_bitplanes() {
         let mut bp1 = vec![0b11001100, 0b11001100, 0b00110011, 0b00110011u8];
-        let mut bpr = vec![0b01011010, 0b01011010, 0b01011010, 0b01011010u8];
+        let bpr = vec![0b01011010, 0b01011010, 0b01011010, 0b01011010u8];
 
         assemble_bitplanes_in_place(&mut bp1[..]);
         assert_eq!(bp1, bpr);
@@ -170,7 +215,7 @@ mod tests {
     #[test]
     fn test_delta_decode_in_place() {
         let mut bytes = vec![0b00000001, 0b00100100, 0b00001000u8];
-        let mut resul = vec![0b11111111, 0b11100011, 0b00000111u8];
+        let resul = vec![0b11111111, 0b11100011, 0b00000111u8];
 
         delta_decode_in_place(&mut bytes[..]);
         assert_eq!(bytes, resul);
@@ -179,13 +224,13 @@ mod tests {
     #[test]
     fn test_jump_decode_in_place() {
         let mut bytes = vec![0b00111100, 0b00110100, 0b10110101u8];
-        let mut resul = vec![0b00111100, 0b00001000, 0b10111101u8];
+        let resul = vec![0b00111100, 0b00001000, 0b10111101u8];
 
         jump_delta_decode_in_place(&mut bytes[..], 8);
         assert_eq!(bytes, resul);
 
         let mut bytes = vec![0b00100011, 0b00100100, 0b10000001u8];
-        let mut resul = vec![0b00010011, 0b01110101, 0b11100110u8];
+        let resul = vec![0b00010011, 0b01110101, 0b11100110u8];
 
         jump_delta_decode_in_place(&mut bytes[..], 4);
         assert_eq!(bytes, resul);
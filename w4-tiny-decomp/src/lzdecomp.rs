@@ -0,0 +1,68 @@
+use tiny_bitfiddle::BitReader;
+use w4_pnger_common::{BitsPerPixel, LZ_MIN_MATCH};
+
+use super::*;
+
+//Matches may reach back past the start of the sprite into the dictionary, which is empty for
+//plain lz sprites
+pub fn decompress<'a>(
+    decompressor: &'a mut Decompressor,
+    bytes: &[u8],
+    dictionary: &[u8],
+) -> Result<SpriteHandle<'a>, &'static str> {
+    if bytes.len() < 4 {
+        return Err("Sprite is too short to have a compression header");
+    }
+
+    let width = bytes[0] as usize;
+    let height = bytes[1] as usize;
+    let flags = bytes[2];
+    let offset_bits = bytes[3] & 0x0f;
+    let length_bits = bytes[3] >> 4;
+
+    let byte_end = BitsPerPixel::try_from_flags(flags)?.packed_len(width, height);
+    if byte_end > decompressor.buf.len() {
+        return Err("Sprite does not fit in the decompression buffer");
+    }
+
+    let buf = &mut decompressor.buf[..byte_end];
+    let mut reader = BitReader::new(&bytes[4..]);
+    let mut written = 0;
+
+    while written < byte_end {
+        let literal = reader
+            .read_bit()
+            .ok_or("Compressed data ends before the sprite does")?;
+
+        if literal {
+            buf[written] = read_bits(&mut reader, 8)? as u8;
+            written += 1;
+        } else {
+            let distance = read_bits(&mut reader, offset_bits)? + 1;
+            let len = read_bits(&mut reader, length_bits)? + LZ_MIN_MATCH;
+            if distance > written + dictionary.len() {
+                return Err("Match starts before the sprite and dictionary do");
+            }
+            if len > byte_end - written {
+                return Err("Match runs past the end of the sprite");
+            }
+
+            //Byte by byte, as a match may overlap the bytes it's writing
+            for i in written..written + len {
+                buf[i] = if i >= distance {
+                    buf[i - distance]
+                } else {
+                    dictionary[dictionary.len() + i - distance]
+                };
+            }
+            written += len;
+        }
+    }
+
+    Ok(SpriteHandle {
+        bytes: &decompressor.buf[..byte_end],
+        width: width as u8,
+        height: height as u8,
+        flags,
+    })
+}
@@ -7,6 +7,10 @@ pub fn decompress<'a>(
     decompressor: &'a mut Decompressor,
     bytes: &[u8],
 ) -> Result<SpriteHandle<'a>, &'static str> {
+    if bytes.len() < 5 {
+        return Err("Sprite is too short to have a compression header");
+    }
+
     let width = bytes[0] as usize;
     let height = bytes[1] as usize;
     let flags = bytes[2];
@@ -16,16 +20,26 @@ pub fn decompress<'a>(
     let jump_delta = (bytes[3] & 0b11000000) >> 6;
     let jump_size = bytes[4];
 
-    let mut writer = BitSliceWriter::new(decompressor.buf);
+    let byte_end = BitsPerPixel::try_from_flags(flags)?.packed_len(width, height);
+    if byte_end > decompressor.buf.len() {
+        return Err("Sprite does not fit in the decompression buffer");
+    }
+    if xor && byte_end % 2 != 0 {
+        return Err("Sprite has an odd number of bytes, so can't have xor'd bitplanes");
+    }
+
+    //The last byte of compressed data is padded, so stop once the sprite is full
+    let bit_end = byte_end * 8;
+    let mut writer = BitSliceWriter::new(&mut decompressor.buf[..byte_end]);
     let mut reader = BitReader::new(&bytes[5..]);
 
-    let mut state = if reader.read_bit().unwrap() {
+    let mut state = if reader.read_bit().ok_or("Sprite has no compressed data")? {
         PkDecompressorState::StartVerbatim
     } else {
         PkDecompressorState::Rle
     };
 
-    loop {
+    while writer.get_end() < bit_end {
         match state {
             PkDecompressorState::StartVerbatim => match reader.read_bit() {
                 Some(more_than_one) => {
@@ -63,7 +77,7 @@ pub fn decompress<'a>(
                     None => break,
                 };
 
-                if b1 == false && b2 == false {
+                if !b1 && !b2 {
                     state = PkDecompressorState::Rle;
                 } else {
                     writer.write_bit(b1 as u8);
@@ -71,8 +85,9 @@ pub fn decompress<'a>(
                 }
             }
             PkDecompressorState::Rle => {
+                //Long runs of zeroes don't fit in a u8
                 let mut len = 0;
-                let mut bits = 0;
+                let mut bits: usize = 0;
 
                 let mut front_bit = match reader.read_bit() {
                     Some(b) => b,
@@ -80,6 +95,9 @@ pub fn decompress<'a>(
                 };
                 while front_bit {
                     len += 1;
+                    if len >= 24 {
+                        return Err("Run of zeroes is longer than any sprite");
+                    }
                     bits = bits << 1 | 1;
 
                     front_bit = match reader.read_bit() {
@@ -91,17 +109,20 @@ pub fn decompress<'a>(
                 len += 1;
                 bits <<= 1;
 
-                let mut bits_2 = 0;
+                let mut bits_2: usize = 0;
                 for _ in 0..len {
                     let b = match reader.read_bit() {
                         Some(b) => b,
                         None => break,
                     };
-                    bits_2 = bits_2 << 1 | b as u8;
+                    bits_2 = bits_2 << 1 | b as usize;
                 }
 
                 let sum = bits + bits_2 + 1;
                 for _ in 0..sum {
+                    if writer.get_end() >= bit_end {
+                        break;
+                    }
                     writer.write_bit(0);
                     writer.write_bit(0);
                 }
@@ -110,36 +131,34 @@ pub fn decompress<'a>(
         }
     }
 
-    let byte_end = match BitsPerPixel::try_from_flags(flags)? {
-        BitsPerPixel::One => (width * height) / 8,
-        BitsPerPixel::Two => (width * height) / 4,
-    };
+    if writer.get_end() < bit_end {
+        return Err("Compressed data ends before the sprite does");
+    }
 
-    let mut written_bytes = &mut decompressor.buf[..byte_end];
+    let written_bytes = &mut decompressor.buf[..byte_end];
 
     for _ in 0..jump_delta {
-        jump_delta_decode_in_place(&mut written_bytes, jump_size as usize);
+        jump_delta_decode_in_place(written_bytes, jump_size as usize);
     }
 
     for _ in 0..seq_delta {
-        delta_decode_in_place(&mut written_bytes);
+        delta_decode_in_place(written_bytes);
     }
 
     if xor {
-        debug_assert!(written_bytes.len() % 2 == 0);
         let (left, right) = written_bytes.split_at_mut(written_bytes.len() / 2);
         xor_bitplanes(right, left);
     }
 
-    if split {
-        assemble_bitplanes_in_place(&mut written_bytes);
+    if split && !written_bytes.is_empty() {
+        assemble_bitplanes_in_place(written_bytes);
     }
 
     Ok(SpriteHandle {
         bytes: written_bytes,
         width: width as u8,
         height: height as u8,
-        flags: flags as u8,
+        flags,
     })
 }
 
@@ -0,0 +1,177 @@
+use super::*;
+
+use bundle::read_u16;
+use w4_pnger_common::{TILEMAP_HEADER_SIZE, TILEMAP_WIDE_CELLS};
+
+//Reads cells from a tilemap, decompressing a row into the decompressor's buffer the first time
+//one of its cells is needed, so the buffer must hold one row. Cells are 0 where the map is
+//empty, and otherwise one more than the index of their tile in the map's tile bundle
+pub struct TileMap<'a> {
+    decompressor: Decompressor<'a>,
+    bytes: &'a [u8],
+    width: usize,
+    height: usize,
+    wide: bool,
+    //The row currently in the buffer
+    row: Option<usize>,
+}
+
+impl<'a> TileMap<'a> {
+    pub fn new(decompressor: Decompressor<'a>, bytes: &'a [u8]) -> Result<Self, &'static str> {
+        if bytes.len() < TILEMAP_HEADER_SIZE {
+            return Err("Tilemap is too short");
+        }
+
+        let wide = bytes[0] & TILEMAP_WIDE_CELLS != 0;
+        let width = read_u16(bytes, 1) as usize;
+        let height = read_u16(bytes, 3) as usize;
+
+        if bytes.len() < TILEMAP_HEADER_SIZE + (height + 1) * 2 {
+            return Err("Tilemap is too short for its offset table");
+        }
+
+        let map = Self {
+            decompressor,
+            bytes,
+            width,
+            height,
+            wide,
+            row: None,
+        };
+
+        if map.offset(height) > bytes.len() {
+            return Err("Tilemap is too short for its contents");
+        }
+        if map.row_len() > map.decompressor.buf.len() {
+            return Err("A tilemap row does not fit in the decompression buffer");
+        }
+
+        Ok(map)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn cell(&mut self, x: usize, y: usize) -> Result<u16, &'static str> {
+        if x >= self.width || y >= self.height {
+            return Err("Cell is outside the tilemap");
+        }
+
+        self.load_row(y)?;
+        Ok(self.read_cell(x))
+    }
+
+    //Decodes every cell in a row into out, which must hold at least width cells
+    pub fn row(&mut self, y: usize, out: &mut [u16]) -> Result<(), &'static str> {
+        if y >= self.height {
+            return Err("Row is outside the tilemap");
+        }
+        if out.len() < self.width {
+            return Err("Output is too short for a row");
+        }
+
+        self.load_row(y)?;
+        for (x, cell) in out[..self.width].iter_mut().enumerate() {
+            *cell = self.read_cell(x);
+        }
+
+        Ok(())
+    }
+
+    //Decodes every cell in a column into out, which must hold at least height cells. This
+    //decompresses every row, so it's much slower than reading a row
+    pub fn column(&mut self, x: usize, out: &mut [u16]) -> Result<(), &'static str> {
+        if x >= self.width {
+            return Err("Column is outside the tilemap");
+        }
+        if out.len() < self.height {
+            return Err("Output is too short for a column");
+        }
+
+        for (y, cell) in out[..self.height].iter_mut().enumerate() {
+            self.load_row(y)?;
+            *cell = self.read_cell(x);
+        }
+
+        Ok(())
+    }
+
+    fn load_row(&mut self, y: usize) -> Result<(), &'static str> {
+        if self.row == Some(y) {
+            return Ok(());
+        }
+
+        self.row = None;
+        let row = self
+            .bytes
+            .get(self.offset(y)..self.offset(y + 1))
+            .ok_or("Tilemap row is outside the tilemap")?;
+        let len = self.decompressor.decompress(row)?.bytes.len();
+        if len != self.row_len() {
+            return Err("Tilemap row is the wrong length");
+        }
+        self.row = Some(y);
+
+        Ok(())
+    }
+
+    fn read_cell(&self, x: usize) -> u16 {
+        if self.wide {
+            read_u16(self.decompressor.buf, x * 2)
+        } else {
+            self.decompressor.buf[x] as u16
+        }
+    }
+
+    fn row_len(&self) -> usize {
+        if self.wide {
+            self.width * 2
+        } else {
+            self.width
+        }
+    }
+
+    fn offset(&self, index: usize) -> usize {
+        read_u16(self.bytes, TILEMAP_HEADER_SIZE + index * 2) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TileMap;
+    use crate::Decompressor;
+
+    #[test]
+    fn test_tilemap() {
+        let bytes = [
+            0, 3, 0, 2, 0, //Flags, width and height
+            11, 0, 18, 0, 25, 0, //Offsets
+            0, 8, 3, 0, 1, 0, 2, //Uncompressed rows, as 8 pixel wide sprites at 1 bpp
+            0, 8, 3, 0, 3, 3, 0,
+        ];
+
+        let mut buf = [0; 3];
+        let mut map = TileMap::new(Decompressor::new(&mut buf), &bytes).unwrap();
+        assert_eq!((map.width(), map.height()), (3, 2));
+        assert_eq!(map.cell(2, 0), Ok(2));
+        assert_eq!(map.cell(0, 1), Ok(3));
+        assert!(map.cell(3, 0).is_err());
+
+        let mut row = [0; 3];
+        map.row(1, &mut row).unwrap();
+        assert_eq!(row, [3, 3, 0]);
+        let mut column = [0; 2];
+        map.column(1, &mut column).unwrap();
+        assert_eq!(column, [0, 3]);
+
+        let mut buf = [0; 2];
+        assert!(TileMap::new(Decompressor::new(&mut buf), &bytes).is_err());
+        let mut buf = [0; 3];
+        assert!(TileMap::new(Decompressor::new(&mut buf), &bytes[..24]).is_err());
+    }
+}